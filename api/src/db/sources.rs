@@ -2,11 +2,12 @@ use aws_lc_rs::{aead::Nonce, error::Unspecified};
 use base64::{prelude::BASE64_STANDARD, DecodeError, Engine};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
-    PgPool,
+    Connection, PgConnection, PgPool,
 };
 use std::{
     fmt::{Debug, Formatter},
     str::{from_utf8, Utf8Error},
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -32,6 +33,18 @@ enum SourceConfigInDb {
 
         /// Postgres slot name
         slot_name: String,
+
+        /// How the connection uses TLS. Defaults to prefer
+        #[serde(default)]
+        ssl_mode: Option<SslMode>,
+
+        /// PEM encoded certificate of the CA which signed the server's certificate
+        #[serde(default)]
+        ca_cert: Option<String>,
+
+        /// Maximum time a statement may run for, in seconds
+        #[serde(default)]
+        statement_timeout_secs: Option<u64>,
     },
 }
 
@@ -44,6 +57,9 @@ impl SourceConfigInDb {
             username,
             password: encrypted_password,
             slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
         } = self;
 
         let decrypted_password = encrypted_password
@@ -75,11 +91,14 @@ impl SourceConfigInDb {
             username,
             password: decrypted_password,
             slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
         })
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub enum SourceConfig {
     Postgres {
         /// Host on which Postgres is running
@@ -99,10 +118,108 @@ pub enum SourceConfig {
 
         /// Postgres slot name
         slot_name: String,
+
+        /// How the connection uses TLS. Defaults to prefer
+        #[serde(default)]
+        ssl_mode: Option<SslMode>,
+
+        /// PEM encoded certificate of the CA which signed the server's certificate
+        #[serde(default)]
+        ca_cert: Option<String>,
+
+        /// Maximum time a statement may run for, in seconds
+        #[serde(default)]
+        statement_timeout_secs: Option<u64>,
     },
 }
 
+/// The sslmode of a Postgres connection, see
+/// https://www.postgresql.org/docs/current/libpq-ssl.html#LIBPQ-SSL-PROTECTION
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl From<SslMode> for PgSslMode {
+    fn from(ssl_mode: SslMode) -> Self {
+        match ssl_mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Allow => PgSslMode::Allow,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require => PgSslMode::Require,
+            SslMode::VerifyCa => PgSslMode::VerifyCa,
+            SslMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
 impl SourceConfig {
+    /// Returns this config with the password of `stored_config` if it has none, so
+    /// that a source can be updated without sending its password again
+    pub fn or_password_of(self, stored_config: &SourceConfig) -> SourceConfig {
+        let SourceConfig::Postgres {
+            host,
+            port,
+            name,
+            username,
+            password,
+            slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
+        } = self;
+        let SourceConfig::Postgres {
+            password: stored_password,
+            ..
+        } = stored_config;
+
+        SourceConfig::Postgres {
+            host,
+            port,
+            name,
+            username,
+            password: password.or_else(|| stored_password.clone()),
+            slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
+        }
+    }
+
+    /// Returns a copy of this config without the password. Every config sent
+    /// back to clients must be redacted.
+    pub fn redacted(&self) -> SourceConfig {
+        let SourceConfig::Postgres {
+            host,
+            port,
+            name,
+            username,
+            password: _,
+            slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
+        } = self;
+
+        SourceConfig::Postgres {
+            host: host.clone(),
+            port: *port,
+            name: name.clone(),
+            username: username.clone(),
+            password: None,
+            slot_name: slot_name.clone(),
+            ssl_mode: *ssl_mode,
+            ca_cert: ca_cert.clone(),
+            statement_timeout_secs: *statement_timeout_secs,
+        }
+    }
+
     pub fn connect_options(&self) -> PgConnectOptions {
         match self {
             SourceConfig::Postgres {
@@ -112,20 +229,29 @@ impl SourceConfig {
                 username,
                 password,
                 slot_name: _,
+                ssl_mode,
+                ca_cert,
+                statement_timeout_secs,
             } => {
-                let ssl_mode = PgSslMode::Prefer;
+                let ssl_mode = ssl_mode.map(Into::into).unwrap_or(PgSslMode::Prefer);
 
-                let options = PgConnectOptions::new_without_pgpass()
+                let mut options = PgConnectOptions::new_without_pgpass()
                     .host(host)
                     .port(*port)
                     .database(name)
                     .username(username)
                     .ssl_mode(ssl_mode);
                 if let Some(password) = password {
-                    options.password(password)
-                } else {
-                    options
+                    options = options.password(password);
                 }
+                if let Some(ca_cert) = ca_cert {
+                    options = options.ssl_root_cert_from_pem(ca_cert.as_bytes().to_vec());
+                }
+                if let Some(statement_timeout_secs) = statement_timeout_secs {
+                    let timeout_millis = statement_timeout_secs.saturating_mul(1000);
+                    options = options.options([("statement_timeout", timeout_millis)]);
+                }
+                options
             }
         }
     }
@@ -141,6 +267,9 @@ impl SourceConfig {
             username,
             password,
             slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
         } = self;
 
         let encrypted_password = password
@@ -164,6 +293,9 @@ impl SourceConfig {
             username,
             password: encrypted_password,
             slot_name,
+            ssl_mode,
+            ca_cert,
+            statement_timeout_secs,
         })
     }
 }
@@ -178,6 +310,9 @@ impl Debug for SourceConfig {
                 username,
                 password: _,
                 slot_name,
+                ssl_mode,
+                ca_cert,
+                statement_timeout_secs,
             } => f
                 .debug_struct("Postgres")
                 .field("host", host)
@@ -186,6 +321,9 @@ impl Debug for SourceConfig {
                 .field("username", username)
                 .field("password", &"REDACTED")
                 .field("slot_name", slot_name)
+                .field("ssl_mode", ssl_mode)
+                .field("ca_cert", ca_cert)
+                .field("statement_timeout_secs", statement_timeout_secs)
                 .finish(),
         }
     }
@@ -219,6 +357,75 @@ pub enum SourcesDbError {
     Utf8(#[from] Utf8Error),
 }
 
+#[derive(Debug, Error)]
+pub enum SourceValidationError {
+    #[error("failed to connect to the source database: {0}")]
+    ConnectionFailed(sqlx::Error),
+
+    #[error("timed out while connecting to the source database")]
+    ConnectionTimedOut,
+
+    #[error("failed to query the source database: {0}")]
+    QueryFailed(#[from] sqlx::Error),
+
+    #[error("user {0} doesn't have the replication attribute")]
+    MissingReplicationAttribute(String),
+
+    #[error("logical replication slot {0} doesn't exist in the source database")]
+    SlotNotFound(String),
+}
+
+const VALIDATION_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connects to the source database to check that it is reachable, that the
+/// configured user is allowed to start logical replication and that the
+/// replication slot exists.
+pub async fn validate_source(config: &SourceConfig) -> Result<(), SourceValidationError> {
+    let SourceConfig::Postgres { slot_name, .. } = config;
+    let options = config.connect_options();
+    let mut connection = tokio::time::timeout(
+        VALIDATION_CONNECT_TIMEOUT,
+        PgConnection::connect_with(&options),
+    )
+    .await
+    .map_err(|_| SourceValidationError::ConnectionTimedOut)?
+    .map_err(SourceValidationError::ConnectionFailed)?;
+
+    let record = sqlx::query!(
+        r#"
+        select current_user as "username!", (rolreplication or rolsuper) as "can_replicate!"
+        from pg_roles
+        where rolname = current_user
+        "#
+    )
+    .fetch_one(&mut connection)
+    .await?;
+
+    if !record.can_replicate {
+        return Err(SourceValidationError::MissingReplicationAttribute(
+            record.username,
+        ));
+    }
+
+    let record = sqlx::query!(
+        r#"
+        select exists (
+            select from pg_replication_slots
+            where slot_name::text = $1 and slot_type = 'logical' and database = current_database()
+        ) as "exists!"
+        "#,
+        slot_name
+    )
+    .fetch_one(&mut connection)
+    .await?;
+
+    if !record.exists {
+        return Err(SourceValidationError::SlotNotFound(slot_name.clone()));
+    }
+
+    Ok(())
+}
+
 pub async fn create_source(
     pool: &PgPool,
     tenant_id: &str,
@@ -278,6 +485,7 @@ pub async fn read_source(
     Ok(source)
 }
 
+/// Updates a source. A `config` without a password keeps the stored password.
 pub async fn update_source(
     pool: &PgPool,
     tenant_id: &str,
@@ -286,7 +494,32 @@ pub async fn update_source(
     config: SourceConfig,
     encryption_key: &EncryptionKey,
 ) -> Result<Option<i64>, SourcesDbError> {
-    let db_config = config.into_db_config(encryption_key)?;
+    let mut txn = pool.begin().await?;
+    let Some(stored) = sqlx::query!(
+        r#"
+        select config
+        from app.sources
+        where tenant_id = $1 and id = $2
+        for update
+        "#,
+        tenant_id,
+        source_id
+    )
+    .fetch_optional(&mut *txn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut db_config = config.into_db_config(encryption_key)?;
+    let SourceConfigInDb::Postgres { password, .. } = &mut db_config;
+    if password.is_none() {
+        let SourceConfigInDb::Postgres {
+            password: stored_password,
+            ..
+        } = serde_json::from_value(stored.config)?;
+        *password = stored_password;
+    }
     let db_config = serde_json::to_value(db_config).expect("failed to serialize config");
     let record = sqlx::query!(
         r#"
@@ -300,8 +533,9 @@ pub async fn update_source(
         tenant_id,
         source_id
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut *txn)
     .await?;
+    txn.commit().await?;
 
     Ok(record.map(|r| r.id))
 }
@@ -330,14 +564,20 @@ pub async fn read_all_sources(
     pool: &PgPool,
     tenant_id: &str,
     encryption_key: &EncryptionKey,
+    limit: Option<i64>,
+    offset: i64,
 ) -> Result<Vec<Source>, SourcesDbError> {
     let records = sqlx::query!(
         r#"
         select id, tenant_id, name, config
         from app.sources
         where tenant_id = $1
+        order by id
+        limit $2 offset $3
         "#,
         tenant_id,
+        limit,
+        offset,
     )
     .fetch_all(pool)
     .await?;
@@ -358,6 +598,21 @@ pub async fn read_all_sources(
     Ok(sources)
 }
 
+pub async fn count_sources(pool: &PgPool, tenant_id: &str) -> Result<i64, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        select count(*) as "count!"
+        from app.sources
+        where tenant_id = $1
+        "#,
+        tenant_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.count)
+}
+
 pub async fn source_exists(
     pool: &PgPool,
     tenant_id: &str,
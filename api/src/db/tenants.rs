@@ -100,12 +100,21 @@ pub async fn delete_tenant(pool: &PgPool, tenant_id: &str) -> Result<Option<Stri
     Ok(record.map(|r| r.id))
 }
 
-pub async fn read_all_tenants(pool: &PgPool) -> Result<Vec<Tenant>, sqlx::Error> {
+/// Returns tenants ordered by id. A `limit` of `None` returns all tenants after `offset`.
+pub async fn read_all_tenants(
+    pool: &PgPool,
+    limit: Option<i64>,
+    offset: i64,
+) -> Result<Vec<Tenant>, sqlx::Error> {
     let mut record = sqlx::query!(
         r#"
         select id, name
         from app.tenants
+        order by id
+        limit $1 offset $2
         "#,
+        limit,
+        offset,
     )
     .fetch_all(pool)
     .await?;
@@ -118,3 +127,16 @@ pub async fn read_all_tenants(pool: &PgPool) -> Result<Vec<Tenant>, sqlx::Error>
         })
         .collect())
 }
+
+pub async fn count_tenants(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        select count(*) as "count!"
+        from app.tenants
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.count)
+}
@@ -20,6 +20,10 @@ pub enum SourceConfig {
 
         /// Postgres publication name
         publication: String,
+
+        /// Maximum time a query of the replicator may run for, in seconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        statement_timeout_secs: Option<u64>,
     },
 }
 
@@ -33,6 +37,7 @@ impl Debug for SourceConfig {
                 username,
                 slot_name,
                 publication,
+                statement_timeout_secs,
             } => f
                 .debug_struct("Postgres")
                 .field("host", host)
@@ -41,6 +46,7 @@ impl Debug for SourceConfig {
                 .field("username", username)
                 .field("slot_name", slot_name)
                 .field("publication", publication)
+                .field("statement_timeout_secs", statement_timeout_secs)
                 .finish(),
         }
     }
@@ -125,6 +131,7 @@ mod tests {
                 username: "postgres".to_string(),
                 slot_name: "replicator_slot".to_string(),
                 publication: "replicator_publication".to_string(),
+                statement_timeout_secs: None,
             },
             sink: SinkConfig::BigQuery {
                 project_id: "project-id".to_string(),
@@ -149,6 +156,7 @@ mod tests {
                 username: "postgres".to_string(),
                 slot_name: "replicator_slot".to_string(),
                 publication: "replicator_publication".to_string(),
+                statement_timeout_secs: Some(30),
             },
             sink: SinkConfig::BigQuery {
                 project_id: "project-id".to_string(),
@@ -159,7 +167,7 @@ mod tests {
                 max_fill_secs: 10,
             },
         };
-        let expected = r#"{"source":{"Postgres":{"host":"localhost","port":5432,"name":"postgres","username":"postgres","slot_name":"replicator_slot","publication":"replicator_publication"}},"sink":{"BigQuery":{"project_id":"project-id","dataset_id":"dataset-id"}},"batch":{"max_size":1000,"max_fill_secs":10}}"#;
+        let expected = r#"{"source":{"Postgres":{"host":"localhost","port":5432,"name":"postgres","username":"postgres","slot_name":"replicator_slot","publication":"replicator_publication","statement_timeout_secs":30}},"sink":{"BigQuery":{"project_id":"project-id","dataset_id":"dataset-id"}},"batch":{"max_size":1000,"max_fill_secs":10}}"#;
         let actual = serde_json::to_string(&actual);
         assert!(actual.is_ok());
         assert_eq!(expected, actual.unwrap());
@@ -1,4 +1,54 @@
-use actix_web::{get, HttpResponse, Responder};
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::{header::ContentType, StatusCode},
+    web::Data,
+    HttpResponse, Responder, ResponseError,
+};
+use sqlx::PgPool;
+use thiserror::Error;
+
+use super::ErrorMessage;
+
+/// How long the readiness check waits for the database before reporting it as unreachable
+const READINESS_DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Error)]
+enum ReadinessError {
+    #[error("database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("database did not respond within {0:?}")]
+    DatabaseTimeout(Duration),
+}
+
+impl ReadinessError {
+    fn to_message(&self) -> String {
+        match self {
+            // Do not expose internal database details in error messages
+            ReadinessError::DatabaseError(_) => "database unreachable".to_string(),
+            e => e.to_string(),
+        }
+    }
+}
+
+impl ResponseError for ReadinessError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error_message = ErrorMessage {
+            error: self.to_message(),
+        };
+        let body =
+            serde_json::to_string(&error_message).expect("failed to serialize error message");
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::json())
+            .body(body)
+    }
+}
 
 #[utoipa::path(
     responses(
@@ -9,3 +59,18 @@ use actix_web::{get, HttpResponse, Responder};
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("ok")
 }
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Api is ready to serve requests"),
+        (status = 503, description = "Api database is unreachable"),
+    )
+)]
+#[get("/ready")]
+pub async fn ready(pool: Data<PgPool>) -> Result<impl Responder, ReadinessError> {
+    let query = sqlx::query("select 1").execute(pool.get_ref());
+    tokio::time::timeout(READINESS_DATABASE_TIMEOUT, query)
+        .await
+        .map_err(|_| ReadinessError::DatabaseTimeout(READINESS_DATABASE_TIMEOUT))??;
+    Ok(HttpResponse::Ok().body("ok"))
+}
@@ -1,4 +1,5 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 pub mod health_check;
 pub mod images;
@@ -7,7 +8,63 @@ pub mod sinks;
 pub mod sources;
 pub mod tenants;
 
+/// Number of items a listing returns if the request sets no limit
+pub const DEFAULT_PAGE_LIMIT: i64 = 100;
+
+/// Maximum number of items a listing returns, whatever limit the request sets
+pub const MAX_PAGE_LIMIT: i64 = 1000;
+
 #[derive(Serialize)]
 pub struct ErrorMessage {
     pub error: String,
 }
+
+/// The `limit` and `offset` query parameters of a listing
+#[derive(Deserialize)]
+pub struct PageQuery {
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+#[derive(Debug, Error)]
+pub enum PageQueryError {
+    #[error("limit must not be negative")]
+    NegativeLimit,
+
+    #[error("offset must not be negative")]
+    NegativeOffset,
+}
+
+impl PageQuery {
+    /// Returns the limit, which is capped at [MAX_PAGE_LIMIT], and the offset of
+    /// the requested page
+    pub fn limit_and_offset(&self) -> Result<(i64, i64), PageQueryError> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if limit < 0 {
+            return Err(PageQueryError::NegativeLimit);
+        }
+        if self.offset < 0 {
+            return Err(PageQueryError::NegativeOffset);
+        }
+        Ok((limit.min(MAX_PAGE_LIMIT), self.offset))
+    }
+}
+
+/// A page of a listing
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of items in the whole listing
+    pub total: i64,
+    /// Offset of the next page, if there are items after this one
+    pub next: Option<i64>,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, offset: i64) -> Page<T> {
+        let end = offset + items.len() as i64;
+        let next = (end < total).then_some(end);
+        Page { items, total, next }
+    }
+}
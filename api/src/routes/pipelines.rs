@@ -19,7 +19,7 @@ use crate::{
         pipelines::{Pipeline, PipelineConfig},
         replicators::Replicator,
         sinks::{sink_exists, Sink, SinkConfig, SinksDbError},
-        sources::{source_exists, Source, SourceConfig, SourcesDbError},
+        sources::{source_exists, Source, SourceConfig, SourcesDbError, SslMode},
     },
     encryption::EncryptionKey,
     k8s_client::{HttpK8sClient, K8sClient, K8sError, PodPhase},
@@ -69,6 +69,14 @@ enum PipelineError {
 
     #[error("sinks db error: {0}")]
     SinksDb(#[from] SinksDbError),
+
+    #[error(
+        "source ssl mode {0:?} is not supported, replicators connect to the source without TLS"
+    )]
+    UnsupportedSslMode(SslMode),
+
+    #[error("source ca cert is not supported, replicators connect to the source without TLS")]
+    UnsupportedCaCert,
 }
 
 impl PipelineError {
@@ -97,7 +105,9 @@ impl ResponseError for PipelineError {
             PipelineError::TenantIdMissing
             | PipelineError::TenantIdIllFormed
             | PipelineError::SourceNotFound(_)
-            | PipelineError::SinkNotFound(_) => StatusCode::BAD_REQUEST,
+            | PipelineError::SinkNotFound(_)
+            | PipelineError::UnsupportedSslMode(_)
+            | PipelineError::UnsupportedCaCert => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -349,6 +359,7 @@ pub async fn read_all_pipelines(
     context_path = "/v1",
     responses(
         (status = 200, description = "Start a pipeline"),
+        (status = 400, description = "Source uses TLS settings which replicators don't support"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -489,8 +500,21 @@ fn create_configs(
         username,
         password: postgres_password,
         slot_name,
+        ssl_mode,
+        ca_cert,
+        statement_timeout_secs,
     } = source_config;
 
+    // the replicator can't connect with TLS yet, so a source which requires it
+    // is rejected rather than connected to without it
+    if let Some(ssl_mode @ (SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull)) = ssl_mode
+    {
+        return Err(PipelineError::UnsupportedSslMode(ssl_mode));
+    }
+    if ca_cert.is_some() {
+        return Err(PipelineError::UnsupportedCaCert);
+    }
+
     let SinkConfig::BigQuery {
         project_id,
         dataset_id,
@@ -510,6 +534,7 @@ fn create_configs(
         username,
         slot_name,
         publication,
+        statement_timeout_secs,
     };
 
     let sink_config = replicator_config::SinkConfig::BigQuery {
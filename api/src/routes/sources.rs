@@ -1,8 +1,8 @@
 use actix_web::{
     delete, get,
     http::{header::ContentType, StatusCode},
-    post,
-    web::{Data, Json, Path},
+    post, route,
+    web::{Data, Json, Path, Query},
     HttpRequest, HttpResponse, Responder, ResponseError,
 };
 use serde::{Deserialize, Serialize};
@@ -10,11 +10,11 @@ use sqlx::PgPool;
 use thiserror::Error;
 use utoipa::ToSchema;
 
-use super::ErrorMessage;
+use super::{ErrorMessage, Page, PageQuery, PageQueryError};
 use crate::{
     db::{
         self,
-        sources::{SourceConfig, SourcesDbError},
+        sources::{SourceConfig, SourceValidationError, SourcesDbError},
     },
     encryption::EncryptionKey,
 };
@@ -38,6 +38,12 @@ enum SourceError {
 
     #[error("sources db error: {0}")]
     SourcesDb(#[from] SourcesDbError),
+
+    #[error("invalid source: {0}")]
+    InvalidSource(#[from] SourceValidationError),
+
+    #[error("invalid page: {0}")]
+    InvalidPage(#[from] PageQueryError),
 }
 
 impl SourceError {
@@ -58,9 +64,10 @@ impl ResponseError for SourceError {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
             SourceError::SourceNotFound(_) => StatusCode::NOT_FOUND,
-            SourceError::TenantIdMissing | SourceError::TenantIdIllFormed => {
-                StatusCode::BAD_REQUEST
-            }
+            SourceError::TenantIdMissing
+            | SourceError::TenantIdIllFormed
+            | SourceError::InvalidSource(_)
+            | SourceError::InvalidPage(_) => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -83,6 +90,12 @@ pub struct PostSourceRequest {
     pub config: SourceConfig,
 }
 
+#[derive(Deserialize)]
+pub struct ValidateSourceQuery {
+    #[serde(default)]
+    validate: bool,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct PostSourceResponse {
     id: i64,
@@ -114,8 +127,12 @@ fn extract_tenant_id(req: &HttpRequest) -> Result<&str, SourceError> {
 #[utoipa::path(
     context_path = "/v1",
     request_body = PostSourceRequest,
+    params(
+        ("validate" = Option<bool>, Query, description = "Check that the source database is reachable, that the user can replicate and that the slot exists before saving the source"),
+    ),
     responses(
         (status = 200, description = "Create new source", body = PostSourceResponse),
+        (status = 400, description = "Source validation failed"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -124,12 +141,16 @@ pub async fn create_source(
     req: HttpRequest,
     pool: Data<PgPool>,
     encryption_key: Data<EncryptionKey>,
+    query: Query<ValidateSourceQuery>,
     source: Json<PostSourceRequest>,
 ) -> Result<impl Responder, SourceError> {
     let source = source.0;
     let tenant_id = extract_tenant_id(&req)?;
     let name = source.name;
     let config = source.config;
+    if query.validate {
+        db::sources::validate_source(&config).await?;
+    }
     let id = db::sources::create_source(&pool, tenant_id, &name, config, &encryption_key).await?;
     let response = PostSourceResponse { id };
     Ok(Json(response))
@@ -141,7 +162,7 @@ pub async fn create_source(
         ("source_id" = i64, Path, description = "Id of the source"),
     ),
     responses(
-        (status = 200, description = "Return source with id = source_id", body = GetSourceResponse),
+        (status = 200, description = "Return source with id = source_id with its password redacted", body = GetSourceResponse),
         (status = 404, description = "Source not found"),
         (status = 500, description = "Internal server error")
     )
@@ -161,30 +182,36 @@ pub async fn read_source(
             id: s.id,
             tenant_id: s.tenant_id,
             name: s.name,
-            config: s.config,
+            config: s.config.redacted(),
         })
         .ok_or(SourceError::SourceNotFound(source_id))?;
     Ok(Json(response))
 }
 
 #[utoipa::path(
+    put,
+    path = "/sources/{source_id}",
     context_path = "/v1",
     request_body = PostSourceRequest,
     params(
         ("source_id" = i64, Path, description = "Id of the source"),
+        ("validate" = Option<bool>, Query, description = "Check that the source database is reachable, that the user can replicate and that the slot exists before saving the source"),
     ),
     responses(
-        (status = 200, description = "Update source with id = source_id"),
+        (status = 200, description = "Update source with id = source_id, keeping its stored password if none is given", body = GetSourceResponse),
+        (status = 400, description = "Source validation failed"),
         (status = 404, description = "Source not found"),
         (status = 500, description = "Internal server error")
     )
 )]
-#[post("/sources/{source_id}")]
+// POST is still accepted for clients which updated sources before PUT was added
+#[route("/sources/{source_id}", method = "PUT", method = "POST")]
 pub async fn update_source(
     req: HttpRequest,
     pool: Data<PgPool>,
     source_id: Path<i64>,
     encryption_key: Data<EncryptionKey>,
+    query: Query<ValidateSourceQuery>,
     source: Json<PostSourceRequest>,
 ) -> Result<impl Responder, SourceError> {
     let source = source.0;
@@ -192,10 +219,24 @@ pub async fn update_source(
     let source_id = source_id.into_inner();
     let name = source.name;
     let config = source.config;
+    if query.validate {
+        let stored_source = db::sources::read_source(&pool, tenant_id, source_id, &encryption_key)
+            .await?
+            .ok_or(SourceError::SourceNotFound(source_id))?;
+        let config = config.clone().or_password_of(&stored_source.config);
+        db::sources::validate_source(&config).await?;
+    }
+    let redacted_config = config.redacted();
     db::sources::update_source(&pool, tenant_id, &name, source_id, config, &encryption_key)
         .await?
         .ok_or(SourceError::SourceNotFound(source_id))?;
-    Ok(HttpResponse::Ok().finish())
+    let response = GetSourceResponse {
+        id: source_id,
+        tenant_id: tenant_id.to_string(),
+        name,
+        config: redacted_config,
+    };
+    Ok(Json(response))
 }
 
 #[utoipa::path(
@@ -204,7 +245,7 @@ pub async fn update_source(
         ("source_id" = i64, Path, description = "Id of the source"),
     ),
     responses(
-        (status = 200, description = "Delete source with id = source_id"),
+        (status = 204, description = "Delete source with id = source_id"),
         (status = 404, description = "Source not found"),
         (status = 500, description = "Internal server error")
     )
@@ -220,13 +261,18 @@ pub async fn delete_source(
     db::sources::delete_source(&pool, tenant_id, source_id)
         .await?
         .ok_or(SourceError::SourceNotFound(source_id))?;
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[utoipa::path(
     context_path = "/v1",
+    params(
+        ("limit" = Option<i64>, Query, description = "Maximum number of sources to return, 100 by default and at most 1000"),
+        ("offset" = Option<i64>, Query, description = "Number of sources to skip"),
+    ),
     responses(
-        (status = 200, description = "Return all sources"),
+        (status = 200, description = "Return a page of the sources ordered by id with their passwords redacted, their total number and the offset of the next page"),
+        (status = 400, description = "Negative limit or offset"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -235,17 +281,23 @@ pub async fn read_all_sources(
     req: HttpRequest,
     pool: Data<PgPool>,
     encryption_key: Data<EncryptionKey>,
+    query: Query<PageQuery>,
 ) -> Result<impl Responder, SourceError> {
     let tenant_id = extract_tenant_id(&req)?;
+    let (limit, offset) = query.limit_and_offset()?;
     let mut sources = vec![];
-    for source in db::sources::read_all_sources(&pool, tenant_id, &encryption_key).await? {
+    for source in
+        db::sources::read_all_sources(&pool, tenant_id, &encryption_key, Some(limit), offset)
+            .await?
+    {
         let source = GetSourceResponse {
             id: source.id,
             tenant_id: source.tenant_id,
             name: source.name,
-            config: source.config,
+            config: source.config.redacted(),
         };
         sources.push(source);
     }
-    Ok(Json(sources))
+    let total = db::sources::count_sources(&pool, tenant_id).await?;
+    Ok(Json(Page::new(sources, total, offset)))
 }
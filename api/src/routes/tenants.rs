@@ -2,7 +2,7 @@ use actix_web::{
     delete, get,
     http::{header::ContentType, StatusCode},
     post, put,
-    web::{Data, Json, Path},
+    web::{Data, Json, Path, Query},
     HttpResponse, Responder, ResponseError,
 };
 use serde::{Deserialize, Serialize};
@@ -12,7 +12,7 @@ use utoipa::ToSchema;
 
 use crate::db;
 
-use super::ErrorMessage;
+use super::{ErrorMessage, Page, PageQuery, PageQueryError};
 
 #[derive(Deserialize, ToSchema)]
 pub struct CreateTenantRequest {
@@ -40,6 +40,12 @@ enum TenantError {
 
     #[error("tenant with id {0} not found")]
     TenantNotFound(String),
+
+    #[error("tenant with id {0} still has sources, sinks or pipelines")]
+    TenantInUse(String),
+
+    #[error("invalid page: {0}")]
+    InvalidPage(#[from] PageQueryError),
 }
 
 impl TenantError {
@@ -58,6 +64,8 @@ impl ResponseError for TenantError {
         match self {
             TenantError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             TenantError::TenantNotFound(_) => StatusCode::NOT_FOUND,
+            TenantError::TenantInUse(_) => StatusCode::CONFLICT,
+            TenantError::InvalidPage(_) => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -184,6 +192,7 @@ pub async fn update_tenant(
     responses(
         (status = 200, description = "Delete tenant with id = tenant_id"),
         (status = 404, description = "Tenant not found"),
+        (status = 409, description = "Tenant still has sources, sinks or pipelines"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -193,22 +202,35 @@ pub async fn delete_tenant(
     tenant_id: Path<String>,
 ) -> Result<impl Responder, TenantError> {
     let tenant_id = tenant_id.into_inner();
-    db::tenants::delete_tenant(&pool, &tenant_id)
-        .await?
-        .ok_or(TenantError::TenantNotFound(tenant_id))?;
+    let deleted_tenant_id = match db::tenants::delete_tenant(&pool, &tenant_id).await {
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            return Err(TenantError::TenantInUse(tenant_id));
+        }
+        result => result?,
+    };
+    deleted_tenant_id.ok_or(TenantError::TenantNotFound(tenant_id))?;
     Ok(HttpResponse::Ok().finish())
 }
 
 #[utoipa::path(
     context_path = "/v1",
+    params(
+        ("limit" = Option<i64>, Query, description = "Maximum number of tenants to return, 100 by default and at most 1000"),
+        ("offset" = Option<i64>, Query, description = "Number of tenants to skip"),
+    ),
     responses(
-        (status = 200, description = "Return all tenants"),
+        (status = 200, description = "Return a page of the tenants ordered by id, their total number and the offset of the next page"),
+        (status = 400, description = "Negative limit or offset"),
         (status = 500, description = "Internal server error")
     )
 )]
 #[get("/tenants")]
-pub async fn read_all_tenants(pool: Data<PgPool>) -> Result<impl Responder, TenantError> {
-    let response: Vec<GetTenantResponse> = db::tenants::read_all_tenants(&pool)
+pub async fn read_all_tenants(
+    pool: Data<PgPool>,
+    query: Query<PageQuery>,
+) -> Result<impl Responder, TenantError> {
+    let (limit, offset) = query.limit_and_offset()?;
+    let tenants: Vec<GetTenantResponse> = db::tenants::read_all_tenants(&pool, Some(limit), offset)
         .await?
         .drain(..)
         .map(|t| GetTenantResponse {
@@ -216,5 +238,6 @@ pub async fn read_all_tenants(pool: Data<PgPool>) -> Result<impl Responder, Tena
             name: t.name,
         })
         .collect();
-    Ok(Json(response))
+    let total = db::tenants::count_tenants(&pool).await?;
+    Ok(Json(Page::new(tenants, total, offset)))
 }
@@ -16,7 +16,7 @@ use crate::{
     encryption,
     k8s_client::HttpK8sClient,
     routes::{
-        health_check::health_check,
+        health_check::{health_check, ready},
         images::{
             create_image, delete_image, read_all_images, read_image, update_image,
             GetImageResponse, PostImageRequest, PostImageResponse,
@@ -122,6 +122,7 @@ pub async fn run(
     #[openapi(
         paths(
             crate::routes::health_check::health_check,
+            crate::routes::health_check::ready,
             crate::routes::images::create_image,
             crate::routes::images::read_image,
             crate::routes::images::update_image,
@@ -188,6 +189,7 @@ pub async fn run(
         let app = App::new()
             .wrap(TracingLogger::default())
             .service(health_check)
+            .service(ready)
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
             )
@@ -0,0 +1,55 @@
+use reqwest::StatusCode;
+
+use crate::test_app::spawn_app;
+
+#[tokio::test]
+async fn unauthenticated_request_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/v1/tenants", app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn request_with_wrong_api_key_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/v1/tenants", app.address))
+        .bearer_auth("9XB3RH3lImLLAo8QvMjoDZv8AKaQOkkO3Q4kjbSTS4Q=")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn health_check_does_not_need_an_api_key() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert!(response.status().is_success());
+}
@@ -1,3 +1,5 @@
+use reqwest::StatusCode;
+
 use crate::test_app::spawn_app;
 
 #[tokio::test]
@@ -18,3 +20,40 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(2), response.content_length());
 }
+
+#[tokio::test]
+async fn ready_works() {
+    // Arrange
+    let app = spawn_app().await;
+
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/ready", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn ready_fails_when_database_is_unreachable() {
+    // Arrange
+    let app = spawn_app().await;
+    app.pool.close().await;
+
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/ready", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
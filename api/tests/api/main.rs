@@ -1,3 +1,4 @@
+mod authentication;
 mod database;
 mod health_check;
 mod images;
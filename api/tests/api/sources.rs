@@ -1,11 +1,17 @@
-use api::db::sources::SourceConfig;
+use api::{
+    configuration::get_configuration,
+    db::sources::{read_source, SourceConfig, SslMode},
+};
 use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+use sqlx::{Connection, PgConnection, Row};
+use uuid::Uuid;
 
 use crate::{
-    tenants::create_tenant,
+    tenants::{create_tenant, create_tenant_with_id_and_name},
     test_app::{
-        spawn_app, CreateSourceRequest, CreateSourceResponse, SourceResponse, TestApp,
-        UpdateSourceRequest,
+        spawn_app, CreateSourceRequest, CreateSourceResponse, PageResponse, SourceResponse,
+        TestApp, UpdateSourceRequest,
     },
 };
 
@@ -21,6 +27,9 @@ fn new_source_config() -> SourceConfig {
         username: "postgres".to_string(),
         password: Some("postgres".to_string()),
         slot_name: "slot".to_string(),
+        ssl_mode: None,
+        ca_cert: None,
+        statement_timeout_secs: None,
     }
 }
 
@@ -36,6 +45,9 @@ fn updated_source_config() -> SourceConfig {
         username: "sergtsop".to_string(),
         password: Some("sergtsop".to_string()),
         slot_name: "tols".to_string(),
+        ssl_mode: Some(SslMode::VerifyFull),
+        ca_cert: Some("-----BEGIN CERTIFICATE-----".to_string()),
+        statement_timeout_secs: Some(30),
     }
 }
 
@@ -80,6 +92,200 @@ async fn source_can_be_created() {
     assert_eq!(response.id, 1);
 }
 
+#[tokio::test]
+async fn reachable_source_can_be_created_with_validation() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let database = get_configuration()
+        .expect("Failed to read configuration")
+        .database;
+    // the slot is dropped when the connection which created it closes
+    let mut connection = PgConnection::connect_with(&database.with_db())
+        .await
+        .expect("Failed to connect to Postgres");
+    let slot_name = format!("validated_{}", Uuid::new_v4().simple());
+    sqlx::query("select pg_create_logical_replication_slot($1, 'pgoutput', true);")
+        .bind(&slot_name)
+        .execute(&mut connection)
+        .await
+        .expect("Failed to create replication slot");
+
+    // Act
+    let source = CreateSourceRequest {
+        name: new_name(),
+        config: SourceConfig::Postgres {
+            host: database.host,
+            port: database.port,
+            name: database.name,
+            username: database.username,
+            password: database
+                .password
+                .map(|password| password.expose_secret().to_string()),
+            slot_name,
+            ssl_mode: None,
+            ca_cert: None,
+            statement_timeout_secs: None,
+        },
+    };
+    let response = app.create_validated_source(tenant_id, &source).await;
+
+    // Assert
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn source_with_a_missing_slot_cant_be_created_with_validation() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let database = get_configuration()
+        .expect("Failed to read configuration")
+        .database;
+
+    // Act
+    let source = CreateSourceRequest {
+        name: new_name(),
+        config: SourceConfig::Postgres {
+            host: database.host,
+            port: database.port,
+            name: database.name,
+            username: database.username,
+            password: database
+                .password
+                .map(|password| password.expose_secret().to_string()),
+            slot_name: "missing_slot".to_string(),
+            ssl_mode: None,
+            ca_cert: None,
+            statement_timeout_secs: None,
+        },
+    };
+    let response = app.create_validated_source(tenant_id, &source).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.text().await.expect("failed to read response");
+    assert!(
+        body.contains("replication slot missing_slot doesn't exist"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn unreachable_source_cant_be_created_with_validation() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+
+    // Act
+    let source = CreateSourceRequest {
+        name: new_name(),
+        config: SourceConfig::Postgres {
+            host: "localhost".to_string(),
+            port: 1,
+            name: "postgres".to_string(),
+            username: "postgres".to_string(),
+            password: None,
+            slot_name: "slot".to_string(),
+            ssl_mode: None,
+            ca_cert: None,
+            statement_timeout_secs: None,
+        },
+    };
+    let response = app.create_validated_source(tenant_id, &source).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn source_password_is_encrypted_in_db() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let password = "a-very-secret-password";
+    let config = SourceConfig::Postgres {
+        host: "localhost".to_string(),
+        port: 5432,
+        name: "postgres".to_string(),
+        username: "postgres".to_string(),
+        password: Some(password.to_string()),
+        slot_name: "slot".to_string(),
+        ssl_mode: None,
+        ca_cert: None,
+        statement_timeout_secs: None,
+    };
+
+    // Act
+    let source_id = create_source_with_config(&app, tenant_id, new_name(), config).await;
+
+    // Assert
+    let record = sqlx::query("select config::text as config from app.sources where id = $1")
+        .bind(source_id)
+        .fetch_one(&app.pool)
+        .await
+        .expect("failed to read source from db");
+    let config: String = record.get("config");
+    assert!(!config.contains(password));
+}
+
+#[tokio::test]
+async fn source_password_is_never_in_responses() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let password = "a-very-secret-password";
+    let config = SourceConfig::Postgres {
+        host: "localhost".to_string(),
+        port: 5432,
+        name: "postgres".to_string(),
+        username: "postgres".to_string(),
+        password: Some(password.to_string()),
+        slot_name: "slot".to_string(),
+        ssl_mode: None,
+        ca_cert: None,
+        statement_timeout_secs: None,
+    };
+    let source = CreateSourceRequest {
+        name: new_name(),
+        config: config.clone(),
+    };
+
+    // Act
+    let response = app.create_source(tenant_id, &source).await;
+    let create_body = response.text().await.expect("failed to read response");
+    let response: CreateSourceResponse =
+        serde_json::from_str(&create_body).expect("failed to deserialize response");
+    let source_id = response.id;
+    let read_body = app
+        .read_source(tenant_id, source_id)
+        .await
+        .text()
+        .await
+        .expect("failed to read response");
+    let updated_source = UpdateSourceRequest {
+        name: updated_name(),
+        config,
+    };
+    let update_body = app
+        .update_source(tenant_id, source_id, &updated_source)
+        .await
+        .text()
+        .await
+        .expect("failed to read response");
+    let read_all_body = app
+        .read_all_sources(tenant_id)
+        .await
+        .text()
+        .await
+        .expect("failed to read response");
+
+    // Assert
+    for body in [create_body, read_body, update_body, read_all_body] {
+        assert!(!body.contains(password), "password in response {body}");
+    }
+}
+
 #[tokio::test]
 async fn an_existing_source_can_be_read() {
     // Arrange
@@ -109,7 +315,7 @@ async fn an_existing_source_can_be_read() {
     assert_eq!(response.id, source_id);
     assert_eq!(&response.tenant_id, tenant_id);
     assert_eq!(response.name, source.name);
-    assert_eq!(response.config, source.config);
+    assert_eq!(response.config, source.config.redacted());
 }
 
 #[tokio::test]
@@ -125,6 +331,31 @@ async fn a_non_existing_source_cant_be_read() {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn another_tenants_source_cant_be_read() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant1_id = &create_tenant_with_id_and_name(
+        &app,
+        "abcdefghijklmnopqrst".to_string(),
+        "tenant_1".to_string(),
+    )
+    .await;
+    let tenant2_id = &create_tenant_with_id_and_name(
+        &app,
+        "tsrqponmlkjihgfedcba".to_string(),
+        "tenant_2".to_string(),
+    )
+    .await;
+    let source2_id = create_source(&app, tenant2_id).await;
+
+    // Act
+    let response = app.read_source(tenant1_id, source2_id).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
 async fn an_existing_source_can_be_updated() {
     // Arrange
@@ -153,6 +384,14 @@ async fn an_existing_source_can_be_updated() {
 
     // Assert
     assert!(response.status().is_success());
+    let response: SourceResponse = response
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(response.id, source_id);
+    assert_eq!(&response.tenant_id, tenant_id);
+    assert_eq!(response.name, updated_config.name);
+    assert_eq!(response.config, updated_config.config.redacted());
     let response = app.read_source(tenant_id, source_id).await;
     let response: SourceResponse = response
         .json()
@@ -161,7 +400,78 @@ async fn an_existing_source_can_be_updated() {
     assert_eq!(response.id, source_id);
     assert_eq!(&response.tenant_id, tenant_id);
     assert_eq!(response.name, updated_config.name);
-    assert_eq!(response.config, updated_config.config);
+    assert_eq!(response.config, updated_config.config.redacted());
+}
+
+#[tokio::test]
+async fn a_source_updated_without_a_password_keeps_its_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let source_id = create_source(&app, tenant_id).await;
+
+    // Act
+    let SourceConfig::Postgres {
+        port,
+        name,
+        username,
+        slot_name,
+        ..
+    } = new_source_config();
+    let updated_source = UpdateSourceRequest {
+        name: new_name(),
+        config: SourceConfig::Postgres {
+            host: "example.com".to_string(),
+            port,
+            name,
+            username,
+            password: None,
+            slot_name,
+            ssl_mode: None,
+            ca_cert: None,
+            statement_timeout_secs: None,
+        },
+    };
+    let response = app
+        .update_source(tenant_id, source_id, &updated_source)
+        .await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let source = read_source(&app.pool, tenant_id, source_id, &app.encryption_key)
+        .await
+        .expect("failed to read source")
+        .expect("source not found");
+    let SourceConfig::Postgres { host, password, .. } = source.config;
+    assert_eq!(host, "example.com");
+    assert_eq!(password.as_deref(), Some("postgres"));
+}
+
+#[tokio::test]
+async fn a_source_can_still_be_updated_with_post() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    let source_id = create_source(&app, tenant_id).await;
+
+    // Act
+    let updated_source = UpdateSourceRequest {
+        name: updated_name(),
+        config: updated_source_config(),
+    };
+    let response = app
+        .update_source_with_post(tenant_id, source_id, &updated_source)
+        .await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let response = app.read_source(tenant_id, source_id).await;
+    let response: SourceResponse = response
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(response.name, updated_source.name);
+    assert_eq!(response.config, updated_source.config.redacted());
 }
 
 #[tokio::test]
@@ -202,7 +512,7 @@ async fn an_existing_source_can_be_deleted() {
     let response = app.delete_source(tenant_id, source_id).await;
 
     // Assert
-    assert!(response.status().is_success());
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
     let response = app.read_source(tenant_id, source_id).await;
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
@@ -220,6 +530,33 @@ async fn a_non_existing_source_cant_be_deleted() {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn another_tenants_source_cant_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant1_id = &create_tenant_with_id_and_name(
+        &app,
+        "abcdefghijklmnopqrst".to_string(),
+        "tenant_1".to_string(),
+    )
+    .await;
+    let tenant2_id = &create_tenant_with_id_and_name(
+        &app,
+        "tsrqponmlkjihgfedcba".to_string(),
+        "tenant_2".to_string(),
+    )
+    .await;
+    let source2_id = create_source(&app, tenant2_id).await;
+
+    // Act
+    let response = app.delete_source(tenant1_id, source2_id).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.read_source(tenant2_id, source2_id).await;
+    assert!(response.status().is_success());
+}
+
 #[tokio::test]
 async fn all_sources_can_be_read() {
     // Arrange
@@ -235,23 +572,84 @@ async fn all_sources_can_be_read() {
 
     // Assert
     assert!(response.status().is_success());
-    let response: Vec<SourceResponse> = response
+    let response: PageResponse<SourceResponse> = response
         .json()
         .await
         .expect("failed to deserialize response");
-    for source in response {
+    assert_eq!(response.items.len(), 2);
+    assert_eq!(response.total, 2);
+    assert_eq!(response.next, None);
+    for source in response.items {
         if source.id == source1_id {
             let name = new_name();
-            let config = new_source_config();
+            let config = new_source_config().redacted();
             assert_eq!(&source.tenant_id, tenant_id);
             assert_eq!(source.name, name);
             assert_eq!(source.config, config);
         } else if source.id == source2_id {
             let name = updated_name();
-            let config = updated_source_config();
+            let config = updated_source_config().redacted();
             assert_eq!(&source.tenant_id, tenant_id);
             assert_eq!(source.name, name);
             assert_eq!(source.config, config);
+        } else {
+            panic!("unexpected source id {}", source.id);
         }
     }
 }
+
+#[tokio::test]
+async fn sources_can_be_read_in_pages() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+    create_source_with_config(&app, tenant_id, new_name(), new_source_config()).await;
+    let source2_id =
+        create_source_with_config(&app, tenant_id, updated_name(), updated_source_config()).await;
+    create_source_with_config(&app, tenant_id, new_name(), new_source_config()).await;
+
+    // Act
+    let first_page = app.read_sources_page(tenant_id, 1, 1).await;
+    let repeated_first_page = app.read_sources_page(tenant_id, 1, 1).await;
+    let last_page = app.read_sources_page(tenant_id, 2, 2).await;
+
+    // Assert
+    assert!(first_page.status().is_success());
+    let first_page: PageResponse<SourceResponse> = first_page
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(first_page.items.len(), 1);
+    assert_eq!(first_page.items[0].id, source2_id);
+    assert_eq!(first_page.items[0].name, updated_name());
+    assert_eq!(first_page.total, 3);
+    assert_eq!(first_page.next, Some(2));
+    let repeated_first_page: PageResponse<SourceResponse> = repeated_first_page
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(repeated_first_page.items.len(), 1);
+    assert_eq!(repeated_first_page.items[0].id, source2_id);
+    let last_page: PageResponse<SourceResponse> = last_page
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(last_page.items.len(), 1);
+    assert_eq!(last_page.total, 3);
+    assert_eq!(last_page.next, None);
+}
+
+#[tokio::test]
+async fn sources_cant_be_read_with_a_negative_limit_or_offset() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = &create_tenant(&app).await;
+
+    // Act
+    let negative_limit = app.read_sources_page(tenant_id, -1, 0).await;
+    let negative_offset = app.read_sources_page(tenant_id, 1, -1).await;
+
+    // Assert
+    assert_eq!(negative_limit.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(negative_offset.status(), StatusCode::BAD_REQUEST);
+}
@@ -1,8 +1,11 @@
 use reqwest::StatusCode;
 
-use crate::test_app::{
-    spawn_app, CreateTenantRequest, CreateTenantResponse, TenantResponse, TestApp,
-    UpdateTenantRequest,
+use crate::{
+    sources::create_source,
+    test_app::{
+        spawn_app, CreateTenantRequest, CreateTenantResponse, PageResponse, TenantResponse,
+        TestApp, UpdateTenantRequest,
+    },
 };
 
 pub async fn create_tenant(app: &TestApp) -> String {
@@ -267,11 +270,14 @@ async fn all_tenants_can_be_read() {
 
     // Assert
     assert!(response.status().is_success());
-    let response: Vec<TenantResponse> = response
+    let response: PageResponse<TenantResponse> = response
         .json()
         .await
         .expect("failed to deserialize response");
-    for tenant in response {
+    assert_eq!(response.items.len(), 2);
+    assert_eq!(response.total, 2);
+    assert_eq!(response.next, None);
+    for tenant in response.items {
         if tenant.id == tenant1_id {
             assert_eq!(tenant.name, "Tenant1");
         } else if tenant.id == tenant2_id {
@@ -279,3 +285,66 @@ async fn all_tenants_can_be_read() {
         }
     }
 }
+
+#[tokio::test]
+async fn tenants_can_be_read_in_pages() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant_with_id_and_name(
+        &app,
+        "abcdefghijklmnopqrst".to_string(),
+        "Tenant1".to_string(),
+    )
+    .await;
+    let tenant2_id = create_tenant_with_id_and_name(
+        &app,
+        "tsrqponmlkjihgfedcba".to_string(),
+        "Tenant2".to_string(),
+    )
+    .await;
+
+    // Act
+    let response = app.read_tenants_page(1, 1).await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let response: PageResponse<TenantResponse> = response
+        .json()
+        .await
+        .expect("failed to deserialize response");
+    assert_eq!(response.items.len(), 1);
+    assert_eq!(response.items[0].id, tenant2_id);
+    assert_eq!(response.items[0].name, "Tenant2");
+    assert_eq!(response.total, 2);
+    assert_eq!(response.next, None);
+}
+
+#[tokio::test]
+async fn tenants_cant_be_read_with_a_negative_limit_or_offset() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let negative_limit = app.read_tenants_page(-1, 0).await;
+    let negative_offset = app.read_tenants_page(1, -1).await;
+
+    // Assert
+    assert_eq!(negative_limit.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(negative_offset.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_tenant_with_sources_cant_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = create_tenant(&app).await;
+    create_source(&app, &tenant_id).await;
+
+    // Act
+    let response = app.delete_tenant(&tenant_id).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app.read_tenant(&tenant_id).await;
+    assert!(response.status().is_success());
+}
@@ -3,11 +3,16 @@ use std::net::TcpListener;
 use api::{
     configuration::get_configuration,
     db::{pipelines::PipelineConfig, sinks::SinkConfig, sources::SourceConfig},
-    encryption::{self, generate_random_key},
+    encryption::EncryptionKey,
     startup::{get_connection_pool, run},
 };
+use aws_lc_rs::{
+    aead::{RandomizedNonceKey, AES_256_GCM},
+    rand::fill,
+};
 use reqwest::{IntoUrl, RequestBuilder};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::database::configure_database;
@@ -16,6 +21,9 @@ pub struct TestApp {
     pub address: String,
     pub api_client: reqwest::Client,
     pub api_key: String,
+    pub pool: PgPool,
+    /// The key the app encrypts secrets with, e.g. to decrypt them in the database
+    pub encryption_key: EncryptionKey,
 }
 
 #[derive(Serialize)]
@@ -57,6 +65,13 @@ pub struct UpdateSourceRequest {
     pub config: SourceConfig,
 }
 
+#[derive(Deserialize)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next: Option<i64>,
+}
+
 #[derive(Deserialize)]
 pub struct SourceResponse {
     pub id: i64,
@@ -211,6 +226,16 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn read_tenants_page(&self, limit: i64, offset: i64) -> reqwest::Response {
+        self.get_authenticated(format!(
+            "{}/v1/tenants?limit={limit}&offset={offset}",
+            &self.address
+        ))
+        .send()
+        .await
+        .expect("failed to execute request")
+    }
+
     pub async fn read_all_tenants(&self) -> reqwest::Response {
         self.get_authenticated(format!("{}/v1/tenants", &self.address))
             .send()
@@ -231,6 +256,19 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn create_validated_source(
+        &self,
+        tenant_id: &str,
+        source: &CreateSourceRequest,
+    ) -> reqwest::Response {
+        self.post_authenticated(format!("{}/v1/sources?validate=true", &self.address))
+            .header("tenant_id", tenant_id)
+            .json(source)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn read_source(&self, tenant_id: &str, source_id: i64) -> reqwest::Response {
         self.get_authenticated(format!("{}/v1/sources/{source_id}", &self.address))
             .header("tenant_id", tenant_id)
@@ -244,6 +282,20 @@ impl TestApp {
         tenant_id: &str,
         source_id: i64,
         source: &UpdateSourceRequest,
+    ) -> reqwest::Response {
+        self.put_authenticated(format!("{}/v1/sources/{source_id}", &self.address))
+            .header("tenant_id", tenant_id)
+            .json(source)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn update_source_with_post(
+        &self,
+        tenant_id: &str,
+        source_id: i64,
+        source: &UpdateSourceRequest,
     ) -> reqwest::Response {
         self.post_authenticated(format!("{}/v1/sources/{source_id}", &self.address))
             .header("tenant_id", tenant_id)
@@ -269,6 +321,22 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn read_sources_page(
+        &self,
+        tenant_id: &str,
+        limit: i64,
+        offset: i64,
+    ) -> reqwest::Response {
+        self.get_authenticated(format!(
+            "{}/v1/sources?limit={limit}&offset={offset}",
+            &self.address
+        ))
+        .header("tenant_id", tenant_id)
+        .send()
+        .await
+        .expect("failed to execute request")
+    }
+
     pub async fn create_sink(
         &self,
         tenant_id: &str,
@@ -420,13 +488,18 @@ pub async fn spawn_app() -> TestApp {
     configuration.database.name = Uuid::new_v4().to_string();
     let connection_pool = get_connection_pool(&configuration.database);
     configure_database(&configuration.database).await;
-    let key = generate_random_key::<32>().expect("failed to generate random key");
-    let encryption_key = encryption::EncryptionKey { id: 0, key };
+    let mut key_bytes = [0u8; 32];
+    fill(&mut key_bytes).expect("failed to generate random key");
+    // the key can't be cloned, so the app and the tests each get one with the same bytes
+    let encryption_key = || EncryptionKey {
+        id: 0,
+        key: RandomizedNonceKey::new(&AES_256_GCM, &key_bytes).expect("failed to create key"),
+    };
     let api_key = "XOUbHmWbt9h7nWl15wWwyWQnctmFGNjpawMc3lT5CFs=".to_string();
     let server = run(
         listener,
         connection_pool.clone(),
-        encryption_key,
+        encryption_key(),
         api_key.clone(),
         None,
     )
@@ -439,5 +512,7 @@ pub async fn spawn_app() -> TestApp {
         address,
         api_client,
         api_key,
+        pool: connection_pool,
+        encryption_key: encryption_key(),
     }
 }
@@ -1,15 +1,22 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use clap::{Args, Parser, Subcommand};
 use pg_replicate::{
+    clients::postgres::ReplicationClient,
+    conversions::{cdc_event::CdcEvent, table_row::Cell, UnsupportedTypePolicy},
     pipeline::{
         data_pipeline::DataPipeline,
         sinks::stdout::StdoutSink,
-        sources::postgres::{PostgresSource, TableNamesFrom},
-        PipelineAction,
+        sources::postgres::{PostgresSource, ReplicationSlot, SourceOptions, TableNamesFrom},
+        PipelineAction, TransformEvent,
     },
     table::TableName,
 };
+use tokio_postgres::{types::PgLsn, Config};
 use tracing::error;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -19,6 +26,15 @@ struct AppArgs {
     #[clap(flatten)]
     db_args: DbArgs,
 
+    /// What to do with columns of unsupported types: skip keeps their raw bytes,
+    /// fail stops the pipeline with an error
+    #[arg(long, default_value = "skip", value_parser = parse_unsupported_type_policy)]
+    on_unsupported_type: UnsupportedTypePolicy,
+
+    /// Replace every string value with *** before printing it
+    #[arg(long)]
+    mask_strings: bool,
+
     #[clap(subcommand)]
     command: Command,
 }
@@ -41,13 +57,27 @@ struct DbArgs {
     #[arg(long)]
     db_username: String,
 
-    /// Postgres database user password
-    #[arg(long)]
+    /// Postgres database user password. Falls back to the PG_REPLICATE_DB_PASSWORD
+    /// environment variable when not passed
+    #[arg(long, env = "PG_REPLICATE_DB_PASSWORD", hide_env_values = true)]
     db_password: Option<String>,
+
+    /// File to read the Postgres database user password from, e.g. a mounted secret.
+    /// A trailing newline is ignored
+    #[arg(long, conflicts_with = "db_password")]
+    db_password_file: Option<PathBuf>,
+
+    /// Cancel queries which run longer than this many seconds, e.g. creating the
+    /// replication slot or starting a table copy. The cdc stream is not affected
+    #[arg(long)]
+    statement_timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
+    /// List all publications and the tables they contain
+    ListPublications,
+
     /// Copy a table
     CopyTable { schema: String, name: String },
 
@@ -55,6 +85,31 @@ enum Command {
     Cdc {
         publication: String,
         slot_name: String,
+
+        /// Create the replication slot if it doesn't exist
+        #[arg(long)]
+        create_slot: bool,
+
+        /// Drop the replication slot when the pipeline exits
+        #[arg(long)]
+        drop_slot_on_exit: bool,
+
+        /// Create a temporary replication slot which is dropped when the pipeline exits.
+        /// Implies --create-slot
+        #[arg(long)]
+        temporary_slot: bool,
+
+        /// Start replicating at this lsn, e.g. 16/B374D848, instead of resuming after the
+        /// last lsn saved by the sink. This may skip changes, or repeat those after the
+        /// slot's confirmed lsn. Earlier lsns are rejected, since Postgres would start
+        /// at the confirmed lsn instead
+        #[arg(long, value_parser = parse_lsn)]
+        start_lsn: Option<PgLsn>,
+
+        /// Confirm the server's wal end to Postgres at most every this many seconds while
+        /// no transaction is in progress, so that WAL isn't retained during quiet periods
+        #[arg(long)]
+        heartbeat_interval_secs: Option<u64>,
     },
 }
 
@@ -67,6 +122,63 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn parse_lsn(lsn: &str) -> Result<PgLsn, String> {
+    lsn.parse().map_err(|_| format!("{lsn} is not a valid lsn"))
+}
+
+fn read_password_file(path: &Path) -> Result<String, std::io::Error> {
+    let password = std::fs::read_to_string(path)?;
+    let trimmed = password
+        .strip_suffix("\r\n")
+        .or_else(|| password.strip_suffix('\n'))
+        .unwrap_or(&password);
+    Ok(trimmed.to_string())
+}
+
+fn connection_config(db_args: &DbArgs) -> Config {
+    let mut config = Config::new();
+    config
+        .host(&db_args.db_host)
+        .port(db_args.db_port)
+        .dbname(&db_args.db_name)
+        .user(&db_args.db_username);
+    if let Some(ref password) = db_args.db_password {
+        config.password(password);
+    }
+    config
+}
+
+fn source_options(db_args: &DbArgs) -> SourceOptions {
+    SourceOptions {
+        statement_timeout: db_args.statement_timeout_secs.map(Duration::from_secs),
+    }
+}
+
+fn parse_unsupported_type_policy(policy: &str) -> Result<UnsupportedTypePolicy, String> {
+    match policy {
+        "skip" => Ok(UnsupportedTypePolicy::Skip),
+        "fail" => Ok(UnsupportedTypePolicy::Fail),
+        _ => Err(format!("{policy} is not one of skip or fail")),
+    }
+}
+
+/// A transform which masks the values of all string columns
+fn mask_strings(event: &mut TransformEvent) -> bool {
+    let row = match event {
+        TransformEvent::TableRow(_, row) => row,
+        TransformEvent::CdcEvent(
+            CdcEvent::Insert((_, row)) | CdcEvent::Update((_, row)) | CdcEvent::Delete((_, row)),
+        ) => row,
+        TransformEvent::CdcEvent(_) => return true,
+    };
+    for value in &mut row.values {
+        if let Cell::String(s) = value {
+            *s = "***".to_string();
+        }
+    }
+    true
+}
+
 fn init_tracing() {
     tracing_subscriber::registry()
         .with(
@@ -87,48 +199,122 @@ async fn main_impl() -> Result<(), Box<dyn Error>> {
     set_log_level();
     init_tracing();
     let args = AppArgs::parse();
-    let db_args = args.db_args;
-
-    let (postgres_source, action) = match args.command {
-        Command::CopyTable { schema, name } => {
-            let table_names = vec![TableName { schema, name }];
-
-            let postgres_source = PostgresSource::new(
-                &db_args.db_host,
-                db_args.db_port,
-                &db_args.db_name,
-                &db_args.db_username,
-                db_args.db_password,
-                None,
-                TableNamesFrom::Vec(table_names),
-            )
-            .await?;
-            (postgres_source, PipelineAction::TableCopiesOnly)
-        }
-        Command::Cdc {
-            publication,
-            slot_name,
-        } => {
-            let postgres_source = PostgresSource::new(
-                &db_args.db_host,
-                db_args.db_port,
-                &db_args.db_name,
-                &db_args.db_username,
-                db_args.db_password,
-                Some(slot_name),
-                TableNamesFrom::Publication(publication),
-            )
-            .await?;
-
-            (postgres_source, PipelineAction::Both)
-        }
-    };
+    let mut db_args = args.db_args;
+    if let Some(ref path) = db_args.db_password_file {
+        db_args.db_password = Some(read_password_file(path)?);
+    }
+
+    let (mut postgres_source, action, slot_to_drop, start_lsn, heartbeat_interval_secs) =
+        match args.command {
+            Command::ListPublications => {
+                let replication_client = ReplicationClient::connect_no_tls(
+                    &db_args.db_host,
+                    db_args.db_port,
+                    &db_args.db_name,
+                    &db_args.db_username,
+                    db_args.db_password,
+                )
+                .await?;
+                for publication in replication_client.list_publications().await? {
+                    println!("{publication}");
+                    for table_name in replication_client
+                        .get_publication_table_names(&publication)
+                        .await?
+                    {
+                        println!("  {table_name}");
+                    }
+                }
+                return Ok(());
+            }
+            Command::CopyTable { schema, name } => {
+                let table_names = vec![TableName { schema, name }];
+
+                let postgres_source = PostgresSource::new_with_options(
+                    connection_config(&db_args),
+                    None,
+                    TableNamesFrom::Vec(table_names),
+                    source_options(&db_args),
+                )
+                .await?;
+                (
+                    postgres_source,
+                    PipelineAction::TableCopiesOnly,
+                    None,
+                    None,
+                    None,
+                )
+            }
+            Command::Cdc {
+                publication,
+                slot_name,
+                create_slot,
+                drop_slot_on_exit,
+                temporary_slot,
+                start_lsn,
+                heartbeat_interval_secs,
+            } => {
+                let slot_to_drop = drop_slot_on_exit.then(|| slot_name.clone());
+                let postgres_source = PostgresSource::new_with_options(
+                    connection_config(&db_args),
+                    Some(ReplicationSlot {
+                        name: slot_name,
+                        create_if_missing: create_slot || temporary_slot,
+                        temporary: temporary_slot,
+                    }),
+                    TableNamesFrom::Publication(publication),
+                    source_options(&db_args),
+                )
+                .await?;
+
+                if let Some(start_lsn) = start_lsn {
+                    postgres_source.validate_start_lsn(start_lsn).await?;
+                }
+
+                (
+                    postgres_source,
+                    PipelineAction::Both,
+                    slot_to_drop,
+                    start_lsn,
+                    heartbeat_interval_secs,
+                )
+            }
+        };
+
+    postgres_source.set_unsupported_type_policy(args.on_unsupported_type);
 
     let stdout_sink = StdoutSink;
 
     let mut pipeline = DataPipeline::new(postgres_source, stdout_sink, action);
 
-    pipeline.start().await?;
+    if let Some(start_lsn) = start_lsn {
+        pipeline.set_start_lsn(start_lsn);
+    }
+
+    if let Some(heartbeat_interval_secs) = heartbeat_interval_secs {
+        pipeline.set_heartbeat_interval(Duration::from_secs(heartbeat_interval_secs));
+    }
+
+    if args.mask_strings {
+        pipeline.set_transform(mask_strings);
+    }
+
+    let result = pipeline.start().await;
+
+    if let Some(slot_name) = slot_to_drop {
+        // the slot stays active until the pipeline's replication connection is closed
+        drop(pipeline);
+        let replication_client = ReplicationClient::connect_no_tls(
+            &db_args.db_host,
+            db_args.db_port,
+            &db_args.db_name,
+            &db_args.db_username,
+            db_args.db_password,
+        )
+        .await?;
+        replication_client.drop_slot(&slot_name, true).await?;
+    }
+
+    result?;
 
     Ok(())
 }
@@ -14,11 +14,16 @@ use gcp_bigquery_client::{
     Client,
 };
 use prost::Message;
-use tokio_postgres::types::{PgLsn, Type};
+use tokio_postgres::types::{Kind, PgLsn, Type};
 use tracing::info;
 
 use crate::{
-    conversions::table_row::{Cell, TableRow},
+    conversions::{
+        base_type,
+        geometry::is_geometric_type,
+        is_hstore, is_nested_type,
+        table_row::{Cell, TableRow},
+    },
     table::{ColumnSchema, TableId, TableSchema},
 };
 
@@ -66,12 +71,24 @@ impl BigQueryClient {
     }
 
     fn postgres_type_to_bigquery_type(typ: &Type) -> &'static str {
-        match typ {
-            &Type::INT2 | &Type::INT4 | &Type::INT8 => "int64",
+        match base_type(typ) {
+            &Type::INT2 | &Type::INT4 | &Type::INT8 | &Type::MONEY => "int64",
             &Type::BOOL => "bool",
             &Type::BYTEA => "bytes",
-            &Type::VARCHAR | &Type::BPCHAR | &Type::TEXT => "string",
+            &Type::VARCHAR
+            | &Type::BPCHAR
+            | &Type::TEXT
+            | &Type::XML
+            | &Type::TS_VECTOR
+            | &Type::TSQUERY => "string",
+            // numerics can exceed bignumeric's precision so they're kept exact as text
+            &Type::NUMERIC => "string",
             &Type::TIMESTAMP => "timestamp",
+            // ranges are replicated in their text form
+            typ if matches!(typ.kind(), Kind::Range(_)) => "string",
+            typ if is_hstore(typ) => "json",
+            typ if is_geometric_type(typ) => "json",
+            typ if is_nested_type(typ) => "json",
             _ => "bytes",
         }
     }
@@ -348,6 +365,18 @@ impl BigQueryClient {
                 let bytes: String = b.iter().map(|b| *b as char).collect();
                 s.push_str(&format!("b'{bytes}'"))
             }
+            Cell::Map(m) => {
+                let json = serde_json::to_string(m).expect("failed to serialize map");
+                s.push_str(&format!("json '{json}'"))
+            }
+            Cell::Geometry(g) => {
+                let json = serde_json::to_string(g).expect("failed to serialize geometry");
+                s.push_str(&format!("json '{json}'"))
+            }
+            Cell::Array(_) | Cell::Composite(_) => {
+                let json = serde_json::to_string(cell).expect("failed to serialize cell");
+                s.push_str(&format!("json '{json}'"))
+            }
         }
     }
 
@@ -544,6 +573,18 @@ impl Message for TableRow {
                         ::prost::encoding::bytes::encode(tag, b, buf);
                     }
                 }
+                Cell::Map(m) => {
+                    let json = serde_json::to_string(m).expect("failed to serialize map");
+                    ::prost::encoding::string::encode(tag, &json, buf);
+                }
+                Cell::Geometry(g) => {
+                    let json = serde_json::to_string(g).expect("failed to serialize geometry");
+                    ::prost::encoding::string::encode(tag, &json, buf);
+                }
+                Cell::Array(_) | Cell::Composite(_) => {
+                    let json = serde_json::to_string(cell).expect("failed to serialize cell");
+                    ::prost::encoding::string::encode(tag, &json, buf);
+                }
             }
             tag += 1;
         }
@@ -618,6 +659,18 @@ impl Message for TableRow {
                         0
                     }
                 }
+                Cell::Map(m) => {
+                    let json = serde_json::to_string(m).expect("failed to serialize map");
+                    ::prost::encoding::string::encoded_len(tag, &json)
+                }
+                Cell::Geometry(g) => {
+                    let json = serde_json::to_string(g).expect("failed to serialize geometry");
+                    ::prost::encoding::string::encoded_len(tag, &json)
+                }
+                Cell::Array(_) | Cell::Composite(_) => {
+                    let json = serde_json::to_string(cell).expect("failed to serialize cell");
+                    ::prost::encoding::string::encoded_len(tag, &json)
+                }
             };
             tag += 1;
         }
@@ -635,6 +688,8 @@ impl Message for TableRow {
                 Cell::I64(i) => *i = 0,
                 Cell::TimeStamp(t) => t.clear(),
                 Cell::Bytes(b) => b.clear(),
+                Cell::Map(m) => m.clear(),
+                Cell::Geometry(_) | Cell::Array(_) | Cell::Composite(_) => *cell = Cell::Null,
             }
         }
     }
@@ -645,15 +700,27 @@ impl From<&TableSchema> for TableDescriptor {
         let mut field_descriptors = Vec::with_capacity(value.column_schemas.len());
         let mut number = 1;
         for column_schema in &value.column_schemas {
-            let typ = match column_schema.typ {
+            let typ = match *base_type(&column_schema.typ) {
                 Type::BOOL => ColumnType::Bool,
-                Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => {
-                    ColumnType::String
-                }
+                Type::CHAR
+                | Type::BPCHAR
+                | Type::VARCHAR
+                | Type::NAME
+                | Type::TEXT
+                | Type::XML
+                | Type::TS_VECTOR
+                | Type::TSQUERY
+                | Type::NUMERIC => ColumnType::String,
                 Type::INT2 => ColumnType::Int64,
                 Type::INT4 => ColumnType::Int64,
                 Type::INT8 => ColumnType::Int64,
+                Type::MONEY => ColumnType::Int64,
                 Type::TIMESTAMP => ColumnType::String,
+                ref typ if matches!(typ.kind(), Kind::Range(_)) => ColumnType::String,
+                // the json column type takes a string
+                ref typ if is_hstore(typ) || is_geometric_type(typ) || is_nested_type(typ) => {
+                    ColumnType::String
+                }
                 _ => ColumnType::Bytes,
             };
             field_descriptors.push(FieldDescriptor {
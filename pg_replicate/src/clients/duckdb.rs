@@ -5,10 +5,16 @@ use duckdb::{
     types::{Null, ToSqlOutput},
     Config, Connection, ToSql,
 };
-use tokio_postgres::types::{PgLsn, Type};
+use tokio_postgres::types::{Kind, PgLsn, Type};
 
 use crate::{
-    conversions::table_row::{Cell, TableRow},
+    conversions::{
+        base_type,
+        geometry::is_geometric_type,
+        is_hstore, is_nested_type,
+        table_row::{Cell, TableRow},
+    },
+    escape::quote_identifier,
     table::{ColumnSchema, TableId, TableName, TableSchema},
 };
 
@@ -72,7 +78,7 @@ impl DuckDbClient {
     }
 
     pub fn create_schema(&self, schema_name: &str) -> Result<(), duckdb::Error> {
-        let query = format!("create schema {schema_name}");
+        let query = format!("create schema {}", quote_identifier(schema_name));
         self.conn.execute(&query, [])?;
         Ok(())
     }
@@ -99,18 +105,37 @@ impl DuckDbClient {
     }
 
     fn postgres_typ_to_duckdb_typ(typ: &Type) -> &'static str {
-        match typ {
+        match base_type(typ) {
             &Type::INT2 | &Type::INT4 | &Type::INT8 => "integer",
+            // money is an int8 amount in cents, which overflows duckdb's 32 bit integer
+            &Type::MONEY => "bigint",
             &Type::BOOL => "bool",
-            &Type::BYTEA => "bytea",
-            &Type::VARCHAR | &Type::BPCHAR => "text",
+            &Type::CHAR
+            | &Type::BPCHAR
+            | &Type::VARCHAR
+            | &Type::NAME
+            | &Type::TEXT
+            | &Type::XML
+            | &Type::TS_VECTOR
+            | &Type::TSQUERY => "text",
+            // numerics can exceed decimal's precision so they're kept exact as text
+            &Type::NUMERIC => "text",
             &Type::TIMESTAMP => "timestamp",
-            typ => panic!("duckdb doesn't yet support type {typ}"),
+            // ranges are replicated in their text form
+            typ if matches!(typ.kind(), Kind::Range(_)) => "text",
+            // hstores are replicated as json objects
+            typ if is_hstore(typ) => "text",
+            // geometric values are replicated as json objects of their points
+            typ if is_geometric_type(typ) => "text",
+            // arrays and composites are replicated as json arrays and objects
+            typ if is_nested_type(typ) => "text",
+            // values of unsupported types are passed through as raw bytes
+            _ => "bytea",
         }
     }
 
     fn duckdb_column_spec(column_schema: &ColumnSchema, s: &mut String) {
-        s.push_str(&column_schema.name);
+        s.push_str(&quote_identifier(&column_schema.name));
         s.push(' ');
         let typ = Self::postgres_typ_to_duckdb_typ(&column_schema.typ);
         s.push_str(typ);
@@ -142,8 +167,9 @@ impl DuckDbClient {
     ) -> Result<(), duckdb::Error> {
         let columns_spec = Self::create_columns_spec(column_schemas);
         let query = format!(
-            "create table {}.{} {}",
-            table_name.schema, table_name.name, columns_spec
+            "create table {} {}",
+            table_name.as_quoted_identifier(),
+            columns_spec
         );
         self.conn.execute(&query, [])?;
         Ok(())
@@ -162,7 +188,7 @@ impl DuckDbClient {
         table_name: &TableName,
         table_row: &TableRow,
     ) -> Result<(), duckdb::Error> {
-        let table_name = format!("{}.{}", table_name.schema, table_name.name);
+        let table_name = table_name.as_quoted_identifier();
         let column_count = table_row.values.len();
         let query = Self::create_insert_row_query(&table_name, column_count);
         let mut stmt = self.conn.prepare(&query)?;
@@ -197,7 +223,7 @@ impl DuckDbClient {
     ) -> Result<(), duckdb::Error> {
         let table_name = &table_schema.table_name;
         let column_schemas = &table_schema.column_schemas;
-        let table_name = format!("{}.{}", table_name.schema, table_name.name);
+        let table_name = table_name.as_quoted_identifier();
         let query = Self::create_update_row_query(&table_name, column_schemas);
         let mut stmt = self.conn.prepare(&query)?;
         let non_identity_cells = column_schemas
@@ -224,7 +250,7 @@ impl DuckDbClient {
         let mut remove_comma = false;
         let non_identity_columns = column_schemas.iter().filter(|s| !s.identity);
         for column in non_identity_columns {
-            s.push_str(&column.name);
+            s.push_str(&quote_identifier(&column.name));
             s.push_str(" = ?,");
             remove_comma = true;
         }
@@ -244,7 +270,7 @@ impl DuckDbClient {
         let mut remove_and = false;
         let identity_columns = column_schemas.iter().filter(|s| s.identity);
         for column in identity_columns {
-            s.push_str(&column.name);
+            s.push_str(&quote_identifier(&column.name));
             s.push_str(" = ? and ");
             remove_and = true;
         }
@@ -264,7 +290,7 @@ impl DuckDbClient {
     ) -> Result<(), duckdb::Error> {
         let table_name = &table_schema.table_name;
         let column_schemas = &table_schema.column_schemas;
-        let table_name = format!("{}.{}", table_name.schema, table_name.name);
+        let table_name = table_name.as_quoted_identifier();
         let query = Self::create_delete_row_query(&table_name, column_schemas);
         let mut stmt = self.conn.prepare(&query)?;
         let identity_cells = column_schemas
@@ -332,7 +358,7 @@ impl DuckDbClient {
     }
 
     pub fn truncate_table(&self, table_name: &TableName) -> Result<(), duckdb::Error> {
-        let query = format!("delete from {}.{}", table_name.schema, table_name.name);
+        let query = format!("delete from {}", table_name.as_quoted_identifier());
         let mut stmt = self.conn.prepare(&query)?;
         stmt.execute([])?;
         Ok(())
@@ -362,6 +388,21 @@ impl ToSql for Cell {
             Cell::TimeStamp(t) => t.to_sql(),
             Cell::Null => Null.to_sql(),
             Cell::Bytes(b) => b.to_sql(),
+            Cell::Map(m) => {
+                let json = serde_json::to_string(m)
+                    .map_err(|e| duckdb::Error::ToSqlConversionFailure(Box::new(e)))?;
+                Ok(ToSqlOutput::from(json))
+            }
+            Cell::Geometry(g) => {
+                let json = serde_json::to_string(g)
+                    .map_err(|e| duckdb::Error::ToSqlConversionFailure(Box::new(e)))?;
+                Ok(ToSqlOutput::from(json))
+            }
+            Cell::Array(_) | Cell::Composite(_) => {
+                let json = serde_json::to_string(self)
+                    .map_err(|e| duckdb::Error::ToSqlConversionFailure(Box::new(e)))?;
+                Ok(ToSqlOutput::from(json))
+            }
         }
     }
 }
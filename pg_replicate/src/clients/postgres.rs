@@ -1,27 +1,75 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display, sync::Mutex, time::Duration};
 
 use thiserror::Error;
 use tokio_postgres::{
     binary_copy::BinaryCopyOutStream,
     config::ReplicationMode,
+    error::SqlState,
     replication::LogicalReplicationStream,
-    types::{Kind, PgLsn, Type},
-    Client as PostgresClient, Config, NoTls, SimpleQueryMessage,
+    types::{Field, Kind, PgLsn, Type},
+    Client as PostgresClient, Config, CopyOutStream, NoTls, SimpleQueryMessage,
 };
 use tracing::{info, warn};
 
 use crate::{
+    conversions::{base_type, is_supported_type, is_text_only_type, UnsupportedTypePolicy},
     escape::{quote_identifier, quote_literal},
-    table::{ColumnSchema, TableId, TableName, TableSchema},
+    pipeline::sources::postgres::TableCopyStream,
+    table::{ColumnSchema, ReplicaIdentity, TableId, TableName, TableSchema},
 };
 
+/// How often a slot which another connection is creating is checked until it can
+/// be used
+const SLOT_CREATION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct SlotInfo {
     pub confirmed_flush_lsn: PgLsn,
+    /// Oldest WAL position still retained for the slot
+    pub restart_lsn: PgLsn,
+}
+
+/// Result of a `CREATE_REPLICATION_SLOT` command
+#[derive(Debug, Clone)]
+pub struct CreatedSlot {
+    pub consistent_point: PgLsn,
+    /// Name of the snapshot exported by the slot creation, if any
+    pub snapshot_name: Option<String>,
+}
+
+/// The part of a table which a publication publishes
+pub struct PublicationTableFilter {
+    pub table_name: TableName,
+    /// Names of the published columns
+    pub column_names: Option<Vec<String>>,
+    /// Only rows for which this expression is true are published
+    pub row_filter: Option<String>,
+}
+
+/// A column whose type the converters don't support
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedColumn {
+    pub table_name: TableName,
+    pub column_name: String,
+    pub typ: Type,
+}
+
+impl Display for UnsupportedColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "column {} of table {} has unsupported type {}",
+            self.column_name, self.table_name, self.typ
+        )
+    }
 }
 
 /// A client for Postgres logical replication
 pub struct ReplicationClient {
     postgres_client: PostgresClient,
+    /// Types which aren't built into Postgres, keyed by their oid
+    types: Mutex<HashMap<u32, Type>>,
+    /// The last slot created by this client
+    created_slot: Mutex<Option<CreatedSlot>>,
 }
 
 #[derive(Debug, Error)]
@@ -49,10 +97,38 @@ pub enum ReplicationClientError {
 
     #[error("failed to create slot")]
     FailedToCreateSlot,
+
+    #[error("replication slot {0} is in use by another connection")]
+    SlotActive(String),
+
+    #[error("replication slot {0} doesn't exist")]
+    MissingSlot(String),
+
+    #[error("replication slot {0} has been invalidated because the WAL it needed was removed, a re-snapshot with a new slot is required")]
+    SlotInvalidated(String),
+
+    #[error("replication slot {0} has no confirmed lsn, it isn't a logical slot or is still being created")]
+    SlotNotConfirmed(String),
+
+    #[error("publication {0} doesn't exist")]
+    MissingPublication(String),
+
+    #[error("type with oid {0} doesn't exist")]
+    MissingType(u32),
+
+    #[error("server version is not a valid number")]
+    InvalidServerVersion,
+
+    #[error("publication column list is not a valid array of column names")]
+    InvalidColumnList,
+
+    #[error("invalid replica identity {0}")]
+    InvalidReplicaIdentity(String),
 }
 
 impl ReplicationClient {
-    /// Connect to a postgres database in logical replication mode without TLS
+    /// Connect to a postgres database in logical replication mode without TLS. A
+    /// `host` starting with `/` is the directory of the server's unix socket.
     pub async fn connect_no_tls(
         host: &str,
         port: u16,
@@ -60,20 +136,48 @@ impl ReplicationClient {
         username: &str,
         password: Option<String>,
     ) -> Result<ReplicationClient, ReplicationClientError> {
-        info!("connecting to postgres");
-
         let mut config = Config::new();
-        config
-            .host(host)
-            .port(port)
-            .dbname(database)
-            .user(username)
-            .replication_mode(ReplicationMode::Logical);
+        config.host(host).port(port).dbname(database).user(username);
 
         if let Some(password) = password {
             config.password(password);
         }
 
+        Self::connect_with_config(config).await
+    }
+
+    /// Parses a `postgres://` connection string, e.g.
+    /// `postgres://user@host:5432/db?connect_timeout=10`, into a config for
+    /// [ReplicationClient::connect_with_config]. A `replication` parameter is
+    /// ignored because the connection is always made in logical replication mode.
+    pub fn parse_connection_string(
+        connection_string: &str,
+    ) -> Result<Config, ReplicationClientError> {
+        let connection_string = match connection_string.split_once('?') {
+            Some((base, params)) => {
+                let params: Vec<&str> = params
+                    .split('&')
+                    .filter(|param| param.split('=').next() != Some("replication"))
+                    .collect();
+                if params.is_empty() {
+                    base.to_string()
+                } else {
+                    format!("{base}?{}", params.join("&"))
+                }
+            }
+            None => connection_string.to_string(),
+        };
+        Ok(connection_string.parse()?)
+    }
+
+    /// Connect to a postgres database with the parameters in `config` without TLS.
+    /// The connection is made in logical replication mode whatever `config` says.
+    pub async fn connect_with_config(
+        mut config: Config,
+    ) -> Result<ReplicationClient, ReplicationClientError> {
+        info!("connecting to postgres");
+
+        config.replication_mode(ReplicationMode::Logical);
         let (postgres_client, connection) = config.connect(NoTls).await?;
 
         tokio::spawn(async move {
@@ -85,7 +189,11 @@ impl ReplicationClient {
 
         info!("successfully connected to postgres");
 
-        Ok(ReplicationClient { postgres_client })
+        Ok(ReplicationClient {
+            postgres_client,
+            types: Mutex::new(HashMap::new()),
+            created_slot: Mutex::new(None),
+        })
     }
 
     /// Starts a read-only trasaction with repeatable read isolation level
@@ -96,6 +204,16 @@ impl ReplicationClient {
         Ok(())
     }
 
+    /// Asks the server to cancel the query which is running on this client's
+    /// connection, if any
+    pub async fn cancel_query(&self) -> Result<(), ReplicationClientError> {
+        self.postgres_client
+            .cancel_token()
+            .cancel_query(NoTls)
+            .await?;
+        Ok(())
+    }
+
     /// Commits a transaction
     pub async fn commit_txn(&self) -> Result<(), ReplicationClientError> {
         self.postgres_client.simple_query("commit;").await?;
@@ -107,22 +225,139 @@ impl ReplicationClient {
         Ok(())
     }
 
-    /// Returns a [BinaryCopyOutStream] for a table
+    /// Returns a [BinaryCopyOutStream] of the columns in `column_schemas` of a
+    /// table's rows which match `row_filter`
     pub async fn get_table_copy_stream(
         &self,
         table_name: &TableName,
-        column_types: &[Type],
+        column_schemas: &[ColumnSchema],
+        row_filter: Option<&str>,
     ) -> Result<BinaryCopyOutStream, ReplicationClientError> {
-        let copy_query = format!(
-            r#"COPY {} TO STDOUT WITH (FORMAT binary);"#,
-            table_name.as_quoted_identifier()
-        );
+        let copy_query = self
+            .table_copy_query(table_name, column_schemas, row_filter, "binary")
+            .await?;
 
+        // a domain's values are in the binary format of its base type
+        let column_types: Vec<Type> = column_schemas
+            .iter()
+            .map(|c| base_type(&c.typ).clone())
+            .collect();
         let stream = self.postgres_client.copy_out_simple(&copy_query).await?;
-        let row_stream = BinaryCopyOutStream::new(stream, column_types);
+        let row_stream = BinaryCopyOutStream::new(stream, &column_types);
         Ok(row_stream)
     }
 
+    /// Returns a [CopyOutStream] of a text format copy of the columns in `column_schemas`
+    /// of a table's rows which match `row_filter`. Each item of the stream is a single row
+    /// of tab separated values.
+    pub async fn get_table_text_copy_stream(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        row_filter: Option<&str>,
+    ) -> Result<CopyOutStream, ReplicationClientError> {
+        let copy_query = self
+            .table_copy_query(table_name, column_schemas, row_filter, "text")
+            .await?;
+
+        let stream = self.postgres_client.copy_out_simple(&copy_query).await?;
+        Ok(stream)
+    }
+
+    /// Checks the types of all columns of `table_schemas` up front, so that every
+    /// column which can't be converted is reported at once instead of the first
+    /// one failing a copy or the cdc stream midway
+    pub fn check_supported_types<'a>(
+        table_schemas: impl IntoIterator<Item = &'a TableSchema>,
+    ) -> Result<(), Vec<UnsupportedColumn>> {
+        let unsupported_columns: Vec<UnsupportedColumn> = table_schemas
+            .into_iter()
+            .flat_map(|table_schema| {
+                table_schema
+                    .column_schemas
+                    .iter()
+                    .filter(|c| !is_supported_type(&c.typ))
+                    .map(|c| UnsupportedColumn {
+                        table_name: table_schema.table_name.clone(),
+                        column_name: c.name.clone(),
+                        typ: c.typ.clone(),
+                    })
+            })
+            .collect();
+
+        if unsupported_columns.is_empty() {
+            Ok(())
+        } else {
+            Err(unsupported_columns)
+        }
+    }
+
+    /// Returns a [TableCopyStream] of the decoded rows of a table which match
+    /// `row_filter`, with a value for each column in `column_schemas`
+    pub async fn copy_table_rows(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        row_filter: Option<&str>,
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<TableCopyStream, ReplicationClientError> {
+        // values of unsupported types are passed on as raw bytes, which should
+        // be the same text representation the cdc stream produces for them
+        let binary = column_schemas
+            .iter()
+            .all(|c| is_supported_type(&c.typ) && !is_text_only_type(&c.typ));
+        let stream = if binary {
+            let stream = self
+                .get_table_copy_stream(table_name, column_schemas, row_filter)
+                .await?;
+            TableCopyStream::binary(stream, column_schemas, unsupported_type_policy)
+        } else {
+            info!("table {table_name} has columns of unsupported or text only types, copying it in text format");
+            let stream = self
+                .get_table_text_copy_stream(table_name, column_schemas, row_filter)
+                .await?;
+            TableCopyStream::text(stream, column_schemas, unsupported_type_policy)
+        };
+
+        Ok(stream)
+    }
+
+    /// A partitioned table, which is published when the publication uses
+    /// `publish_via_partition_root`, can't be copied directly so its rows are
+    /// copied from all partitions via a select. A select is also used to apply
+    /// a row filter and to read generated columns, which `COPY` rejects in its
+    /// column list.
+    async fn table_copy_query(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        row_filter: Option<&str>,
+        format: &str,
+    ) -> Result<String, ReplicationClientError> {
+        let columns = column_schemas
+            .iter()
+            .map(|c| quote_identifier(&c.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let use_select = row_filter.is_some()
+            || column_schemas.iter().any(|c| c.generated)
+            || self.is_partitioned_table(table_name).await?;
+        let table = if use_select {
+            let mut select = format!(
+                "SELECT {columns} FROM {}",
+                table_name.as_quoted_identifier()
+            );
+            if let Some(row_filter) = row_filter {
+                select.push_str(&format!(" WHERE {row_filter}"));
+            }
+            format!("({select})")
+        } else {
+            format!("{} ({columns})", table_name.as_quoted_identifier())
+        };
+
+        Ok(format!(r#"COPY {table} TO STDOUT WITH (FORMAT {format});"#))
+    }
+
     /// Returns a vector of columns of a table
     pub async fn get_column_schemas(
         &self,
@@ -133,10 +368,14 @@ impl ReplicationClient {
                 a.atttypid,
                 a.atttypmod,
                 a.attnotnull,
-                a.attnum = ANY(i.indkey) is_identity
+                a.attnum = ANY(i.indkey) is_identity,
+                pg_catalog.pg_get_expr(d.adbin, d.adrelid) default_expr,
+                a.attgenerated <> '' is_generated
            FROM pg_catalog.pg_attribute a
            LEFT JOIN pg_catalog.pg_index i
                 ON (i.indexrelid = pg_get_replica_identity_index({}))
+           LEFT JOIN pg_catalog.pg_attrdef d
+                ON (d.adrelid = a.attrelid AND d.adnum = a.attnum AND a.attgenerated = '')
           WHERE a.attnum > 0::pg_catalog.int2
             AND NOT a.attisdropped
             AND a.attrelid = {}
@@ -169,13 +408,7 @@ impl ReplicationClient {
                     .parse()
                     .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
 
-                //TODO: For now we assume all types are simple, fix it later
-                let typ = Type::from_oid(type_oid).unwrap_or(Type::new(
-                    format!("unnamed(oid: {type_oid})"),
-                    type_oid,
-                    Kind::Simple,
-                    "pg_catalog".to_string(),
-                ));
+                let typ = self.get_type(type_oid).await?;
 
                 let modifier = row
                     .try_get("atttypmod")?
@@ -202,12 +435,24 @@ impl ReplicationClient {
                         ))?
                         == "t";
 
+                let default = row.try_get("default_expr")?.map(|d| d.to_string());
+
+                let generated =
+                    row.try_get("is_generated")?
+                        .ok_or(ReplicationClientError::MissingColumn(
+                            "attgenerated".to_string(),
+                            "pg_attribute".to_string(),
+                        ))?
+                        == "t";
+
                 column_schemas.push(ColumnSchema {
                     name,
                     typ,
                     modifier,
                     nullable,
                     identity,
+                    default,
+                    generated,
                 })
             }
         }
@@ -215,6 +460,126 @@ impl ReplicationClient {
         Ok(column_schemas)
     }
 
+    /// Returns the type with oid `type_oid`. Types which aren't built into Postgres,
+    /// e.g. those created by extensions, are looked up in pg_type once and cached.
+    pub async fn get_type(&self, type_oid: u32) -> Result<Type, ReplicationClientError> {
+        if let Some(typ) = Type::from_oid(type_oid) {
+            return Ok(typ);
+        }
+
+        if let Some(typ) = self.cached_type(type_oid) {
+            return Ok(typ);
+        }
+
+        let typ = self.load_type(type_oid).await?;
+        self.types
+            .lock()
+            .expect("types mutex poisoned")
+            .insert(type_oid, typ.clone());
+
+        Ok(typ)
+    }
+
+    fn cached_type(&self, type_oid: u32) -> Option<Type> {
+        self.types
+            .lock()
+            .expect("types mutex poisoned")
+            .get(&type_oid)
+            .cloned()
+    }
+
+    /// Loads a type from pg_type, together with the element type of an array, the
+    /// base type of a domain and the field types of a composite.
+    async fn load_type(&self, type_oid: u32) -> Result<Type, ReplicationClientError> {
+        let type_query = format!(
+            "SELECT t.typname,
+                n.nspname,
+                t.typtype,
+                t.typcategory,
+                t.typelem,
+                t.typbasetype,
+                t.typrelid
+           FROM pg_catalog.pg_type t
+          INNER JOIN pg_catalog.pg_namespace n
+                ON (t.typnamespace = n.oid)
+          WHERE t.oid = {type_oid}"
+        );
+
+        for msg in self.postgres_client.simple_query(&type_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get_column = |column: &str| {
+                    row.try_get(column)?
+                        .ok_or(ReplicationClientError::MissingColumn(
+                            column.to_string(),
+                            "pg_type".to_string(),
+                        ))
+                };
+
+                let name = get_column("typname")?.to_string();
+                let schema = get_column("nspname")?.to_string();
+                let element_oid: u32 = get_column("typelem")?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+                let base_oid: u32 = get_column("typbasetype")?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+                let relation_oid: u32 = get_column("typrelid")?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+                let typtype = get_column("typtype")?.to_string();
+                let typcategory = get_column("typcategory")?.to_string();
+
+                // element, base and field types are loaded too, since arrays,
+                // domains and composites can nest each other
+                let kind = match (typtype.as_str(), typcategory.as_str()) {
+                    ("d", _) => Kind::Domain(Box::pin(self.get_type(base_oid)).await?),
+                    (_, "A") => Kind::Array(Box::pin(self.get_type(element_oid)).await?),
+                    ("c", _) => Kind::Composite(self.get_composite_fields(relation_oid).await?),
+                    _ => Kind::Simple,
+                };
+
+                return Ok(Type::new(name, type_oid, kind, schema));
+            }
+        }
+
+        Err(ReplicationClientError::MissingType(type_oid))
+    }
+
+    /// Returns the fields of a composite type from the attributes of its relation
+    async fn get_composite_fields(
+        &self,
+        relation_oid: u32,
+    ) -> Result<Vec<Field>, ReplicationClientError> {
+        let fields_query = format!(
+            "select attname, atttypid
+            from pg_catalog.pg_attribute
+            where attrelid = {relation_oid} and attnum > 0 and not attisdropped
+            order by attnum"
+        );
+
+        let mut fields = vec![];
+        for msg in self.postgres_client.simple_query(&fields_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get_column = |column: &str| {
+                    row.try_get(column)?
+                        .ok_or(ReplicationClientError::MissingColumn(
+                            column.to_string(),
+                            "pg_attribute".to_string(),
+                        ))
+                };
+
+                let name = get_column("attname")?.to_string();
+                let type_oid: u32 = get_column("atttypid")?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+                let typ = Box::pin(self.get_type(type_oid)).await?;
+                fields.push(Field::new(name, typ));
+            }
+        }
+
+        Ok(fields)
+    }
+
     pub async fn get_table_schemas(
         &self,
         table_names: &[TableName],
@@ -237,14 +602,107 @@ impl ReplicationClient {
             .get_table_id(&table_name)
             .await?
             .ok_or(ReplicationClientError::MissingTable(table_name.clone()))?;
-        let column_schemas = self.get_column_schemas(table_id).await?;
+        let mut column_schemas = self.get_column_schemas(table_id).await?;
+        let primary_key = self.get_primary_key_column_names(table_id).await?;
+        let replica_identity = self.get_replica_identity(&table_name).await?;
+        // a full replica identity has no index, but the old tuple carries every column
+        if replica_identity == ReplicaIdentity::Full {
+            for column_schema in &mut column_schemas {
+                column_schema.identity = true;
+            }
+        }
         Ok(TableSchema {
             table_name,
             table_id,
             column_schemas,
+            primary_key,
+            replica_identity,
         })
     }
 
+    /// Returns how Postgres identifies the old row in updates and deletes of the table
+    pub async fn get_replica_identity(
+        &self,
+        table: &TableName,
+    ) -> Result<ReplicaIdentity, ReplicationClientError> {
+        let quoted_schema = quote_literal(&table.schema);
+        let quoted_name = quote_literal(&table.name);
+
+        let replica_identity_query = format!(
+            "SELECT c.relreplident
+          FROM pg_catalog.pg_class c
+          INNER JOIN pg_catalog.pg_namespace n
+                ON (c.relnamespace = n.oid)
+         WHERE n.nspname = {}
+           AND c.relname = {};",
+            quoted_schema, quoted_name
+        );
+
+        for msg in self
+            .postgres_client
+            .simple_query(&replica_identity_query)
+            .await?
+        {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let replica_identity = row.get(0).ok_or(ReplicationClientError::MissingColumn(
+                    "relreplident".to_string(),
+                    "pg_class".to_string(),
+                ))?;
+                return match replica_identity {
+                    "d" => Ok(ReplicaIdentity::Default),
+                    "i" => Ok(ReplicaIdentity::Index),
+                    "f" => Ok(ReplicaIdentity::Full),
+                    "n" => Ok(ReplicaIdentity::Nothing),
+                    other => Err(ReplicationClientError::InvalidReplicaIdentity(
+                        other.to_string(),
+                    )),
+                };
+            }
+        }
+
+        Err(ReplicationClientError::MissingTable(table.clone()))
+    }
+
+    /// Returns the names of the columns of a table's primary key in key order.
+    /// The vector is empty if the table has no primary key
+    pub async fn get_primary_key_column_names(
+        &self,
+        table_id: TableId,
+    ) -> Result<Vec<String>, ReplicationClientError> {
+        let primary_key_query = format!(
+            "SELECT a.attname
+           FROM pg_catalog.pg_index i
+          CROSS JOIN LATERAL unnest(i.indkey::pg_catalog.int2[]) WITH ORDINALITY AS k(attnum, ord)
+           JOIN pg_catalog.pg_attribute a
+                ON (a.attrelid = i.indrelid AND a.attnum = k.attnum)
+          WHERE i.indrelid = {}
+            AND i.indisprimary
+          ORDER BY k.ord",
+            table_id
+        );
+
+        let mut column_names = vec![];
+
+        for message in self
+            .postgres_client
+            .simple_query(&primary_key_query)
+            .await?
+        {
+            if let SimpleQueryMessage::Row(row) = message {
+                let name = row
+                    .try_get("attname")?
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "attname".to_string(),
+                        "pg_attribute".to_string(),
+                    ))?
+                    .to_string();
+                column_names.push(name);
+            }
+        }
+
+        Ok(column_names)
+    }
+
     /// Returns the table id (called relation id in Postgres) of a table
     pub async fn get_table_id(
         &self,
@@ -280,75 +738,429 @@ impl ReplicationClient {
         Ok(None)
     }
 
+    /// Returns true if the table is the root of a declaratively partitioned table
+    pub async fn is_partitioned_table(
+        &self,
+        table: &TableName,
+    ) -> Result<bool, ReplicationClientError> {
+        let quoted_schema = quote_literal(&table.schema);
+        let quoted_name = quote_literal(&table.name);
+
+        let relkind_query = format!(
+            "SELECT c.relkind
+          FROM pg_catalog.pg_class c
+          INNER JOIN pg_catalog.pg_namespace n
+                ON (c.relnamespace = n.oid)
+         WHERE n.nspname = {}
+           AND c.relname = {};",
+            quoted_schema, quoted_name
+        );
+
+        for msg in self.postgres_client.simple_query(&relkind_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let relkind = row.get(0).ok_or(ReplicationClientError::MissingColumn(
+                    "relkind".to_string(),
+                    "pg_class".to_string(),
+                ))?;
+                return Ok(relkind == "p");
+            }
+        }
+
+        Err(ReplicationClientError::MissingTable(table.clone()))
+    }
+
     /// Returns the slot info of an existing slot. The slot info currently only has the
-    /// confirmed_flush_lsn column of the pg_replication_slots table.
-    async fn get_slot(&self, slot_name: &str) -> Result<Option<SlotInfo>, ReplicationClientError> {
+    /// confirmed_flush_lsn and restart_lsn columns of the pg_replication_slots table.
+    /// A slot without them can't be streamed from and fails with
+    /// [ReplicationClientError::SlotNotConfirmed] or, once Postgres removed the WAL
+    /// it needed, [ReplicationClientError::SlotInvalidated].
+    pub async fn get_slot(
+        &self,
+        slot_name: &str,
+    ) -> Result<Option<SlotInfo>, ReplicationClientError> {
         let query = format!(
-            r#"select confirmed_flush_lsn from pg_replication_slots where slot_name = {};"#,
+            r#"select confirmed_flush_lsn, restart_lsn from pg_replication_slots where slot_name = {};"#,
             quote_literal(slot_name)
         );
 
         let query_result = self.postgres_client.simple_query(&query).await?;
 
-        if let SimpleQueryMessage::Row(row) = &query_result[0] {
-            let confirmed_flush_lsn = row
-                .get("confirmed_flush_lsn")
-                .ok_or(ReplicationClientError::MissingColumn(
-                    "confirmed_flush_lsn".to_string(),
-                    "pg_replication_slots".to_string(),
-                ))?
-                .parse()
-                .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
-
-            Ok(Some(SlotInfo {
-                confirmed_flush_lsn,
-            }))
-        } else {
-            Ok(None)
+        for msg in query_result {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let confirmed_flush_lsn = row
+                    .get("confirmed_flush_lsn")
+                    .ok_or_else(|| ReplicationClientError::SlotNotConfirmed(slot_name.to_string()))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+
+                // an invalidated slot keeps its confirmed_flush_lsn
+                let restart_lsn = row
+                    .get("restart_lsn")
+                    .ok_or_else(|| ReplicationClientError::SlotInvalidated(slot_name.to_string()))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+
+                return Ok(Some(SlotInfo {
+                    confirmed_flush_lsn,
+                    restart_lsn,
+                }));
+            }
         }
+
+        Ok(None)
+    }
+
+    /// Returns the position up to which the consumer of the slot has confirmed
+    /// receiving changes
+    pub async fn confirmed_flush_lsn(
+        &self,
+        slot_name: &str,
+    ) -> Result<PgLsn, ReplicationClientError> {
+        self.get_slot(slot_name)
+            .await?
+            .map(|slot_info| slot_info.confirmed_flush_lsn)
+            .ok_or(ReplicationClientError::MissingSlot(slot_name.to_string()))
+    }
+
+    /// Returns the commit lsn of the first transaction in the slot's pending changes
+    /// which committed at or after `timestamp`, e.g. `2024-05-01T15:00:00Z`, or None
+    /// if there is none. A cdc stream started at it skips the transactions which
+    /// committed before.
+    ///
+    /// The commit timestamps are read from the begin messages of the changes, which
+    /// are decoded with pgoutput without consuming them. This takes a while if the
+    /// slot retains a lot of WAL, and transactions the slot has already confirmed
+    /// can't be found.
+    pub async fn commit_lsn_since(
+        &self,
+        slot_name: &str,
+        publication: &str,
+        timestamp: &str,
+    ) -> Result<Option<PgLsn>, ReplicationClientError> {
+        // a begin message is the byte 'B' followed by the commit lsn and the commit
+        // timestamp, in microseconds since 2000-01-01, as 8 byte integers
+        let query = format!(
+            r#"select upper(encode(substr(data, 2, 4), 'hex')) || '/' || upper(encode(substr(data, 6, 4), 'hex')) as commit_lsn
+            from pg_logical_slot_peek_binary_changes({}, null, null, 'proto_version', '1', 'publication_names', {})
+            where get_byte(data, 0) = ascii('B')
+                and ('x' || encode(substr(data, 10, 8), 'hex'))::bit(64)::bigint
+                    >= (extract(epoch from {}::timestamptz) - 946684800) * 1000000
+            order by lsn
+            limit 1;"#,
+            quote_literal(slot_name),
+            quote_literal(&quote_identifier(publication)),
+            quote_literal(timestamp)
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let commit_lsn = row
+                    .get("commit_lsn")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "commit_lsn".to_string(),
+                        "pg_logical_slot_peek_binary_changes".to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+                return Ok(Some(commit_lsn));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the current write-ahead log write location of the server. Together
+    /// with [ReplicationClient::confirmed_flush_lsn] it gives the replication lag of a slot.
+    pub async fn current_wal_lsn(&self) -> Result<PgLsn, ReplicationClientError> {
+        self.query_wal_lsn("pg_current_wal_lsn()").await
+    }
+
+    /// Returns the current write-ahead log flush location of the server. WAL senders
+    /// only stream WAL up to this location, so a cdc stream has caught up with the
+    /// server once it has reached it.
+    ///
+    /// Like any other query it can't be run on a connection which is streaming
+    /// changes, use a separate client to call it while a cdc stream is open.
+    pub async fn current_wal_flush_lsn(&self) -> Result<PgLsn, ReplicationClientError> {
+        self.query_wal_lsn("pg_current_wal_flush_lsn()").await
+    }
+
+    /// Returns the position up to which a standby server has replayed the write-ahead
+    /// log, so that new snapshots include all changes before it. On a primary server
+    /// the current write-ahead log location is returned instead.
+    pub async fn replayed_wal_lsn(&self) -> Result<PgLsn, ReplicationClientError> {
+        self.query_wal_lsn(
+            "case when pg_is_in_recovery() then pg_last_wal_replay_lsn() else pg_current_wal_lsn() end",
+        )
+        .await
+    }
+
+    async fn query_wal_lsn(&self, expression: &str) -> Result<PgLsn, ReplicationClientError> {
+        let query = format!("select {expression} as lsn;");
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                return row
+                    .get("lsn")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "lsn".to_string(),
+                        expression.to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn);
+            }
+        }
+
+        Err(ReplicationClientError::MissingColumn(
+            "lsn".to_string(),
+            expression.to_string(),
+        ))
     }
 
     /// Creates a logical replication slot. This will only succeed if the postgres connection
     /// is in logical replication mode. Otherwise it will fail with the following error:
     /// `syntax error at or near "CREATE_REPLICATION_SLOT"``
     ///
+    /// A temporary slot is dropped by Postgres when the connection which created it closes.
+    ///
+    /// The slot is created with `USE_SNAPSHOT`, so the current transaction switches to the
+    /// slot's snapshot. Table copies made later in the same transaction see exactly the
+    /// data as of the slot's consistent_point, which is where the cdc stream starts.
+    ///
     /// Returns the consistent_point column as slot info.
-    async fn create_slot(&self, slot_name: &str) -> Result<SlotInfo, ReplicationClientError> {
+    async fn create_slot(
+        &self,
+        slot_name: &str,
+        temporary: bool,
+    ) -> Result<SlotInfo, ReplicationClientError> {
         let query = format!(
-            r#"CREATE_REPLICATION_SLOT {} LOGICAL pgoutput USE_SNAPSHOT"#,
-            quote_identifier(slot_name)
+            r#"CREATE_REPLICATION_SLOT {}{} LOGICAL pgoutput USE_SNAPSHOT"#,
+            quote_identifier(slot_name),
+            if temporary { " TEMPORARY" } else { "" }
         );
-        let slot_query = self.postgres_client.simple_query(&query).await?;
-        if let SimpleQueryMessage::Row(row) = &slot_query[0] {
-            let consistent_point: PgLsn = row
-                .get("consistent_point")
-                .ok_or(ReplicationClientError::MissingColumn(
-                    "consistent_point".to_string(),
-                    "create_replication_slot".to_string(),
-                ))?
-                .parse()
-                .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
-            Ok(SlotInfo {
-                confirmed_flush_lsn: consistent_point,
-            })
-        } else {
-            Err(ReplicationClientError::FailedToCreateSlot)
+        let created_slot = self.execute_create_slot(&query).await?;
+        Ok(SlotInfo {
+            confirmed_flush_lsn: created_slot.consistent_point,
+            restart_lsn: created_slot.consistent_point,
+        })
+    }
+
+    /// Creates a logical replication slot using the output `plugin` unless a slot
+    /// named `slot_name` already exists. Unlike [ReplicationClient::get_or_create_slot]
+    /// this doesn't need to be called inside a transaction, and the snapshot created
+    /// along with the slot is exported so that it can be imported by other sessions.
+    ///
+    /// Returns `None` if the slot already existed, including when another connection
+    /// created it between checking for it and creating it.
+    pub async fn create_slot_if_missing(
+        &self,
+        slot_name: &str,
+        plugin: &str,
+    ) -> Result<Option<CreatedSlot>, ReplicationClientError> {
+        match self.get_slot(slot_name).await {
+            // a slot without a confirmed lsn may be being created by another connection
+            Ok(Some(_)) | Err(ReplicationClientError::SlotNotConfirmed(_)) => {
+                info!("replication slot {slot_name} already exists");
+                return Ok(None);
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+
+        let query = format!(
+            r#"CREATE_REPLICATION_SLOT {} LOGICAL {}"#,
+            quote_identifier(slot_name),
+            quote_identifier(plugin)
+        );
+        match self.execute_create_slot(&query).await {
+            Ok(created_slot) => {
+                info!("created replication slot {slot_name}");
+                Ok(Some(created_slot))
+            }
+            Err(ReplicationClientError::TokioPostgresError(e))
+                if e.code() == Some(&SqlState::DUPLICATE_OBJECT) =>
+            {
+                info!("replication slot {slot_name} already exists");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn execute_create_slot(
+        &self,
+        query: &str,
+    ) -> Result<CreatedSlot, ReplicationClientError> {
+        let slot_query = self.postgres_client.simple_query(query).await?;
+        for msg in slot_query {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let consistent_point: PgLsn = row
+                    .get("consistent_point")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "consistent_point".to_string(),
+                        "create_replication_slot".to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+                let snapshot_name = row.get("snapshot_name").map(|name| name.to_string());
+                let created_slot = CreatedSlot {
+                    consistent_point,
+                    snapshot_name,
+                };
+                *self
+                    .created_slot
+                    .lock()
+                    .expect("created slot mutex poisoned") = Some(created_slot.clone());
+                return Ok(created_slot);
+            }
         }
+
+        Err(ReplicationClientError::FailedToCreateSlot)
+    }
+
+    /// Returns the name of the snapshot exported when this client last created a
+    /// slot. Other sessions can import it with `SET TRANSACTION SNAPSHOT` to copy
+    /// tables as of the slot's consistent point, as long as the transaction which
+    /// exported it is open. Slots created for [ReplicationClient::get_or_create_slot]
+    /// use their snapshot instead of exporting it, so there's no name for them.
+    pub fn snapshot_name(&self) -> Option<String> {
+        self.created_slot
+            .lock()
+            .expect("created slot mutex poisoned")
+            .as_ref()
+            .and_then(|created_slot| created_slot.snapshot_name.clone())
+    }
+
+    /// Returns the lsn at which the slot this client last created became consistent,
+    /// which is where its cdc stream starts
+    pub fn consistent_point(&self) -> Option<PgLsn> {
+        self.created_slot
+            .lock()
+            .expect("created slot mutex poisoned")
+            .as_ref()
+            .map(|created_slot| created_slot.consistent_point)
     }
 
     /// Either return the slot info of an existing slot or creates a new
-    /// slot and returns its slot info.
+    /// slot and returns its slot info. `temporary` is only used when a new
+    /// slot is created. A slot which another connection creates at the same time
+    /// is waited for and returned like an existing slot.
     pub async fn get_or_create_slot(
         &self,
         slot_name: &str,
+        temporary: bool,
     ) -> Result<SlotInfo, ReplicationClientError> {
-        if let Some(slot_info) = self.get_slot(slot_name).await? {
-            Ok(slot_info)
-        } else {
-            self.rollback_txn().await?;
-            self.begin_readonly_transaction().await?;
-            Ok(self.create_slot(slot_name).await?)
+        match self.get_slot(slot_name).await {
+            Ok(Some(slot_info)) => return Ok(slot_info),
+            Ok(None) => {}
+            Err(ReplicationClientError::SlotNotConfirmed(_)) => {
+                return self.wait_for_created_slot(slot_name).await
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.rollback_txn().await?;
+        self.begin_readonly_transaction().await?;
+        match self.create_slot(slot_name, temporary).await {
+            Ok(slot_info) => Ok(slot_info),
+            Err(ReplicationClientError::TokioPostgresError(e))
+                if e.code() == Some(&SqlState::DUPLICATE_OBJECT) =>
+            {
+                info!("replication slot {slot_name} was created by another connection");
+                // the failed command aborted the transaction
+                self.rollback_txn().await?;
+                self.begin_readonly_transaction().await?;
+                self.wait_for_created_slot(slot_name).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Waits until the slot which another connection is creating has a confirmed
+    /// lsn, which it gets once it is consistent, and returns its slot info
+    async fn wait_for_created_slot(
+        &self,
+        slot_name: &str,
+    ) -> Result<SlotInfo, ReplicationClientError> {
+        loop {
+            match self.get_slot(slot_name).await {
+                Ok(Some(slot_info)) => return Ok(slot_info),
+                // the creating connection failed and the slot was dropped
+                Ok(None) => return Err(ReplicationClientError::MissingSlot(slot_name.to_string())),
+                Err(ReplicationClientError::SlotNotConfirmed(_)) => {
+                    tokio::time::sleep(SLOT_CREATION_POLL_INTERVAL).await
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Drops a replication slot. If `wait` is true and the slot is in use by another
+    /// connection, waits until that connection releases the slot instead of failing
+    /// with a [ReplicationClientError::SlotActive] error.
+    pub async fn drop_slot(
+        &self,
+        slot_name: &str,
+        wait: bool,
+    ) -> Result<(), ReplicationClientError> {
+        let query = format!(
+            r#"DROP_REPLICATION_SLOT {}{}"#,
+            quote_identifier(slot_name),
+            if wait { " WAIT" } else { "" }
+        );
+
+        match self.postgres_client.simple_query(&query).await {
+            Ok(_) => {
+                info!("dropped replication slot {slot_name}");
+                Ok(())
+            }
+            Err(e) if e.code() == Some(&SqlState::OBJECT_IN_USE) => {
+                Err(ReplicationClientError::SlotActive(slot_name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the names of all publications in the database
+    pub async fn list_publications(&self) -> Result<Vec<String>, ReplicationClientError> {
+        let query = "select pubname from pg_publication order by pubname;";
+
+        let mut publications = vec![];
+        for msg in self.postgres_client.simple_query(query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let pubname = row
+                    .get(0)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "pubname".to_string(),
+                        "pg_publication".to_string(),
+                    ))?
+                    .to_string();
+                publications.push(pubname);
+            }
+        }
+
+        Ok(publications)
+    }
+
+    /// Returns true if a publication named `publication` exists
+    pub async fn publication_exists(
+        &self,
+        publication: &str,
+    ) -> Result<bool, ReplicationClientError> {
+        let publication_exists_query = format!(
+            "select 1 as exists from pg_publication where pubname = {};",
+            quote_literal(publication)
+        );
+        for msg in self
+            .postgres_client
+            .simple_query(&publication_exists_query)
+            .await?
+        {
+            if let SimpleQueryMessage::Row(_) = msg {
+                return Ok(true);
+            }
         }
+        Ok(false)
     }
 
     /// Returns all table names in a publication
@@ -391,15 +1203,134 @@ impl ReplicationClient {
         Ok(table_names)
     }
 
+    /// Returns the ids of all tables in a publication
+    pub async fn get_publication_table_ids(
+        &self,
+        publication: &str,
+    ) -> Result<Vec<TableId>, ReplicationClientError> {
+        let publication_query = format!(
+            "select c.oid
+            from pg_publication_tables pt
+            join pg_namespace n on n.nspname = pt.schemaname
+            join pg_class c on c.relnamespace = n.oid and c.relname = pt.tablename
+            where pt.pubname = {};",
+            quote_literal(publication)
+        );
+
+        let mut table_ids = vec![];
+        for msg in self
+            .postgres_client
+            .simple_query(&publication_query)
+            .await?
+        {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let table_id = row
+                    .get(0)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "oid".to_string(),
+                        "pg_class".to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+
+                table_ids.push(table_id)
+            }
+        }
+
+        Ok(table_ids)
+    }
+
+    /// Returns the column lists and row filters of the tables in a publication.
+    /// Both were added in Postgres 15, so nothing is returned for older servers,
+    /// which always publish all columns and rows.
+    pub async fn get_publication_table_filters(
+        &self,
+        publication: &str,
+    ) -> Result<Vec<PublicationTableFilter>, ReplicationClientError> {
+        if self.server_version_num().await? < 150000 {
+            return Ok(vec![]);
+        }
+
+        let filters_query = format!(
+            "select schemaname, tablename, array_to_json(attnames) attnames, rowfilter
+            from pg_publication_tables
+            where pubname = {};",
+            quote_literal(publication)
+        );
+
+        let mut filters = vec![];
+        for msg in self.postgres_client.simple_query(&filters_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let schema = row
+                    .try_get("schemaname")?
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "schemaname".to_string(),
+                        "pg_publication_tables".to_string(),
+                    ))?
+                    .to_string();
+
+                let name = row
+                    .try_get("tablename")?
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "tablename".to_string(),
+                        "pg_publication_tables".to_string(),
+                    ))?
+                    .to_string();
+
+                let column_names = row
+                    .try_get("attnames")?
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .map_err(|_| ReplicationClientError::InvalidColumnList)?;
+
+                let row_filter = row.try_get("rowfilter")?.map(|f| f.to_string());
+
+                filters.push(PublicationTableFilter {
+                    table_name: TableName { schema, name },
+                    column_names,
+                    row_filter,
+                });
+            }
+        }
+
+        Ok(filters)
+    }
+
+    async fn server_version_num(&self) -> Result<u32, ReplicationClientError> {
+        for msg in self
+            .postgres_client
+            .simple_query("show server_version_num;")
+            .await?
+        {
+            if let SimpleQueryMessage::Row(row) = msg {
+                return row
+                    .get("server_version_num")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "server_version_num".to_string(),
+                        "show server_version_num".to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidServerVersion);
+            }
+        }
+
+        Err(ReplicationClientError::MissingColumn(
+            "server_version_num".to_string(),
+            "show server_version_num".to_string(),
+        ))
+    }
+
     pub async fn get_logical_replication_stream(
         &self,
         publication: &str,
         slot_name: &str,
         start_lsn: PgLsn,
     ) -> Result<LogicalReplicationStream, ReplicationClientError> {
+        // pgoutput splits publication_names like a list of identifiers, which
+        // folds unquoted names to lower case
         let options = format!(
             "(\"proto_version\" '1', \"publication_names\" {})",
-            quote_literal(publication)
+            quote_literal(&quote_identifier(publication))
         );
 
         let query = format!(
@@ -409,10 +1340,17 @@ impl ReplicationClient {
             options
         );
 
-        let copy_stream = self
+        let copy_stream = match self
             .postgres_client
             .copy_both_simple::<bytes::Bytes>(&query)
-            .await?;
+            .await
+        {
+            Ok(copy_stream) => copy_stream,
+            Err(e) if e.code() == Some(&SqlState::OBJECT_IN_USE) => {
+                return Err(ReplicationClientError::SlotActive(slot_name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         let stream = LogicalReplicationStream::new(copy_stream);
 
@@ -1,49 +1,38 @@
-use std::{
-    collections::HashMap,
-    num::ParseIntError,
-    str::{from_utf8, ParseBoolError, Utf8Error},
-};
+use std::collections::HashMap;
 
-use chrono::NaiveDateTime;
 use postgres_protocol::message::backend::{
-    BeginBody, CommitBody, DeleteBody, InsertBody, LogicalReplicationMessage, RelationBody,
-    ReplicationMessage, TupleData, UpdateBody,
+    BeginBody, CommitBody, DeleteBody, InsertBody, LogicalReplicationMessage, OriginBody,
+    RelationBody, ReplicaIdentity as RelationReplicaIdentity, ReplicationMessage, TupleData,
+    TypeBody, UpdateBody,
 };
 use thiserror::Error;
-use tokio_postgres::types::Type;
+use tokio_postgres::types::{Kind, PgLsn, Type};
+use tracing::info;
 
 use crate::{
     pipeline::batching::BatchBoundary,
-    table::{ColumnSchema, TableId, TableSchema},
+    table::{ColumnSchema, ReplicaIdentity, TableId, TableName, TableSchema},
 };
 
-use super::table_row::{Cell, TableRow};
+use super::{
+    table_row::{Cell, TableRow},
+    text::{FromTextError, TextFormatConverter},
+    UnsupportedTypePolicy,
+};
 
 #[derive(Debug, Error)]
 pub enum CdcEventConversionError {
-    #[error("message not supported")]
-    MessageNotSupported,
+    #[error("{0} message not supported")]
+    MessageNotSupported(&'static str),
 
-    #[error("unknown replication message")]
-    UnknownReplicationMessage,
+    #[error("unknown replication message {0}")]
+    UnknownReplicationMessage(String),
 
     #[error("unchanged toast not yet supported")]
     UnchangedToastNotSupported,
 
-    #[error("invalid string value")]
-    InvalidStr(#[from] Utf8Error),
-
-    #[error("invalid bool value")]
-    InvalidBool(#[from] ParseBoolError),
-
-    #[error("invalid int value")]
-    InvalidInt(#[from] ParseIntError),
-
-    #[error("invalid timestamp value")]
-    InvalidTimestamp(#[from] chrono::ParseError),
-
-    #[error("unsupported type: {0}")]
-    UnsupportedType(String),
+    #[error("invalid value: {0}")]
+    FromText(#[from] FromTextError),
 
     #[error("out of range timestamp")]
     OutOfRangeTimestamp,
@@ -62,12 +51,19 @@ pub enum CdcEventConversionError {
 
     #[error("invalid column name: {0}")]
     InvalidColumnName(String),
+
+    #[error("tuple has {0} values but the table has {1} columns")]
+    ColumnCountMismatch(usize, usize),
 }
 
 pub struct CdcEventConverter;
 
 impl CdcEventConverter {
-    fn from_tuple_data(typ: &Type, val: &TupleData) -> Result<Cell, CdcEventConversionError> {
+    fn from_tuple_data(
+        typ: &Type,
+        val: &TupleData,
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<Cell, CdcEventConversionError> {
         let bytes = match val {
             TupleData::Null => {
                 return Ok(Cell::Null);
@@ -77,50 +73,41 @@ impl CdcEventConverter {
             }
             TupleData::Text(bytes) => &bytes[..],
         };
-        match *typ {
-            Type::BOOL => {
-                let val = from_utf8(bytes)?;
-                let val: bool = val.parse()?;
-                Ok(Cell::Bool(val))
-            }
-            // Type::BYTEA => Ok(Value::Bytes(bytes.to_vec())),
-            Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => {
-                let val = from_utf8(bytes)?;
-                Ok(Cell::String(val.to_string()))
-            }
-            Type::INT2 => {
-                let val = from_utf8(bytes)?;
-                let val: i16 = val.parse()?;
-                Ok(Cell::I16(val))
-            }
-            Type::INT4 => {
-                let val = from_utf8(bytes)?;
-                let val: i32 = val.parse()?;
-                Ok(Cell::I32(val))
-            }
-            Type::INT8 => {
-                let val = from_utf8(bytes)?;
-                let val: i64 = val.parse()?;
-                Ok(Cell::I64(val))
-            }
-            Type::TIMESTAMP => {
-                let val = from_utf8(bytes)?;
-                let val = NaiveDateTime::parse_from_str(val, "%Y-%m-%d %H:%M:%S%.f")?;
-                let val = val.format("%Y-%m-%d %H:%M:%S%.f").to_string();
-                Ok(Cell::TimeStamp(val))
-            }
-            _ => Ok(Cell::Bytes(bytes.to_vec())),
-        }
+        Ok(TextFormatConverter::try_from_bytes(
+            typ,
+            bytes,
+            unsupported_type_policy,
+        )?)
     }
 
     fn from_tuple_data_slice(
         column_schemas: &[ColumnSchema],
         tuple_data: &[TupleData],
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<TableRow, CdcEventConversionError> {
+        // generated columns are only in the tuple if the server publishes them
+        let includes_generated = tuple_data.len() == column_schemas.len();
+        let published_columns = column_schemas.iter().filter(|c| !c.generated).count();
+        if !includes_generated && tuple_data.len() != published_columns {
+            return Err(CdcEventConversionError::ColumnCountMismatch(
+                tuple_data.len(),
+                column_schemas.len(),
+            ));
+        }
+
         let mut values = Vec::with_capacity(column_schemas.len());
+        let mut tuple_data = tuple_data.iter();
 
-        for (i, column_schema) in column_schemas.iter().enumerate() {
-            let val = Self::from_tuple_data(&column_schema.typ, &tuple_data[i])?;
+        for column_schema in column_schemas {
+            if column_schema.generated && !includes_generated {
+                values.push(Cell::Null);
+                continue;
+            }
+            let tuple_data = tuple_data
+                .next()
+                .expect("tuple data length was checked against the column count");
+            let val =
+                Self::from_tuple_data(&column_schema.typ, tuple_data, unsupported_type_policy)?;
             values.push(val);
         }
 
@@ -131,8 +118,13 @@ impl CdcEventConverter {
         table_id: TableId,
         column_schemas: &[ColumnSchema],
         insert_body: InsertBody,
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<CdcEvent, CdcEventConversionError> {
-        let row = Self::from_tuple_data_slice(column_schemas, insert_body.tuple().tuple_data())?;
+        let row = Self::from_tuple_data_slice(
+            column_schemas,
+            insert_body.tuple().tuple_data(),
+            unsupported_type_policy,
+        )?;
 
         Ok(CdcEvent::Insert((table_id, row)))
     }
@@ -142,9 +134,13 @@ impl CdcEventConverter {
         table_id: TableId,
         column_schemas: &[ColumnSchema],
         update_body: UpdateBody,
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<CdcEvent, CdcEventConversionError> {
-        let row =
-            Self::from_tuple_data_slice(column_schemas, update_body.new_tuple().tuple_data())?;
+        let row = Self::from_tuple_data_slice(
+            column_schemas,
+            update_body.new_tuple().tuple_data(),
+            unsupported_type_policy,
+        )?;
 
         Ok(CdcEvent::Update((table_id, row)))
     }
@@ -153,34 +149,141 @@ impl CdcEventConverter {
         table_id: TableId,
         column_schemas: &[ColumnSchema],
         delete_body: DeleteBody,
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<CdcEvent, CdcEventConversionError> {
         let tuple = delete_body
             .key_tuple()
             .or(delete_body.old_tuple())
             .ok_or(CdcEventConversionError::MissingTupleInDeleteBody)?;
 
-        let row = Self::from_tuple_data_slice(column_schemas, tuple.tuple_data())?;
+        let row = Self::from_tuple_data_slice(
+            column_schemas,
+            tuple.tuple_data(),
+            unsupported_type_policy,
+        )?;
 
         Ok(CdcEvent::Delete((table_id, row)))
     }
 
+    /// Postgres sends a relation message before the first change to a table in a
+    /// session and again after the table's columns change, e.g. by an `alter table`,
+    /// or after it is renamed. The cached schema of the table is replaced with the
+    /// name, columns and replica identity in the message so that later tuples are
+    /// decoded with the new column list. The order of the columns in the message is
+    /// the order of the values in the tuples, which can differ from the catalog's
+    /// order, e.g. after a dropped column is added again.
+    fn update_table_schema(
+        relation_body: &RelationBody,
+        table_schemas: &mut HashMap<TableId, TableSchema>,
+    ) -> Result<(), CdcEventConversionError> {
+        let Some(table_schema) = table_schemas.get_mut(&relation_body.rel_id()) else {
+            return Ok(());
+        };
+
+        let table_name = TableName {
+            schema: relation_body
+                .namespace()
+                .map_err(|e| CdcEventConversionError::InvalidNamespace(e.to_string()))?
+                .to_string(),
+            name: relation_body
+                .name()
+                .map_err(|e| CdcEventConversionError::InvalidRelationName(e.to_string()))?
+                .to_string(),
+        };
+        // the table id stays the same, so sinks which key their tables by it keep
+        // writing to the destination table created under the old name
+        if table_name != table_schema.table_name {
+            info!(
+                "table {} was renamed to {table_name}",
+                table_schema.table_name
+            );
+            table_schema.table_name = table_name;
+        }
+
+        let mut column_schemas = Vec::with_capacity(relation_body.columns().len());
+        for column in relation_body.columns() {
+            let name = column
+                .name()
+                .map_err(|e| CdcEventConversionError::InvalidColumnName(e.to_string()))?
+                .to_string();
+
+            // relation messages don't carry nullability or defaults, so they are kept
+            // from the previous schema and new columns are assumed to be nullable
+            let previous_column = table_schema.column_schemas.iter().find(|c| c.name == name);
+            let nullable = previous_column.map(|c| c.nullable).unwrap_or(true);
+            let default = previous_column.and_then(|c| c.default.clone());
+            let generated = previous_column.is_some_and(|c| c.generated);
+
+            // reuse the type loaded with the table's schema, which also resolves
+            // types that aren't built into Postgres
+            let type_oid = column.type_id() as u32;
+            let cached_type = table_schema
+                .column_schemas
+                .iter()
+                .find(|c| c.typ.oid() == type_oid)
+                .map(|c| c.typ.clone());
+            let typ = cached_type
+                .or_else(|| Type::from_oid(type_oid))
+                .unwrap_or(Type::new(
+                    format!("unnamed(oid: {type_oid})"),
+                    type_oid,
+                    Kind::Simple,
+                    "pg_catalog".to_string(),
+                ));
+
+            column_schemas.push(ColumnSchema {
+                name,
+                typ,
+                modifier: column.type_modifier(),
+                nullable,
+                identity: column.flags() == 1,
+                default,
+                generated,
+            });
+        }
+
+        // generated columns missing from the message aren't published, keep them
+        // where they were so that the schema matches the table's
+        for (i, previous_column) in table_schema.column_schemas.iter().enumerate() {
+            if previous_column.generated
+                && !column_schemas
+                    .iter()
+                    .any(|c| c.name == previous_column.name)
+            {
+                column_schemas.insert(i.min(column_schemas.len()), previous_column.clone());
+            }
+        }
+
+        if column_schemas != table_schema.column_schemas {
+            info!("schema of table {} changed", table_schema.table_name);
+            table_schema.column_schemas = column_schemas;
+        }
+
+        table_schema.replica_identity = match relation_body.replica_identity() {
+            RelationReplicaIdentity::Default => ReplicaIdentity::Default,
+            RelationReplicaIdentity::Index => ReplicaIdentity::Index,
+            RelationReplicaIdentity::Full => ReplicaIdentity::Full,
+            RelationReplicaIdentity::Nothing => ReplicaIdentity::Nothing,
+        };
+
+        Ok(())
+    }
+
     pub fn try_from(
         value: ReplicationMessage<LogicalReplicationMessage>,
-        table_schemas: &HashMap<TableId, TableSchema>,
+        table_schemas: &mut HashMap<TableId, TableSchema>,
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<CdcEvent, CdcEventConversionError> {
         match value {
             ReplicationMessage::XLogData(xlog_data) => match xlog_data.into_data() {
                 LogicalReplicationMessage::Begin(begin_body) => Ok(CdcEvent::Begin(begin_body)),
                 LogicalReplicationMessage::Commit(commit_body) => Ok(CdcEvent::Commit(commit_body)),
-                LogicalReplicationMessage::Origin(_) => {
-                    Err(CdcEventConversionError::MessageNotSupported)
-                }
+                LogicalReplicationMessage::Origin(origin_body) => Ok(CdcEvent::Origin(origin_body)),
                 LogicalReplicationMessage::Relation(relation_body) => {
+                    Self::update_table_schema(&relation_body, table_schemas)?;
                     Ok(CdcEvent::Relation(relation_body))
                 }
-                LogicalReplicationMessage::Type(_) => {
-                    Err(CdcEventConversionError::MessageNotSupported)
-                }
+                LogicalReplicationMessage::Type(type_body) => Ok(CdcEvent::Type(type_body)),
                 LogicalReplicationMessage::Insert(insert_body) => {
                     let table_id = insert_body.rel_id();
                     let column_schemas = &table_schemas
@@ -191,6 +294,7 @@ impl CdcEventConverter {
                         table_id,
                         column_schemas,
                         insert_body,
+                        unsupported_type_policy,
                     )?)
                 }
                 LogicalReplicationMessage::Update(update_body) => {
@@ -203,6 +307,7 @@ impl CdcEventConverter {
                         table_id,
                         column_schemas,
                         update_body,
+                        unsupported_type_policy,
                     )?)
                 }
                 LogicalReplicationMessage::Delete(delete_body) => {
@@ -215,37 +320,77 @@ impl CdcEventConverter {
                         table_id,
                         column_schemas,
                         delete_body,
+                        unsupported_type_policy,
                     )?)
                 }
                 LogicalReplicationMessage::Truncate(_) => {
-                    Err(CdcEventConversionError::MessageNotSupported)
+                    Err(CdcEventConversionError::MessageNotSupported("truncate"))
                 }
-                _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+                message => Err(CdcEventConversionError::UnknownReplicationMessage(
+                    message_kind(&message),
+                )),
             },
             ReplicationMessage::PrimaryKeepAlive(keep_alive) => Ok(CdcEvent::KeepAliveRequested {
                 reply: keep_alive.reply() == 1,
+                wal_end: keep_alive.wal_end().into(),
             }),
-            _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+            message => Err(CdcEventConversionError::UnknownReplicationMessage(
+                message_kind(&message),
+            )),
         }
     }
 }
 
+/// Returns the name of a message's variant, e.g. `StreamStart`, without its body
+fn message_kind(message: &impl std::fmt::Debug) -> String {
+    let message = format!("{message:?}");
+    match message.find(['(', ' ', '{']) {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
 #[derive(Debug)]
 pub enum CdcEvent {
     Begin(BeginBody),
     Commit(CommitBody),
+    /// Sent after a begin for a transaction which was replicated into the source,
+    /// with the name of the replication origin it was applied by
+    Origin(OriginBody),
     Insert((TableId, TableRow)),
     Update((TableId, TableRow)),
     Delete((TableId, TableRow)),
     Relation(RelationBody),
-    KeepAliveRequested { reply: bool },
+    /// Sent before a relation message for each column type which isn't built into
+    /// Postgres. The types are already resolved in the table schemas.
+    Type(TypeBody),
+    /// Written between transactions for a table which was removed from the source,
+    /// e.g. dropped from the publication. No more changes to it follow.
+    TableRemoved(TableId),
+    /// `wal_end` is the position up to which the server has sent the WAL
+    KeepAliveRequested {
+        reply: bool,
+        wal_end: PgLsn,
+    },
+}
+
+impl CdcEventConversionError {
+    /// Returns true if the error is about a replication message which can't be
+    /// converted into an event, rather than about the data in one
+    pub fn is_unsupported_message(&self) -> bool {
+        matches!(
+            self,
+            CdcEventConversionError::MessageNotSupported(_)
+                | CdcEventConversionError::UnknownReplicationMessage(_)
+        )
+    }
 }
 
 impl BatchBoundary for CdcEvent {
     fn is_last_in_batch(&self) -> bool {
         matches!(
             self,
-            CdcEvent::Commit(_) | CdcEvent::KeepAliveRequested { reply: _ }
+            CdcEvent::Commit(_) | CdcEvent::KeepAliveRequested { .. }
         )
     }
 }
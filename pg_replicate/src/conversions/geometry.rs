@@ -0,0 +1,153 @@
+use bytes::Buf;
+use postgres_protocol::types;
+use serde::Serialize;
+use tokio_postgres::types::{FromSql, Type};
+
+use super::text::FromTextError;
+
+/// Returns true if `typ` is one of Postgres's builtin geometric types
+pub fn is_geometric_type(typ: &Type) -> bool {
+    matches!(
+        *typ,
+        Type::POINT
+            | Type::LINE
+            | Type::LSEG
+            | Type::BOX
+            | Type::PATH
+            | Type::POLYGON
+            | Type::CIRCLE
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A value of one of the geometric types, with all of its points
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Geometry {
+    Point(Point),
+    /// The line `a*x + b*y + c = 0`
+    Line {
+        a: f64,
+        b: f64,
+        c: f64,
+    },
+    Lseg {
+        start: Point,
+        end: Point,
+    },
+    Box {
+        high: Point,
+        low: Point,
+    },
+    Path {
+        closed: bool,
+        points: Vec<Point>,
+    },
+    Polygon {
+        points: Vec<Point>,
+    },
+    Circle {
+        center: Point,
+        radius: f64,
+    },
+}
+
+impl Geometry {
+    /// Parses the text form of a value of the geometric type `typ`, e.g. `(1,2)`
+    /// for a point, `((0,0),(1,1))` for a polygon or `<(0,0),1>` for a circle
+    pub fn from_text(typ: &Type, val: &str) -> Result<Geometry, FromTextError> {
+        let invalid = || FromTextError::InvalidGeometry(val.to_string());
+        // all the text forms are numbers between brackets and commas, so the
+        // numbers alone are enough once the type is known
+        let numbers = val
+            .split(['(', ')', '[', ']', '{', '}', '<', '>', ','])
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        if numbers.len() % 2 != 0 && !matches!(*typ, Type::LINE | Type::CIRCLE) {
+            return Err(invalid());
+        }
+        let points: Vec<Point> = numbers
+            .chunks_exact(2)
+            .map(|p| Point { x: p[0], y: p[1] })
+            .collect();
+
+        let geometry = match (typ, &numbers[..], &points[..]) {
+            (&Type::POINT, _, &[point]) => Geometry::Point(point),
+            (&Type::LINE, &[a, b, c], _) => Geometry::Line { a, b, c },
+            (&Type::LSEG, _, &[start, end]) => Geometry::Lseg { start, end },
+            (&Type::BOX, _, &[high, low]) => Geometry::Box { high, low },
+            (&Type::PATH, _, [_, ..]) => Geometry::Path {
+                // an open path is in square brackets
+                closed: !val.trim_start().starts_with('['),
+                points,
+            },
+            (&Type::POLYGON, _, [_, ..]) => Geometry::Polygon { points },
+            (&Type::CIRCLE, &[x, y, radius], _) => Geometry::Circle {
+                center: Point { x, y },
+                radius,
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(geometry)
+    }
+}
+
+/// Point, box and circle have fixed size binary forms. The other geometric types
+/// are copied in the text format.
+impl<'a> FromSql<'a> for Geometry {
+    fn from_sql(
+        ty: &Type,
+        mut raw: &'a [u8],
+    ) -> Result<Geometry, Box<dyn std::error::Error + Sync + Send>> {
+        let geometry = match *ty {
+            Type::POINT => {
+                let point = types::point_from_sql(raw)?;
+                Geometry::Point(Point {
+                    x: point.x(),
+                    y: point.y(),
+                })
+            }
+            Type::BOX => {
+                let bx = types::box_from_sql(raw)?;
+                Geometry::Box {
+                    high: Point {
+                        x: bx.upper_right().x(),
+                        y: bx.upper_right().y(),
+                    },
+                    low: Point {
+                        x: bx.lower_left().x(),
+                        y: bx.lower_left().y(),
+                    },
+                }
+            }
+            Type::CIRCLE => {
+                if raw.len() != 24 {
+                    return Err("invalid buffer size".into());
+                }
+                Geometry::Circle {
+                    center: Point {
+                        x: raw.get_f64(),
+                        y: raw.get_f64(),
+                    },
+                    radius: raw.get_f64(),
+                }
+            }
+            ref typ => return Err(format!("{typ} is only decoded from the text format").into()),
+        };
+
+        Ok(geometry)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::POINT | Type::BOX | Type::CIRCLE)
+    }
+}
@@ -1,2 +1,103 @@
+use tokio_postgres::types::{Kind, Type};
+
+use geometry::is_geometric_type;
+
 pub mod cdc_event;
+pub mod geometry;
 pub mod table_row;
+pub mod text;
+
+/// Returns true if values of `typ` are decoded into a typed [table_row::Cell].
+/// Values of other types are handled according to the [UnsupportedTypePolicy].
+/// A range type is supported if its subtype is, an array or composite type if the
+/// types it contains are, at any depth, and a domain if its base type is.
+pub fn is_supported_type(typ: &Type) -> bool {
+    match typ.kind() {
+        Kind::Domain(base_type) => return is_supported_type(base_type),
+        Kind::Range(subtype) => return is_supported_type(subtype),
+        Kind::Array(element_type) => return is_supported_type(element_type),
+        Kind::Composite(fields) => return fields.iter().all(|f| is_supported_type(f.type_())),
+        _ => {}
+    }
+
+    matches!(
+        *typ,
+        Type::BOOL
+            | Type::CHAR
+            | Type::BPCHAR
+            | Type::VARCHAR
+            | Type::NAME
+            | Type::TEXT
+            | Type::INT2
+            | Type::INT4
+            | Type::INT8
+            | Type::TIMESTAMP
+            | Type::MONEY
+            | Type::NUMERIC
+    ) || is_text_only_type(typ)
+        || is_geometric_type(typ)
+        || is_hstore(typ)
+}
+
+/// Returns true if values of `typ` are only decoded from the text format, in
+/// which they arrive ready-made, or which nest other values, like arrays and
+/// composites. Tables with columns of such types are copied in the text format.
+pub fn is_text_only_type(typ: &Type) -> bool {
+    let typ = base_type(typ);
+    matches!(typ.kind(), Kind::Array(_) | Kind::Composite(_))
+        || matches!(
+            *typ,
+            Type::XML
+                | Type::TS_VECTOR
+                | Type::TSQUERY
+                | Type::LINE
+                | Type::LSEG
+                | Type::PATH
+                | Type::POLYGON
+        )
+}
+
+/// Returns the base type of a domain, through domains over other domains, or
+/// `typ` itself if it isn't a domain. Values of a domain are stored and sent like
+/// those of its base type.
+pub fn base_type(typ: &Type) -> &Type {
+    match typ.kind() {
+        Kind::Domain(base_type) => self::base_type(base_type),
+        _ => typ,
+    }
+}
+
+/// Returns true if `typ` is an array or composite type whose values are decoded
+/// into nested cells, i.e. one whose contained types are all supported
+pub fn is_nested_type(typ: &Type) -> bool {
+    let typ = base_type(typ);
+    matches!(typ.kind(), Kind::Array(_) | Kind::Composite(_)) && is_supported_type(typ)
+}
+
+/// Returns true if `typ` is the hstore extension's type. An extension's types
+/// get their oids when it is created, so hstore is recognized by its name, which
+/// is resolved through `pg_type` with the table's schema.
+pub fn is_hstore(typ: &Type) -> bool {
+    typ.name() == "hstore"
+}
+
+/// Decides what happens to a column whose type the converters don't support
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsupportedTypePolicy {
+    /// Keep the column's raw value as `Cell::Bytes` and carry on
+    #[default]
+    Skip,
+    /// Fail the conversion with an unsupported type error
+    Fail,
+}
+
+/// Decides what the cdc stream does with a replication message which it can't
+/// convert into an event, e.g. one added by a newer Postgres version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownMessagePolicy {
+    /// Fail the stream with a message not supported error
+    #[default]
+    Fail,
+    /// Log the message's kind and carry on with the next message
+    Skip,
+}
@@ -1,13 +1,25 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Buf;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use postgres_protocol::types;
+use serde::{ser::SerializeMap, Serialize, Serializer};
 use thiserror::Error;
 use tokio_postgres::{
     binary_copy::BinaryCopyOutRow,
-    types::{FromSql, Type},
+    types::{FromSql, Kind, Type},
 };
 
 use crate::{pipeline::batching::BatchBoundary, table::ColumnSchema};
 
+use super::{
+    base_type,
+    geometry::{is_geometric_type, Geometry},
+    is_hstore, is_supported_type,
+    text::{FromTextError, TextFormatConverter},
+    UnsupportedTypePolicy,
+};
+
 #[derive(Debug)]
 pub enum Cell {
     Null,
@@ -18,6 +30,39 @@ pub enum Cell {
     I64(i64),
     TimeStamp(String),
     Bytes(Vec<u8>),
+    /// An hstore's keys and their values, which may be null
+    Map(BTreeMap<String, Option<String>>),
+    Geometry(Geometry),
+    /// An array's elements, with a nested array for each further dimension
+    Array(Vec<Cell>),
+    /// A composite's field names and values, in the order of its fields
+    Composite(Vec<(String, Cell)>),
+}
+
+/// Cells are serialized as json values, which sinks use for the types without a
+/// column type of their own, e.g. arrays and composites
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Null => serializer.serialize_none(),
+            Cell::Bool(b) => serializer.serialize_bool(*b),
+            Cell::String(s) | Cell::TimeStamp(s) => serializer.serialize_str(s),
+            Cell::I16(i) => serializer.serialize_i16(*i),
+            Cell::I32(i) => serializer.serialize_i32(*i),
+            Cell::I64(i) => serializer.serialize_i64(*i),
+            Cell::Bytes(b) => serializer.serialize_bytes(b),
+            Cell::Map(m) => m.serialize(serializer),
+            Cell::Geometry(g) => g.serialize(serializer),
+            Cell::Array(cells) => cells.serialize(serializer),
+            Cell::Composite(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, cell) in fields {
+                    map.serialize_entry(name, cell)?;
+                }
+                map.end()
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -38,6 +83,15 @@ pub enum TableRowConversionError {
 
     #[error("failed to get timestamp nanos from {0}")]
     NoTimestampNanos(DateTime<Utc>),
+
+    #[error("invalid value: {0}")]
+    FromText(#[from] FromTextError),
+
+    #[error("copied row has {0} values but the table has {1} columns")]
+    ColumnCountMismatch(usize, usize),
+
+    #[error("failed to read column value: {0}")]
+    InvalidColumnValue(#[from] tokio_postgres::Error),
 }
 
 pub struct TableRowConverter;
@@ -80,137 +134,284 @@ impl<'a> FromSql<'a> for VecWrapper {
     }
 }
 
+/// A money value, which is sent as an int8 of the amount in the currency's
+/// smallest unit, e.g. cents
+struct Money(i64);
+
+impl<'a> FromSql<'a> for Money {
+    fn from_sql(
+        _: &Type,
+        raw: &'a [u8],
+    ) -> Result<Money, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Money(types::int8_from_sql(raw)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::MONEY
+    }
+}
+
+/// A numeric value decoded from the binary format into its exact text form, e.g.
+/// `-12.3400` or `NaN`, which is the form the cdc stream sends numerics in
+struct NumericText(String);
+
+impl NumericText {
+    const POSITIVE: u16 = 0x0000;
+    const NEGATIVE: u16 = 0x4000;
+    const NAN: u16 = 0xC000;
+    const POSITIVE_INFINITY: u16 = 0xD000;
+    const NEGATIVE_INFINITY: u16 = 0xF000;
+}
+
+impl<'a> FromSql<'a> for NumericText {
+    /// A numeric is sent as its number of base 10000 digits, the weight of the first
+    /// digit, the sign, the number of decimal digits after the point and the digits
+    fn from_sql(
+        _: &Type,
+        mut raw: &'a [u8],
+    ) -> Result<NumericText, Box<dyn std::error::Error + Sync + Send>> {
+        if raw.len() < 8 {
+            return Err("numeric value is too short".into());
+        }
+        let ndigits = raw.get_i16();
+        let weight = raw.get_i16();
+        let sign = raw.get_u16();
+        let dscale = raw.get_u16() as usize;
+        let ndigits = usize::try_from(ndigits).map_err(|_| "invalid numeric digit count")?;
+        if raw.len() != ndigits * 2 {
+            return Err("numeric value's length doesn't match its digit count".into());
+        }
+        let digits: Vec<i16> = (0..ndigits).map(|_| raw.get_i16()).collect();
+
+        let mut text = match sign {
+            Self::POSITIVE => String::new(),
+            Self::NEGATIVE => "-".to_string(),
+            Self::NAN => return Ok(NumericText("NaN".to_string())),
+            Self::POSITIVE_INFINITY => return Ok(NumericText("Infinity".to_string())),
+            Self::NEGATIVE_INFINITY => return Ok(NumericText("-Infinity".to_string())),
+            sign => return Err(format!("invalid numeric sign {sign:#x}").into()),
+        };
+        let digit = |i: i32| {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| digits.get(i).copied())
+                .unwrap_or(0)
+        };
+
+        let weight = i32::from(weight);
+        if weight < 0 {
+            text.push('0');
+        } else {
+            text.push_str(&digit(0).to_string());
+            for i in 1..=weight {
+                text.push_str(&format!("{:04}", digit(i)));
+            }
+        }
+
+        if dscale > 0 {
+            let mut fraction = String::with_capacity(dscale + 4);
+            let mut i = weight + 1;
+            while fraction.len() < dscale {
+                fraction.push_str(&format!("{:04}", digit(i)));
+                i += 1;
+            }
+            fraction.truncate(dscale);
+            text.push('.');
+            text.push_str(&fraction);
+        }
+
+        Ok(NumericText(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+/// A range value decoded from the binary format into its canonical text form,
+/// e.g. `[1,10)` or `empty`, which is the form the cdc stream sends ranges in.
+struct RangeText(String);
+
+impl RangeText {
+    /// Converts a binary format range bound to text, quoting it like Postgres does
+    fn bound_to_text(
+        subtype: &Type,
+        raw: Option<&[u8]>,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        let Some(raw) = raw else {
+            return Ok(String::new());
+        };
+
+        let val = match *base_type(subtype) {
+            Type::BOOL => if bool::from_sql(subtype, raw)? {
+                "t"
+            } else {
+                "f"
+            }
+            .to_string(),
+            Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => {
+                types::text_from_sql(raw)?.to_string()
+            }
+            Type::INT2 => i16::from_sql(subtype, raw)?.to_string(),
+            Type::INT4 => i32::from_sql(subtype, raw)?.to_string(),
+            Type::INT8 => i64::from_sql(subtype, raw)?.to_string(),
+            Type::NUMERIC => NumericText::from_sql(subtype, raw)?.0,
+            Type::TIMESTAMP => NaiveDateTime::from_sql(subtype, raw)?
+                .format("%Y-%m-%d %H:%M:%S%.f")
+                .to_string(),
+            ref typ => return Err(format!("unsupported range subtype {typ}").into()),
+        };
+
+        let needs_quotes = val.is_empty()
+            || val.chars().any(|c| {
+                matches!(c, '"' | '\\' | '(' | ')' | '[' | ']' | ',') || c.is_whitespace()
+            });
+        if !needs_quotes {
+            return Ok(val);
+        }
+
+        let mut quoted = String::with_capacity(val.len() + 2);
+        quoted.push('"');
+        for c in val.chars() {
+            if matches!(c, '"' | '\\') {
+                quoted.push(c);
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        Ok(quoted)
+    }
+}
+
+impl<'a> FromSql<'a> for RangeText {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<RangeText, Box<dyn std::error::Error + Sync + Send>> {
+        let Kind::Range(subtype) = ty.kind() else {
+            return Err(format!("{ty} is not a range type").into());
+        };
+
+        let text = match types::range_from_sql(raw)? {
+            types::Range::Empty => "empty".to_string(),
+            types::Range::Nonempty(lower, upper) => {
+                let (lower_bracket, lower) = match lower {
+                    types::RangeBound::Inclusive(val) => ('[', val),
+                    types::RangeBound::Exclusive(val) => ('(', val),
+                    types::RangeBound::Unbounded => ('(', None),
+                };
+                let (upper_bracket, upper) = match upper {
+                    types::RangeBound::Inclusive(val) => (']', val),
+                    types::RangeBound::Exclusive(val) => (')', val),
+                    types::RangeBound::Unbounded => (')', None),
+                };
+                let lower = Self::bound_to_text(subtype, lower)?;
+                let upper = Self::bound_to_text(subtype, upper)?;
+                format!("{lower_bracket}{lower},{upper}{upper_bracket}")
+            }
+        };
+
+        Ok(RangeText(text))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Range(_))
+    }
+}
+
 impl TableRowConverter {
+    /// Nulls are read through `Option` for every type so that they become
+    /// [Cell::Null] regardless of the column's nullability, while any other
+    /// error, e.g. an invalid value, is returned.
     fn get_cell_value(
         row: &BinaryCopyOutRow,
         column_schema: &ColumnSchema,
         i: usize,
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<Cell, TableRowConversionError> {
-        match column_schema.typ {
-            Type::BOOL => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<bool>(i) {
-                        Ok(b) => Cell::Bool(b),
-                        //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                        Err(_) => Cell::Null,
-                    }
-                } else {
-                    let val = row.get::<bool>(i);
-                    Cell::Bool(val)
-                };
-                Ok(val)
-            }
+        let val = match *base_type(&column_schema.typ) {
+            Type::BOOL => row.try_get::<Option<bool>>(i)?.map(Cell::Bool),
             // Type::BYTEA => {
             //     let bytes = row.get(i);
             //     Ok(Value::Bytes(bytes))
             // }
-            Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<&str>(i) {
-                        Ok(s) => Cell::String(s.to_string()),
-                        //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                        Err(_) => Cell::Null,
-                    }
-                } else {
-                    let val = row.get::<&str>(i);
-                    Cell::String(val.to_string())
-                };
-                Ok(val)
-            }
+            Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => row
+                .try_get::<Option<&str>>(i)?
+                .map(|s| Cell::String(s.to_string())),
             // Type::JSON | Type::JSONB => {
             //     let val = row.get::<serde_json::Value>(i);
             //     let val = json_to_cbor_value(&val);
             //     Ok(val)
             // }
-            Type::INT2 => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<i16>(i) {
-                        Ok(i) => Cell::I16(i),
-                        Err(_) => {
-                            //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                            Cell::Null
-                        }
-                    }
-                } else {
-                    let val = row.get::<i16>(i);
-                    Cell::I16(val)
-                };
-                Ok(val)
-            }
-            Type::INT4 => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<i32>(i) {
-                        Ok(i) => Cell::I32(i),
-                        Err(_) => {
-                            //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                            Cell::Null
-                        }
-                    }
-                } else {
-                    let val = row.get::<i32>(i);
-                    Cell::I32(val)
-                };
-                Ok(val)
-            }
-            Type::INT8 => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<i64>(i) {
-                        Ok(i) => Cell::I64(i),
-                        Err(_) => {
-                            //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                            Cell::Null
-                        }
-                    }
-                } else {
-                    let val = row.get::<i64>(i);
-                    Cell::I64(val)
-                };
-                Ok(val)
-            }
-            Type::TIMESTAMP => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<NaiveDateTime>(i) {
-                        Ok(s) => {
-                            let s = s.format("%Y-%m-%d %H:%M:%S%.f").to_string();
-                            Cell::TimeStamp(s.to_string())
-                        }
-                        Err(_) => {
-                            //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                            Cell::Null
-                        }
-                    }
-                } else {
-                    let val = row.get::<NaiveDateTime>(i);
-                    let val = val.format("%Y-%m-%d %H:%M:%S%.f").to_string();
-                    Cell::TimeStamp(val)
-                };
-                Ok(val)
+            Type::INT2 => row.try_get::<Option<i16>>(i)?.map(Cell::I16),
+            Type::INT4 => row.try_get::<Option<i32>>(i)?.map(Cell::I32),
+            Type::INT8 => row.try_get::<Option<i64>>(i)?.map(Cell::I64),
+            Type::TIMESTAMP => row
+                .try_get::<Option<NaiveDateTime>>(i)?
+                .map(|t| Cell::TimeStamp(t.format("%Y-%m-%d %H:%M:%S%.f").to_string())),
+            Type::NUMERIC => row
+                .try_get::<Option<NumericText>>(i)?
+                .map(|n| Cell::String(n.0)),
+            // locale independent amount in the smallest unit, unlike money's text form
+            Type::MONEY => row.try_get::<Option<Money>>(i)?.map(|m| Cell::I64(m.0)),
+            ref typ if is_hstore(typ) => row
+                .try_get::<Option<HashMap<String, Option<String>>>>(i)?
+                .map(|m| Cell::Map(m.into_iter().collect())),
+            ref typ if is_geometric_type(typ) => {
+                row.try_get::<Option<Geometry>>(i)?.map(Cell::Geometry)
             }
-            _ => {
-                let val = if column_schema.nullable {
-                    match row.try_get::<VecWrapper>(i) {
-                        Ok(v) => Cell::Bytes(v.0),
-                        Err(_) => {
-                            //TODO: Only return null if the error is WasNull from tokio_postgres crate
-                            Cell::Null
-                        }
-                    }
-                } else {
-                    let val = row.get::<VecWrapper>(i);
-                    Cell::Bytes(val.0)
-                };
-                Ok(val)
+            ref typ if is_supported_type(typ) && matches!(typ.kind(), Kind::Range(_)) => row
+                .try_get::<Option<RangeText>>(i)?
+                .map(|r| Cell::String(r.0)),
+            ref typ if unsupported_type_policy == UnsupportedTypePolicy::Fail => {
+                return Err(TableRowConversionError::UnsupportedType(typ.clone()))
             }
-        }
+            _ => row
+                .try_get::<Option<VecWrapper>>(i)?
+                .map(|v| Cell::Bytes(v.0)),
+        };
+
+        Ok(val.unwrap_or(Cell::Null))
     }
 
     pub fn try_from(
         row: &tokio_postgres::binary_copy::BinaryCopyOutRow,
         column_schemas: &[crate::table::ColumnSchema],
+        unsupported_type_policy: UnsupportedTypePolicy,
     ) -> Result<TableRow, TableRowConversionError> {
         let mut values = Vec::with_capacity(column_schemas.len());
         for (i, column_schema) in column_schemas.iter().enumerate() {
-            let value = Self::get_cell_value(row, column_schema, i)?;
+            let value = Self::get_cell_value(row, column_schema, i, unsupported_type_policy)?;
+            values.push(value);
+        }
+
+        Ok(TableRow { values })
+    }
+
+    /// Converts a row of a text format `COPY ... TO STDOUT`
+    pub fn try_from_text(
+        row: &[u8],
+        column_schemas: &[crate::table::ColumnSchema],
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<TableRow, TableRowConversionError> {
+        let row = TextFormatConverter::split_copy_row(row);
+        if row.len() != column_schemas.len() {
+            return Err(TableRowConversionError::ColumnCountMismatch(
+                row.len(),
+                column_schemas.len(),
+            ));
+        }
+
+        let mut values = Vec::with_capacity(column_schemas.len());
+        for (value, column_schema) in row.iter().zip(column_schemas) {
+            let value = match value {
+                Some(bytes) => TextFormatConverter::try_from_bytes(
+                    &column_schema.typ,
+                    bytes,
+                    unsupported_type_policy,
+                )?,
+                None => Cell::Null,
+            };
             values.push(value);
         }
 
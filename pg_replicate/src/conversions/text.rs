@@ -0,0 +1,435 @@
+use std::{
+    collections::BTreeMap,
+    iter::Peekable,
+    num::ParseIntError,
+    str::{from_utf8, Chars, ParseBoolError, Utf8Error},
+};
+
+use chrono::NaiveDateTime;
+use thiserror::Error;
+use tokio_postgres::types::{Field, Kind, Type};
+
+use super::{
+    geometry::{is_geometric_type, Geometry},
+    is_hstore, is_supported_type,
+    table_row::Cell,
+    UnsupportedTypePolicy,
+};
+
+#[derive(Debug, Error)]
+pub enum FromTextError {
+    #[error("invalid string value")]
+    InvalidStr(#[from] Utf8Error),
+
+    #[error("invalid bool value")]
+    InvalidBool(#[from] ParseBoolError),
+
+    #[error("invalid int value")]
+    InvalidInt(#[from] ParseIntError),
+
+    #[error("invalid timestamp value")]
+    InvalidTimestamp(#[from] chrono::ParseError),
+
+    #[error("invalid hstore value: {0}")]
+    InvalidHstore(String),
+
+    #[error("invalid geometric value: {0}")]
+    InvalidGeometry(String),
+
+    #[error("invalid array value: {0}")]
+    InvalidArray(String),
+
+    #[error("invalid composite value: {0}")]
+    InvalidComposite(String),
+
+    #[error("unsupported type {0}")]
+    UnsupportedType(Type),
+}
+
+/// Converts values in Postgres's text format, which is used both by the cdc
+/// stream and by text format table copies.
+pub struct TextFormatConverter;
+
+impl TextFormatConverter {
+    pub fn try_from_bytes(
+        typ: &Type,
+        bytes: &[u8],
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<Cell, FromTextError> {
+        // a domain has the same text representation as its base type
+        if let Kind::Domain(base_type) = typ.kind() {
+            return Self::try_from_bytes(base_type, bytes, unsupported_type_policy);
+        }
+
+        // ranges are sent in their canonical text form, e.g. [1,10) or empty
+        if let Kind::Range(_) = typ.kind() {
+            let val = from_utf8(bytes)?;
+            return Ok(Cell::String(val.to_string()));
+        }
+
+        // arrays and composites of supported types are converted element by element
+        if is_supported_type(typ) {
+            match typ.kind() {
+                Kind::Array(element_type) => {
+                    let val = from_utf8(bytes)?;
+                    let cells = Self::parse_array(element_type, val, unsupported_type_policy)?;
+                    return Ok(Cell::Array(cells));
+                }
+                Kind::Composite(fields) => {
+                    let val = from_utf8(bytes)?;
+                    let fields = Self::parse_composite(fields, val, unsupported_type_policy)?;
+                    return Ok(Cell::Composite(fields));
+                }
+                _ => {}
+            }
+        }
+
+        match *typ {
+            Type::BOOL => {
+                let val = match from_utf8(bytes)? {
+                    "t" => true,
+                    "f" => false,
+                    val => val.parse()?,
+                };
+                Ok(Cell::Bool(val))
+            }
+            // Type::BYTEA => Ok(Value::Bytes(bytes.to_vec())),
+            Type::CHAR
+            | Type::BPCHAR
+            | Type::VARCHAR
+            | Type::NAME
+            | Type::TEXT
+            | Type::XML
+            | Type::TS_VECTOR
+            | Type::TSQUERY
+            | Type::NUMERIC => {
+                let val = from_utf8(bytes)?;
+                Ok(Cell::String(val.to_string()))
+            }
+            Type::INT2 => {
+                let val = from_utf8(bytes)?;
+                let val: i16 = val.parse()?;
+                Ok(Cell::I16(val))
+            }
+            Type::INT4 => {
+                let val = from_utf8(bytes)?;
+                let val: i32 = val.parse()?;
+                Ok(Cell::I32(val))
+            }
+            Type::INT8 => {
+                let val = from_utf8(bytes)?;
+                let val: i64 = val.parse()?;
+                Ok(Cell::I64(val))
+            }
+            Type::TIMESTAMP => {
+                let val = from_utf8(bytes)?;
+                let val = NaiveDateTime::parse_from_str(val, "%Y-%m-%d %H:%M:%S%.f")?;
+                let val = val.format("%Y-%m-%d %H:%M:%S%.f").to_string();
+                Ok(Cell::TimeStamp(val))
+            }
+            Type::MONEY => {
+                let val = from_utf8(bytes)?;
+                Ok(Cell::I64(Self::parse_money(val)?))
+            }
+            ref typ if is_hstore(typ) => {
+                let val = from_utf8(bytes)?;
+                Ok(Cell::Map(Self::parse_hstore(val)?))
+            }
+            ref typ if is_geometric_type(typ) => {
+                let val = from_utf8(bytes)?;
+                Ok(Cell::Geometry(Geometry::from_text(typ, val)?))
+            }
+            ref typ if unsupported_type_policy == UnsupportedTypePolicy::Fail => {
+                Err(FromTextError::UnsupportedType(typ.clone()))
+            }
+            _ => Ok(Cell::Bytes(bytes.to_vec())),
+        }
+    }
+
+    /// Parses money's locale dependent text form, e.g. `$1,234.56` or `-$1,234.56`,
+    /// into its amount in the currency's smallest unit, e.g. 123456 cents. Money
+    /// is printed with all of its fractional digits, so dropping the currency
+    /// symbol and separators leaves exactly that amount.
+    fn parse_money(val: &str) -> Result<i64, ParseIntError> {
+        // some locales put negative amounts in parentheses
+        let negative = val.contains(['-', '(']);
+        let digits: String = val.chars().filter(char::is_ascii_digit).collect();
+        let amount: i64 = digits.parse()?;
+        Ok(if negative { -amount } else { amount })
+    }
+
+    /// Parses hstore's text form, e.g. `"a"=>"1", "b"=>NULL`. Keys and values are
+    /// double quoted with backslash escapes, an unquoted NULL value is a null.
+    fn parse_hstore(val: &str) -> Result<BTreeMap<String, Option<String>>, FromTextError> {
+        let invalid = || FromTextError::InvalidHstore(val.to_string());
+        let mut map = BTreeMap::new();
+        let mut chars = val.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(key) = Self::parse_hstore_token(&mut chars) else {
+                break;
+            };
+
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.next() != Some('=') || chars.next() != Some('>') {
+                return Err(invalid());
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let value = Self::parse_hstore_token(&mut chars).ok_or_else(invalid)?;
+            let value = match value {
+                (value, false) if value.eq_ignore_ascii_case("null") => None,
+                (value, _) => Some(value),
+            };
+            map.insert(key.0, value);
+
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.next() {
+                Some(',') => {}
+                None => break,
+                Some(_) => return Err(invalid()),
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Returns the next quoted or unquoted key or value of an hstore and whether
+    /// it was quoted
+    fn parse_hstore_token(
+        chars: &mut std::iter::Peekable<std::str::Chars>,
+    ) -> Option<(String, bool)> {
+        let mut token = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => return Some((token, true)),
+                    '\\' => token.push(chars.next()?),
+                    c => token.push(c),
+                }
+            }
+        }
+
+        while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != ',' && c != '=') {
+            token.push(c);
+        }
+        (!token.is_empty()).then_some((token, false))
+    }
+
+    /// Parses an array's text form, e.g. `{{1,2},{3,NULL}}`, into its elements, with
+    /// a nested array for each further dimension. Elements are double quoted with
+    /// backslash escapes if needed and an unquoted NULL is a null.
+    fn parse_array(
+        element_type: &Type,
+        val: &str,
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<Vec<Cell>, FromTextError> {
+        let invalid = || FromTextError::InvalidArray(val.to_string());
+        // arrays whose lower bounds aren't 1 start with their bounds, e.g. [0:1]={1,2}
+        let elements = if val.starts_with('[') {
+            val.split_once('=').ok_or_else(invalid)?.1
+        } else {
+            val
+        };
+        // box is the only builtin type whose array elements aren't comma separated
+        let delimiter = if *element_type == Type::BOX { ';' } else { ',' };
+
+        let mut chars = elements.chars().peekable();
+        let cells = Self::parse_array_dimension(
+            &mut chars,
+            element_type,
+            delimiter,
+            unsupported_type_policy,
+            val,
+        )?;
+        if chars.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(cells)
+    }
+
+    /// Parses the elements between a pair of braces of array `val`
+    fn parse_array_dimension(
+        chars: &mut Peekable<Chars>,
+        element_type: &Type,
+        delimiter: char,
+        unsupported_type_policy: UnsupportedTypePolicy,
+        val: &str,
+    ) -> Result<Vec<Cell>, FromTextError> {
+        let invalid = || FromTextError::InvalidArray(val.to_string());
+        if chars.next() != Some('{') {
+            return Err(invalid());
+        }
+        let mut cells = vec![];
+        if chars.next_if_eq(&'}').is_some() {
+            return Ok(cells);
+        }
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let cell = if chars.peek() == Some(&'{') {
+                Cell::Array(Self::parse_array_dimension(
+                    chars,
+                    element_type,
+                    delimiter,
+                    unsupported_type_policy,
+                    val,
+                )?)
+            } else {
+                match Self::parse_array_element(chars, delimiter).ok_or_else(invalid)? {
+                    (element, false) if element.eq_ignore_ascii_case("null") => Cell::Null,
+                    (element, _) => Self::try_from_bytes(
+                        element_type,
+                        element.as_bytes(),
+                        unsupported_type_policy,
+                    )?,
+                }
+            };
+            cells.push(cell);
+
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.next() {
+                Some('}') => return Ok(cells),
+                Some(c) if c == delimiter => {}
+                _ => return Err(invalid()),
+            }
+        }
+    }
+
+    /// Returns the next quoted or unquoted element of an array and whether it was
+    /// quoted
+    fn parse_array_element(chars: &mut Peekable<Chars>, delimiter: char) -> Option<(String, bool)> {
+        let mut element = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => return Some((element, true)),
+                    '\\' => element.push(chars.next()?),
+                    c => element.push(c),
+                }
+            }
+        }
+
+        while let Some(c) = chars.next_if(|&c| c != delimiter && c != '}') {
+            element.push(c);
+        }
+        let element = element.trim_end();
+        (!element.is_empty()).then(|| (element.to_string(), false))
+    }
+
+    /// Parses a composite's text form, e.g. `(1,"a b",)`, into its fields' names and
+    /// values. Values are double quoted if needed, with doubled quotes or backslash
+    /// escapes inside, and an empty unquoted value is a null.
+    fn parse_composite(
+        fields: &[Field],
+        val: &str,
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> Result<Vec<(String, Cell)>, FromTextError> {
+        let invalid = || FromTextError::InvalidComposite(val.to_string());
+        let values = val
+            .strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .ok_or_else(invalid)?;
+
+        let mut chars = values.chars().peekable();
+        let mut cells = Vec::with_capacity(fields.len());
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 && chars.next() != Some(',') {
+                return Err(invalid());
+            }
+
+            let mut value = String::new();
+            let mut quoted = false;
+            let mut in_quotes = false;
+            while let Some(c) = chars.next_if(|&c| in_quotes || c != ',') {
+                match c {
+                    '"' if in_quotes && chars.next_if_eq(&'"').is_some() => value.push('"'),
+                    '"' => {
+                        quoted = true;
+                        in_quotes = !in_quotes;
+                    }
+                    '\\' => value.push(chars.next().ok_or_else(invalid)?),
+                    c => value.push(c),
+                }
+            }
+            if in_quotes {
+                return Err(invalid());
+            }
+
+            let cell = if value.is_empty() && !quoted {
+                Cell::Null
+            } else {
+                Self::try_from_bytes(field.type_(), value.as_bytes(), unsupported_type_policy)?
+            };
+            cells.push((field.name().to_string(), cell));
+        }
+        if chars.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(cells)
+    }
+
+    /// Splits a row of a text format `COPY ... TO STDOUT` into its column values,
+    /// undoing the copy's backslash escapes. `None` is returned for null values.
+    pub fn split_copy_row(row: &[u8]) -> Vec<Option<Vec<u8>>> {
+        let row = row.strip_suffix(b"\n").unwrap_or(row);
+        // tabs and newlines inside values are escaped, so they only occur as delimiters
+        row.split(|&b| b == b'\t')
+            .map(|value| {
+                if value == b"\\N" {
+                    None
+                } else {
+                    Some(Self::unescape_copy_value(value))
+                }
+            })
+            .collect()
+    }
+
+    fn unescape_copy_value(value: &[u8]) -> Vec<u8> {
+        let mut unescaped = Vec::with_capacity(value.len());
+        let mut i = 0;
+        while i < value.len() {
+            if value[i] != b'\\' || i + 1 == value.len() {
+                unescaped.push(value[i]);
+                i += 1;
+                continue;
+            }
+
+            i += 1;
+            match value[i] {
+                b'b' => unescaped.push(0x08),
+                b'f' => unescaped.push(0x0c),
+                b'n' => unescaped.push(b'\n'),
+                b'r' => unescaped.push(b'\r'),
+                b't' => unescaped.push(b'\t'),
+                b'v' => unescaped.push(0x0b),
+                b'0'..=b'7' => {
+                    let digits = Self::count_digits(&value[i..], 3, |b| matches!(b, b'0'..=b'7'));
+                    unescaped.push(Self::parse_digits(&value[i..i + digits], 8));
+                    i += digits - 1;
+                }
+                b'x' if value.get(i + 1).is_some_and(u8::is_ascii_hexdigit) => {
+                    let digits = Self::count_digits(&value[i + 1..], 2, |b| b.is_ascii_hexdigit());
+                    unescaped.push(Self::parse_digits(&value[i + 1..i + 1 + digits], 16));
+                    i += digits;
+                }
+                b => unescaped.push(b),
+            }
+            i += 1;
+        }
+
+        unescaped
+    }
+
+    fn count_digits(bytes: &[u8], max: usize, is_digit: impl Fn(&u8) -> bool) -> usize {
+        bytes.iter().take(max).take_while(|b| is_digit(b)).count()
+    }
+
+    fn parse_digits(digits: &[u8], radix: u32) -> u8 {
+        digits.iter().fold(0u8, |val, &d| {
+            let d = (d as char).to_digit(radix).unwrap_or(0) as u8;
+            val.wrapping_mul(radix as u8).wrapping_add(d)
+        })
+    }
+}
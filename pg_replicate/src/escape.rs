@@ -1,21 +1,13 @@
-use std::borrow::Cow;
-
-pub fn quote_identifier(identifier: &str) -> Cow<'_, str> {
-    if identifier.find('"').is_some() {
-        Cow::Owned(quote_identifier_alloc(identifier))
-    } else {
-        Cow::Borrowed(identifier)
-    }
-}
-
-fn quote_identifier_alloc(identifier: &str) -> String {
-    let mut quoted_identifier = String::with_capacity(identifier.len());
+pub fn quote_identifier(identifier: &str) -> String {
+    let mut quoted_identifier = String::with_capacity(identifier.len() + 2);
+    quoted_identifier.push('"');
     for char in identifier.chars() {
         if char == '"' {
             quoted_identifier.push('"');
         }
         quoted_identifier.push(char);
     }
+    quoted_identifier.push('"');
     quoted_identifier
 }
 
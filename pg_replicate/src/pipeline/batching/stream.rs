@@ -42,6 +42,12 @@ impl<B: BatchBoundary, S: Stream<Item = B>> BatchTimeoutStream<B, S> {
     pub fn get_inner_mut(&mut self) -> &mut S {
         &mut self.stream
     }
+
+    /// Returns the pinned underlying stream, e.g. to send a status update on a
+    /// cdc stream while it is being batched
+    pub fn get_inner_pin_mut(self: Pin<&mut Self>) -> Pin<&mut S> {
+        self.project().stream
+    }
 }
 
 impl<B: BatchBoundary, S: Stream<Item = B>> Stream for BatchTimeoutStream<B, S> {
@@ -1,17 +1,44 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
 use tokio::pin;
 use tokio_postgres::types::PgLsn;
+use tracing::info;
 
-use crate::{conversions::cdc_event::CdcEvent, pipeline::sources::SourceError, table::TableId};
+use crate::{
+    conversions::cdc_event::CdcEvent,
+    pipeline::sources::{postgres::commit_lag, SourceError},
+    table::TableId,
+};
 
-use super::{sinks::Sink, sources::Source, PipelineAction, PipelineError};
+use super::{
+    sinks::Sink, sources::Source, transform_keeps, CdcDeadline, CdcOperation, CdcProgress,
+    FeedbackPolicy, LargeTransactionPolicy, OriginFilter, PipelineAction, PipelineError,
+    TransactionSizeGuard, Transform, TransformEvent, REPLICATION_LAG_LOG_INTERVAL,
+};
 
 pub struct DataPipeline<Src: Source, Snk: Sink> {
     source: Src,
     sink: Snk,
     action: PipelineAction,
+    start_lsn: Option<PgLsn>,
+    heartbeat_interval: Option<Duration>,
+    feedback_interval: Option<Duration>,
+    always_reply_to_keepalives: bool,
+    feedback_policy: FeedbackPolicy,
+    stop_lsn: Option<PgLsn>,
+    max_runtime: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    stopped_idle: bool,
+    publication_check_interval: Option<Duration>,
+    copy_all_tables: bool,
+    max_transaction_rows: Option<(u64, LargeTransactionPolicy)>,
+    operations: HashSet<CdcOperation>,
+    skipped_origins: HashSet<String>,
+    transform: Option<Transform>,
 }
 
 impl<Src: Source, Snk: Sink> DataPipeline<Src, Snk> {
@@ -20,9 +47,135 @@ impl<Src: Source, Snk: Sink> DataPipeline<Src, Snk> {
             source,
             sink,
             action,
+            start_lsn: None,
+            heartbeat_interval: None,
+            feedback_interval: None,
+            always_reply_to_keepalives: false,
+            feedback_policy: FeedbackPolicy::default(),
+            stop_lsn: None,
+            max_runtime: None,
+            idle_timeout: None,
+            stopped_idle: false,
+            publication_check_interval: None,
+            copy_all_tables: false,
+            max_transaction_rows: None,
+            operations: CdcOperation::all(),
+            skipped_origins: HashSet::new(),
+            transform: None,
         }
     }
 
+    /// Starts the cdc stream at `start_lsn` instead of the position after the
+    /// last lsn saved by the sink. This bypasses the sink's resumption state and
+    /// can cause events to be skipped, or delivered again if it's before the sink's
+    /// last lsn. Postgres never streams from before the slot's confirmed lsn, so a
+    /// `start_lsn` before it doesn't rewind the stream, see
+    /// `PostgresSource::validate_start_lsn`.
+    pub fn set_start_lsn(&mut self, start_lsn: PgLsn) {
+        self.start_lsn = Some(start_lsn);
+    }
+
+    /// Confirms the server's wal end to Postgres, at most once per `heartbeat_interval`,
+    /// when a keepalive arrives between transactions. Without it only the lsn of the
+    /// last transaction written to the sink is confirmed, so Postgres has to retain
+    /// all WAL written since then while the published tables see no changes. The
+    /// confirmed wal end is written to the sink with [Sink::write_last_lsn] first.
+    pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Duration) {
+        self.heartbeat_interval = Some(heartbeat_interval);
+    }
+
+    /// Sends a status update with the lsn of the last transaction written to the sink
+    /// whenever `feedback_interval` passes without one, even while the server sends
+    /// no keepalives. Some managed Postgres services disconnect consumers which don't
+    /// report their progress often enough.
+    pub fn set_feedback_interval(&mut self, feedback_interval: Duration) {
+        self.feedback_interval = Some(feedback_interval);
+    }
+
+    /// Sets which lsn status updates confirm to Postgres as flushed
+    pub fn set_feedback_policy(&mut self, feedback_policy: FeedbackPolicy) {
+        self.feedback_policy = feedback_policy;
+    }
+
+    /// Replies to every keepalive with a status update, not only to those in which
+    /// the server requests a reply
+    pub fn set_always_reply_to_keepalives(&mut self, always_reply_to_keepalives: bool) {
+        self.always_reply_to_keepalives = always_reply_to_keepalives;
+    }
+
+    /// Stops the cdc stream once all transactions up to `stop_lsn` have been written
+    /// to the sink and confirmed to Postgres, or once a keepalive between transactions
+    /// shows the server hasn't written anything beyond it.
+    pub fn set_stop_lsn(&mut self, stop_lsn: PgLsn) {
+        self.stop_lsn = Some(stop_lsn);
+    }
+
+    /// Stops the cdc stream after it has run for `max_runtime`. The lsn of the last
+    /// transaction written to the sink is confirmed before stopping and a partially
+    /// received transaction is sent again by Postgres on the next run.
+    pub fn set_max_runtime(&mut self, max_runtime: Duration) {
+        self.max_runtime = Some(max_runtime);
+    }
+
+    /// Stops the cdc stream once it has received nothing but keepalives for
+    /// `idle_timeout`, e.g. for a scheduled job to exit when there is nothing to
+    /// replicate. The lsn of the last transaction written to the sink is confirmed
+    /// before stopping.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = Some(idle_timeout);
+    }
+
+    /// Returns true if the last run of the cdc stream was stopped by the idle timeout
+    pub fn stopped_idle(&self) -> bool {
+        self.stopped_idle
+    }
+
+    /// Checks between transactions, at most once per `publication_check_interval`,
+    /// whether tables were removed from the source, e.g. dropped from its
+    /// publication, and writes a [CdcEvent::TableRemoved] event for each to the sink
+    pub fn set_publication_check_interval(&mut self, publication_check_interval: Duration) {
+        self.publication_check_interval = Some(publication_check_interval);
+    }
+
+    /// Copies all tables, including those which the sink's resumption state lists as
+    /// already copied, e.g. to copy them again after changes to them were lost
+    pub fn set_copy_all_tables(&mut self, copy_all_tables: bool) {
+        self.copy_all_tables = copy_all_tables;
+    }
+
+    /// Limits the number of rows a single transaction may change. Only the changes
+    /// left after the operation, origin and transform filters count. What happens
+    /// with a transaction changing more rows is up to `policy`.
+    pub fn set_max_transaction_rows(&mut self, max_rows: u64, policy: LargeTransactionPolicy) {
+        self.max_transaction_rows = Some((max_rows, policy));
+    }
+
+    /// Only writes inserts, updates and deletes whose kind is in `operations` to the
+    /// sink. Transactions are still written, so the sink's lsn keeps advancing even
+    /// when all of a transaction's changes are skipped.
+    pub fn set_operations(&mut self, operations: HashSet<CdcOperation>) {
+        self.operations = operations;
+    }
+
+    /// Skips the inserts, updates and deletes of transactions which were applied to
+    /// the source by one of the replication `origins`, e.g. changes which another
+    /// pipeline replicated into it, so that they don't loop back to where they came
+    /// from. Their begins and commits are still written to the sink.
+    pub fn set_skipped_origins(&mut self, origins: HashSet<String>) {
+        self.skipped_origins = origins;
+    }
+
+    /// Passes copied rows and row changes to `transform` before writing them to the
+    /// sink. It can change them in place, or drop them by returning false. Begins
+    /// and commits aren't passed to it, so dropping every change of a transaction
+    /// still advances the sink's lsn.
+    pub fn set_transform(
+        &mut self,
+        transform: impl Fn(&mut TransformEvent) -> bool + Send + Sync + 'static,
+    ) {
+        self.transform = Some(Box::new(transform));
+    }
+
     async fn copy_table_schemas(&mut self) -> Result<(), PipelineError> {
         let table_schemas = self.source.get_table_schemas();
         let table_schemas = table_schemas.clone();
@@ -52,7 +205,11 @@ impl<Src: Source, Snk: Sink> DataPipeline<Src, Snk> {
             pin!(table_rows);
 
             while let Some(row) = table_rows.next().await {
-                let row = row.map_err(SourceError::TableCopyStream)?;
+                let mut row = row.map_err(SourceError::TableCopyStream)?;
+                let event = TransformEvent::TableRow(table_schema.table_id, &mut row);
+                if !transform_keeps(&self.transform, event) {
+                    continue;
+                }
                 self.sink
                     .write_table_row(row, table_schema.table_id)
                     .await?;
@@ -66,26 +223,122 @@ impl<Src: Source, Snk: Sink> DataPipeline<Src, Snk> {
     }
 
     async fn copy_cdc_events(&mut self, last_lsn: PgLsn) -> Result<(), PipelineError> {
-        let mut last_lsn: u64 = last_lsn.into();
-        last_lsn += 1;
-        let cdc_events = self.source.get_cdc_stream(last_lsn.into()).await?;
+        let start_lsn = match self.start_lsn {
+            Some(start_lsn) => start_lsn,
+            None => {
+                let last_lsn: u64 = last_lsn.into();
+                (last_lsn + 1).into()
+            }
+        };
+        let cdc_events = self.source.get_cdc_stream(start_lsn).await?;
 
         pin!(cdc_events);
 
-        while let Some(cdc_event) = cdc_events.next().await {
-            let cdc_event = cdc_event.map_err(SourceError::CdcStream)?;
-            let send_status_update = if let CdcEvent::KeepAliveRequested { reply } = cdc_event {
-                reply
-            } else {
-                false
+        let mut in_transaction = false;
+        let mut last_lag_log = Instant::now();
+        let mut last_publication_check = Instant::now();
+        let mut progress = CdcProgress::new(
+            last_lsn,
+            self.feedback_policy,
+            self.feedback_interval,
+            self.heartbeat_interval,
+            self.idle_timeout,
+            self.max_runtime,
+        );
+        let mut transaction_size = TransactionSizeGuard::new(self.max_transaction_rows);
+        let mut origin_filter = OriginFilter::new(self.skipped_origins.clone());
+        self.stopped_idle = false;
+
+        loop {
+            let cdc_event = match progress.next(cdc_events.next(), in_transaction).await {
+                Ok(cdc_event) => cdc_event,
+                Err(deadline) => {
+                    if progress
+                        .handle_deadline(cdc_events.as_mut(), deadline)
+                        .await?
+                    {
+                        self.stopped_idle = deadline == CdcDeadline::Idle;
+                        break;
+                    }
+                    continue;
+                }
             };
-            let last_lsn = self.sink.write_cdc_event(cdc_event).await?;
-            if send_status_update {
-                cdc_events
-                    .as_mut()
-                    .send_status_update(last_lsn)
-                    .await
-                    .map_err(|e| PipelineError::SourceError(SourceError::StatusUpdate(e)))?;
+            let Some(cdc_event) = cdc_event else {
+                break;
+            };
+            let mut cdc_event = cdc_event.map_err(SourceError::CdcStream)?;
+            progress.received(&cdc_event);
+            let keep_alive = match cdc_event {
+                CdcEvent::Begin(_) => {
+                    in_transaction = true;
+                    None
+                }
+                CdcEvent::Commit(ref commit_body) => {
+                    in_transaction = false;
+                    if last_lag_log.elapsed() >= REPLICATION_LAG_LOG_INTERVAL {
+                        info!("replication lag: {}ms", commit_lag(commit_body).as_millis());
+                        last_lag_log = Instant::now();
+                    }
+                    None
+                }
+                CdcEvent::KeepAliveRequested { reply, wal_end } => Some((reply, wal_end)),
+                _ => None,
+            };
+            if origin_filter.skips(&cdc_event)
+                || CdcOperation::skips(&self.operations, &cdc_event)
+                || !transform_keeps(&self.transform, TransformEvent::CdcEvent(&mut cdc_event))
+            {
+                continue;
+            }
+            transaction_size.count(&cdc_event)?;
+            let mut last_lsn = self.sink.write_cdc_event(cdc_event).await?;
+            progress.committed_lsn = last_lsn;
+            if !in_transaction
+                && self
+                    .publication_check_interval
+                    .is_some_and(|interval| last_publication_check.elapsed() >= interval)
+            {
+                for table_id in self.source.removed_tables().await? {
+                    last_lsn = self
+                        .sink
+                        .write_cdc_event(CdcEvent::TableRemoved(table_id))
+                        .await?;
+                }
+                last_publication_check = Instant::now();
+            }
+            let mut caught_up = false;
+            let mut status_update_lsn = None;
+            if let Some((reply, wal_end)) = keep_alive {
+                let heartbeat = progress.heartbeat_due(in_transaction);
+                caught_up =
+                    !in_transaction && self.stop_lsn.is_some_and(|stop_lsn| wal_end >= stop_lsn);
+                if heartbeat || caught_up {
+                    if wal_end > last_lsn {
+                        self.sink.write_last_lsn(wal_end).await?;
+                        last_lsn = wal_end;
+                        progress.committed_lsn = last_lsn;
+                    }
+                    status_update_lsn = Some(last_lsn);
+                } else if reply || self.always_reply_to_keepalives {
+                    status_update_lsn = Some(last_lsn);
+                }
+            }
+            let stop = caught_up || self.stop_lsn.is_some_and(|stop_lsn| last_lsn >= stop_lsn);
+            if stop && status_update_lsn.is_none() {
+                status_update_lsn = Some(last_lsn);
+            }
+            if let Some(lsn) = status_update_lsn {
+                let lsn = progress
+                    .send_status_update(cdc_events.as_mut(), lsn)
+                    .await?;
+                status_update_lsn = Some(lsn);
+            }
+            if stop {
+                info!(
+                    "reached stop lsn, stopping at lsn: {}",
+                    status_update_lsn.unwrap_or(last_lsn)
+                );
+                break;
             }
         }
 
@@ -93,7 +346,10 @@ impl<Src: Source, Snk: Sink> DataPipeline<Src, Snk> {
     }
 
     pub async fn start(&mut self) -> Result<(), PipelineError> {
-        let resumption_state = self.sink.get_resumption_state().await?;
+        let mut resumption_state = self.sink.get_resumption_state().await?;
+        if self.copy_all_tables {
+            resumption_state.copied_tables.clear();
+        }
         match self.action {
             PipelineAction::TableCopiesOnly => {
                 self.copy_table_schemas().await?;
@@ -1,17 +1,348 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, future::Future, pin::Pin, time::Duration};
 
 use thiserror::Error;
+use tokio::time::{timeout_at, Instant};
 use tokio_postgres::types::PgLsn;
+use tracing::{info, warn};
 
-use crate::table::TableId;
+use crate::{
+    clients::postgres::ReplicationClientError,
+    conversions::{cdc_event::CdcEvent, table_row::TableRow},
+    table::TableId,
+};
 
-use self::{sinks::SinkError, sources::SourceError};
+use self::{
+    sinks::SinkError,
+    sources::{
+        postgres::{CdcStream, PostgresSourceError},
+        SourceError,
+    },
+};
 
 pub mod batching;
 pub mod data_pipeline;
 pub mod sinks;
 pub mod sources;
 
+/// How often the pipelines log the replication lag while transactions are being committed
+const REPLICATION_LAG_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A kind of row change in the cdc stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CdcOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl CdcOperation {
+    pub fn all() -> HashSet<CdcOperation> {
+        HashSet::from([
+            CdcOperation::Insert,
+            CdcOperation::Update,
+            CdcOperation::Delete,
+        ])
+    }
+
+    fn of(event: &CdcEvent) -> Option<CdcOperation> {
+        match event {
+            CdcEvent::Insert(_) => Some(CdcOperation::Insert),
+            CdcEvent::Update(_) => Some(CdcOperation::Update),
+            CdcEvent::Delete(_) => Some(CdcOperation::Delete),
+            _ => None,
+        }
+    }
+
+    /// Returns true if `event` is a row change whose kind isn't in `operations`.
+    /// Other events, e.g. begins and commits, are never skipped, so that transactions
+    /// whose changes are all skipped still advance the sink's lsn.
+    fn skips(operations: &HashSet<CdcOperation>, event: &CdcEvent) -> bool {
+        CdcOperation::of(event).is_some_and(|operation| !operations.contains(&operation))
+    }
+}
+
+/// Tracks whether the transaction in progress was applied to the source by a
+/// replication origin whose changes are skipped
+struct OriginFilter {
+    skipped_origins: HashSet<String>,
+    skipping: bool,
+}
+
+impl OriginFilter {
+    fn new(skipped_origins: HashSet<String>) -> OriginFilter {
+        OriginFilter {
+            skipped_origins,
+            skipping: false,
+        }
+    }
+
+    /// Returns true if `event` is a row change of a transaction from a skipped
+    /// origin. The origin of a transaction is only known once its origin event
+    /// arrives and is forgotten at its commit.
+    fn skips(&mut self, event: &CdcEvent) -> bool {
+        match event {
+            CdcEvent::Begin(_) | CdcEvent::Commit(_) => {
+                self.skipping = false;
+                false
+            }
+            CdcEvent::Origin(origin_body) => {
+                self.skipping = origin_body
+                    .name()
+                    .is_ok_and(|name| self.skipped_origins.contains(name));
+                false
+            }
+            event => self.skipping && CdcOperation::of(event).is_some(),
+        }
+    }
+}
+
+/// Which lsn the pipelines confirm as flushed in status updates. Postgres retains
+/// the WAL after the confirmed lsn and resumes streaming from it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FeedbackPolicy {
+    /// Confirm the lsn of the last transaction written to the sink. Nothing is lost
+    /// on a crash, but Postgres retains the WAL of everything not yet written.
+    #[default]
+    Flushed,
+    /// Confirm the last lsn received from Postgres, whether or not its changes have
+    /// been written to the sink, so that Postgres retains less WAL. Changes received
+    /// but not written when the pipeline crashes aren't streamed again and are lost.
+    Received,
+}
+
+impl FeedbackPolicy {
+    /// Returns the lsn to confirm in a status update which would confirm
+    /// `flushed_lsn` under [FeedbackPolicy::Flushed]
+    fn status_update_lsn(self, flushed_lsn: PgLsn, received_lsn: PgLsn) -> PgLsn {
+        match self {
+            FeedbackPolicy::Flushed => flushed_lsn,
+            FeedbackPolicy::Received => flushed_lsn.max(received_lsn),
+        }
+    }
+}
+
+/// A reason for a pipeline to send a status update while waiting for the next event
+/// of its cdc stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CdcDeadline {
+    /// The feedback interval passed without a status update
+    Feedback,
+    /// The idle timeout passed with nothing but keepalives received
+    Idle,
+    /// The max runtime passed
+    MaxRuntime,
+}
+
+/// What a pipeline's cdc stream has received and written to the sink so far, and
+/// when it is due to send a status update or to stop
+struct CdcProgress {
+    feedback_policy: FeedbackPolicy,
+    feedback_interval: Option<Duration>,
+    heartbeat_interval: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_runtime_deadline: Option<Instant>,
+    /// lsn returned by the sink for the last event written to it
+    committed_lsn: PgLsn,
+    /// Highest lsn of a commit or keepalive received from the server
+    received_lsn: PgLsn,
+    last_status_update: Instant,
+    /// When the last event other than a keepalive was received
+    last_activity: Instant,
+}
+
+impl CdcProgress {
+    fn new(
+        last_lsn: PgLsn,
+        feedback_policy: FeedbackPolicy,
+        feedback_interval: Option<Duration>,
+        heartbeat_interval: Option<Duration>,
+        idle_timeout: Option<Duration>,
+        max_runtime: Option<Duration>,
+    ) -> CdcProgress {
+        let now = Instant::now();
+        CdcProgress {
+            feedback_policy,
+            feedback_interval,
+            heartbeat_interval,
+            idle_timeout,
+            max_runtime_deadline: max_runtime.map(|max_runtime| now + max_runtime),
+            committed_lsn: last_lsn,
+            received_lsn: last_lsn,
+            last_status_update: now,
+            last_activity: now,
+        }
+    }
+
+    fn received(&mut self, event: &CdcEvent) {
+        match event {
+            CdcEvent::Commit(commit_body) => {
+                self.received_lsn = self.received_lsn.max(commit_body.end_lsn().into());
+            }
+            CdcEvent::KeepAliveRequested { wal_end, .. } => {
+                self.received_lsn = self.received_lsn.max(*wal_end);
+                return;
+            }
+            _ => {}
+        }
+        self.last_activity = Instant::now();
+    }
+
+    /// Waits for `next`, the next event of the cdc stream, unless one of the
+    /// deadlines passes first. The idle timeout doesn't pass within a transaction.
+    async fn next<F: Future>(
+        &self,
+        next: F,
+        in_transaction: bool,
+    ) -> Result<F::Output, CdcDeadline> {
+        let deadlines = [
+            self.max_runtime_deadline
+                .map(|deadline| (deadline, CdcDeadline::MaxRuntime)),
+            self.feedback_interval
+                .map(|interval| (self.last_status_update + interval, CdcDeadline::Feedback)),
+            self.idle_timeout
+                .filter(|_| !in_transaction)
+                .map(|idle_timeout| (self.last_activity + idle_timeout, CdcDeadline::Idle)),
+        ];
+        match deadlines
+            .into_iter()
+            .flatten()
+            .min_by_key(|(deadline, _)| *deadline)
+        {
+            Some((deadline, reason)) => timeout_at(deadline, next).await.map_err(|_| reason),
+            None => Ok(next.await),
+        }
+    }
+
+    /// Sends a status update for `deadline` and returns true if the cdc stream must
+    /// stop because of it
+    async fn handle_deadline(
+        &mut self,
+        cdc_stream: Pin<&mut CdcStream>,
+        deadline: CdcDeadline,
+    ) -> Result<bool, PipelineError> {
+        let lsn = self
+            .send_status_update(cdc_stream, self.committed_lsn)
+            .await?;
+        match deadline {
+            CdcDeadline::Feedback => return Ok(false),
+            CdcDeadline::Idle => info!("no changes received while idle, stopping at lsn: {lsn}"),
+            CdcDeadline::MaxRuntime => info!("max runtime reached, stopping at lsn: {lsn}"),
+        }
+        Ok(true)
+    }
+
+    /// Returns true if a keepalive between transactions is due to be answered with
+    /// the server's wal end
+    fn heartbeat_due(&self, in_transaction: bool) -> bool {
+        !in_transaction
+            && self
+                .heartbeat_interval
+                .is_some_and(|interval| self.last_status_update.elapsed() >= interval)
+    }
+
+    /// Confirms `flushed_lsn`, or a later lsn depending on the feedback policy, to
+    /// the server and returns the confirmed lsn
+    async fn send_status_update(
+        &mut self,
+        cdc_stream: Pin<&mut CdcStream>,
+        flushed_lsn: PgLsn,
+    ) -> Result<PgLsn, PipelineError> {
+        let lsn = self
+            .feedback_policy
+            .status_update_lsn(flushed_lsn, self.received_lsn);
+        cdc_stream
+            .send_status_update(lsn)
+            .await
+            .map_err(|e| PipelineError::SourceError(SourceError::StatusUpdate(e)))?;
+        self.last_status_update = Instant::now();
+        Ok(lsn)
+    }
+}
+
+/// A copied row or a row change passed to a pipeline's [Transform]
+pub enum TransformEvent<'a> {
+    /// A row copied from the table with the given id
+    TableRow(TableId, &'a mut TableRow),
+    /// An insert, update or delete from the cdc stream
+    CdcEvent(&'a mut CdcEvent),
+}
+
+/// Changes an event in place before it's written to the sink, e.g. to mask a
+/// column's values, and returns false to drop it instead
+pub type Transform = Box<dyn Fn(&mut TransformEvent) -> bool + Send + Sync>;
+
+/// Returns false if `transform` drops `event`. Only row changes are passed to
+/// it, begins, commits and other events are always kept so that transactions
+/// stay complete and the sink's lsn keeps advancing.
+fn transform_keeps(transform: &Option<Transform>, mut event: TransformEvent) -> bool {
+    if let TransformEvent::CdcEvent(ref cdc_event) = event {
+        if CdcOperation::of(cdc_event).is_none() {
+            return true;
+        }
+    }
+    match transform {
+        Some(transform) => transform(&mut event),
+        None => true,
+    }
+}
+
+/// What a pipeline does with a transaction which changes more rows than the limit
+/// set with `set_max_transaction_rows`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LargeTransactionPolicy {
+    /// Stop with [PipelineError::TransactionTooLarge]
+    #[default]
+    Fail,
+    /// Log a warning and write the whole transaction to the sink as usual. It isn't
+    /// split up, so a sink writing a batch at a time may still write it in parts.
+    Warn,
+}
+
+/// Counts the row changes of the transaction in progress which are written to the
+/// sink against a limit
+struct TransactionSizeGuard {
+    limit: Option<(u64, LargeTransactionPolicy)>,
+    xid: u32,
+    rows: u64,
+}
+
+impl TransactionSizeGuard {
+    fn new(limit: Option<(u64, LargeTransactionPolicy)>) -> TransactionSizeGuard {
+        TransactionSizeGuard {
+            limit,
+            xid: 0,
+            rows: 0,
+        }
+    }
+
+    fn count(&mut self, event: &CdcEvent) -> Result<(), PipelineError> {
+        let Some((max_rows, policy)) = self.limit else {
+            return Ok(());
+        };
+        match event {
+            CdcEvent::Begin(begin_body) => {
+                self.xid = begin_body.xid();
+                self.rows = 0;
+                return Ok(());
+            }
+            CdcEvent::Insert(_) | CdcEvent::Update(_) | CdcEvent::Delete(_) => self.rows += 1,
+            _ => return Ok(()),
+        }
+        if self.rows == max_rows + 1 {
+            let xid = self.xid;
+            match policy {
+                LargeTransactionPolicy::Fail => {
+                    return Err(PipelineError::TransactionTooLarge { xid, max_rows })
+                }
+                LargeTransactionPolicy::Warn => {
+                    warn!("transaction {xid} changes more than {max_rows} rows")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 pub enum PipelineAction {
     TableCopiesOnly,
     CdcOnly,
@@ -25,9 +356,26 @@ pub enum PipelineError {
 
     #[error("sink error: {0}")]
     SinkError(#[from] SinkError),
+
+    #[error("transaction {xid} changes more than {max_rows} rows")]
+    TransactionTooLarge { xid: u32, max_rows: u64 },
+}
+
+impl PipelineError {
+    /// Returns true if the cdc stream couldn't start because another connection is
+    /// streaming from the replication slot. This is usually transient, e.g. while
+    /// the previous instance shuts down during a rolling deploy.
+    pub fn is_slot_active(&self) -> bool {
+        matches!(
+            self,
+            PipelineError::SourceError(SourceError::Postgres(
+                PostgresSourceError::ReplicationClient(ReplicationClientError::SlotActive(_))
+            ))
+        )
+    }
 }
 
 pub struct PipelineResumptionState {
-    copied_tables: HashSet<TableId>,
-    last_lsn: PgLsn,
+    pub copied_tables: HashSet<TableId>,
+    pub last_lsn: PgLsn,
 }
@@ -91,6 +91,8 @@ impl BatchSink for BigQueryBatchSink {
             modifier: 0,
             nullable: false,
             identity: true,
+            default: None,
+            generated: false,
         }];
 
         self.client
@@ -108,6 +110,8 @@ impl BatchSink for BigQueryBatchSink {
                 modifier: 0,
                 nullable: false,
                 identity: true,
+                default: None,
+                generated: false,
             },
             ColumnSchema {
                 name: "lsn".to_string(),
@@ -115,6 +119,8 @@ impl BatchSink for BigQueryBatchSink {
                 modifier: 0,
                 nullable: false,
                 identity: false,
+                default: None,
+                generated: false,
             },
         ];
         if self
@@ -216,8 +222,11 @@ impl BatchSink for BigQueryBatchSink {
                         table_name_to_table_rows.entry(table_id).or_default();
                     table_rows.push(table_row);
                 }
+                CdcEvent::Origin(_) => {}
                 CdcEvent::Relation(_) => {}
-                CdcEvent::KeepAliveRequested { reply: _ } => {}
+                CdcEvent::Type(_) => {}
+                CdcEvent::TableRemoved(_) => {}
+                CdcEvent::KeepAliveRequested { .. } => {}
             }
         }
 
@@ -241,6 +250,12 @@ impl BatchSink for BigQueryBatchSink {
         Ok(committed_lsn)
     }
 
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+        self.client.set_last_lsn(&self.dataset_id, lsn).await?;
+        self.committed_lsn = Some(lsn);
+        Ok(())
+    }
+
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError> {
         self.client
             .insert_into_copied_tables(&self.dataset_id, table_id)
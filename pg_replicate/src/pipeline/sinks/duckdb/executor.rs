@@ -17,6 +17,7 @@ pub enum DuckDbRequest {
     CreateTables(HashMap<TableId, TableSchema>),
     InsertRow(TableRow, TableId),
     HandleCdcEvent(CdcEvent),
+    SetLastLsn(PgLsn),
     TableCopied(TableId),
     TruncateTable(TableId),
 }
@@ -26,6 +27,7 @@ pub enum DuckDbResponse {
     CreateTablesResponse(Result<(), DuckDbExecutorError>),
     InsertRowResponse(Result<(), DuckDbExecutorError>),
     HandleCdcEventResponse(Result<PgLsn, DuckDbExecutorError>),
+    SetLastLsnResponse(Result<(), DuckDbExecutorError>),
     TableCopiedResponse(Result<(), DuckDbExecutorError>),
     TruncateTableResponse(Result<(), DuckDbExecutorError>),
 }
@@ -114,8 +116,11 @@ impl DuckDbExecutor {
                             CdcEvent::Delete((table_id, table_row)) => {
                                 self.delete_row(table_id, table_row)
                             }
+                            CdcEvent::Origin(_) => Ok(()),
                             CdcEvent::Relation(_) => Ok(()),
-                            CdcEvent::KeepAliveRequested { reply: _ } => Ok(()),
+                            CdcEvent::Type(_) => Ok(()),
+                            CdcEvent::TableRemoved(_) => Ok(()),
+                            CdcEvent::KeepAliveRequested { .. } => Ok(()),
                         };
 
                         let committed_lsn = self.committed_lsn.expect("committed lsn is none");
@@ -123,6 +128,11 @@ impl DuckDbExecutor {
                         let response = DuckDbResponse::HandleCdcEventResponse(result);
                         self.send_response(response).await;
                     }
+                    DuckDbRequest::SetLastLsn(lsn) => {
+                        let result = self.set_last_lsn(lsn);
+                        let response = DuckDbResponse::SetLastLsnResponse(result);
+                        self.send_response(response).await;
+                    }
                     DuckDbRequest::TableCopied(table_id) => {
                         let result = self.table_copied(table_id);
                         let response = DuckDbResponse::TableCopiedResponse(result);
@@ -156,6 +166,8 @@ impl DuckDbExecutor {
             modifier: 0,
             nullable: false,
             identity: true,
+            default: None,
+            generated: false,
         }];
         self.client
             .create_schema_if_missing(&copied_tables_table_name.schema)?;
@@ -173,6 +185,8 @@ impl DuckDbExecutor {
             modifier: 0,
             nullable: false,
             identity: true,
+            default: None,
+            generated: false,
         }];
         if self
             .client
@@ -265,6 +279,12 @@ impl DuckDbExecutor {
         Ok(())
     }
 
+    fn set_last_lsn(&mut self, last_lsn: PgLsn) -> Result<(), DuckDbExecutorError> {
+        self.client.set_last_lsn(last_lsn)?;
+        self.committed_lsn = Some(last_lsn);
+        Ok(())
+    }
+
     fn set_last_lsn_and_commit_transaction(
         &self,
         last_lsn: PgLsn,
@@ -142,6 +142,17 @@ impl Sink for DuckDbSink {
         Ok(last_lsn)
     }
 
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+        let req = DuckDbRequest::SetLastLsn(lsn);
+        match self.execute(req).await? {
+            DuckDbResponse::SetLastLsnResponse(res) => {
+                let _ = res?;
+            }
+            _ => panic!("invalid response to SetLastLsn request"),
+        }
+        Ok(())
+    }
+
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError> {
         let req = DuckDbRequest::TableCopied(table_id);
         match self.execute(req).await? {
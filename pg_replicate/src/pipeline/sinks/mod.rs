@@ -60,6 +60,10 @@ pub trait Sink {
     ) -> Result<(), SinkError>;
     async fn write_table_row(&mut self, row: TableRow, table_id: TableId) -> Result<(), SinkError>;
     async fn write_cdc_event(&mut self, event: CdcEvent) -> Result<PgLsn, SinkError>;
+    /// Records `lsn` as the resumption state's last lsn without writing any changes.
+    /// The pipelines call it before confirming the server's wal end between
+    /// transactions, so that a resumed pipeline starts where the slot does.
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError>;
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError>;
     async fn truncate_table(&mut self, table_id: TableId) -> Result<(), SinkError>;
 }
@@ -77,6 +81,10 @@ pub trait BatchSink {
         table_id: TableId,
     ) -> Result<(), SinkError>;
     async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, SinkError>;
+    /// Records `lsn` as the resumption state's last lsn without writing any changes.
+    /// The pipelines call it before confirming the server's wal end between
+    /// transactions, so that a resumed pipeline starts where the slot does.
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError>;
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError>;
     async fn truncate_table(&mut self, table_id: TableId) -> Result<(), SinkError>;
 }
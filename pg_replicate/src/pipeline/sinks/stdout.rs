@@ -45,6 +45,11 @@ impl Sink for StdoutSink {
         Ok(PgLsn::from(0))
     }
 
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+        info!("last lsn {lsn}");
+        Ok(())
+    }
+
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError> {
         info!("table {table_id} copied");
         Ok(())
@@ -41,4 +41,9 @@ pub trait Source {
     async fn commit_transaction(&self) -> Result<(), SourceError>;
 
     async fn get_cdc_stream(&self, start_lsn: PgLsn) -> Result<CdcStream, SourceError>;
+
+    /// Returns the tables which have been removed from the source since the last
+    /// call, e.g. dropped from a publication, and forgets their schemas. Their
+    /// changes are no longer streamed.
+    async fn removed_tables(&mut self) -> Result<Vec<TableId>, SourceError>;
 }
@@ -1,37 +1,132 @@
 use std::{
     collections::HashMap,
+    future::Future,
     pin::Pin,
     task::{Context, Poll},
-    time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
 use futures::{ready, Stream};
 use pin_project_lite::pin_project;
+use postgres_protocol::message::backend::CommitBody;
 use thiserror::Error;
 use tokio_postgres::{
-    binary_copy::BinaryCopyOutStream,
-    replication::LogicalReplicationStream,
-    types::{PgLsn, Type},
+    binary_copy::BinaryCopyOutStream, config::Host, replication::LogicalReplicationStream,
+    types::PgLsn, Config, CopyOutStream,
 };
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
-    clients::postgres::{ReplicationClient, ReplicationClientError},
+    clients::postgres::{PublicationTableFilter, ReplicationClient, ReplicationClientError},
     conversions::{
         cdc_event::{CdcEvent, CdcEventConversionError, CdcEventConverter},
         table_row::{TableRow, TableRowConversionError, TableRowConverter},
+        UnknownMessagePolicy, UnsupportedTypePolicy,
     },
     table::{ColumnSchema, TableId, TableName, TableSchema},
 };
 
 use super::{Source, SourceError};
 
+/// Seconds from the Unix epoch to the Postgres epoch, 2000-01-01
+const TIME_SEC_CONVERSION: u64 = 946_684_800;
+
+/// How often a read replica's replay position is checked while waiting for it to
+/// catch up with the replication slot
+const REPLICA_REPLAY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait at most for a read replica to catch up with the replication
+/// slot, unless a [CopyConnection] sets another limit
+pub const DEFAULT_MAX_REPLICA_REPLAY_WAIT: Duration = Duration::from_secs(300);
+
+/// Returns the wall clock time elapsed since a transaction was committed in Postgres,
+/// i.e. the replication lag at the time its commit is processed. Zero is returned if
+/// the commit seems to be in the future because of clock skew.
+pub fn commit_lag(commit_body: &CommitBody) -> Duration {
+    let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
+    let commit_micros = u64::try_from(commit_body.timestamp()).unwrap_or(0);
+    let commit_time = postgres_epoch + Duration::from_micros(commit_micros);
+    SystemTime::now()
+        .duration_since(commit_time)
+        .unwrap_or_default()
+}
+
+/// Returns the hosts and ports of `config` for logging, e.g. `replica:5432`
+fn describe_hosts(config: &Config) -> String {
+    config
+        .get_hosts()
+        .iter()
+        .enumerate()
+        .map(|(i, host)| {
+            let host = match host {
+                Host::Tcp(host) => host.clone(),
+                #[cfg(unix)]
+                Host::Unix(path) => path.display().to_string(),
+            };
+            match config.get_ports().get(i).or(config.get_ports().first()) {
+                Some(port) => format!("{host}:{port}"),
+                None => host,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 pub enum TableNamesFrom {
     Vec(Vec<TableName>),
     Publication(String),
 }
 
+/// Replication slot used by the cdc stream
+pub struct ReplicationSlot {
+    pub name: String,
+    /// If true the slot is created when it doesn't exist yet, otherwise
+    /// a missing slot is reported as an error
+    pub create_if_missing: bool,
+    /// If true a newly created slot is temporary and is dropped by Postgres when
+    /// the source's connection closes. A pipeline using a temporary slot can't be
+    /// resumed after a restart.
+    pub temporary: bool,
+}
+
+/// Connection parameters of the connection which table copies use, e.g. to copy
+/// from a read replica or as a user with different privileges. Unset parameters
+/// default to those of the source's replication connection. A `host` starting with
+/// `/` is the directory of the server's unix socket.
+#[derive(Clone)]
+pub struct CopyConnection {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// How long to wait at most for a read replica to replay the WAL up to the
+    /// replication slot's position before the copies start
+    pub max_replay_wait: Duration,
+}
+
+impl Default for CopyConnection {
+    fn default() -> Self {
+        CopyConnection {
+            host: None,
+            port: None,
+            username: None,
+            password: None,
+            max_replay_wait: DEFAULT_MAX_REPLICA_REPLAY_WAIT,
+        }
+    }
+}
+
+/// Options of a [PostgresSource] which apply from the moment it connects
+#[derive(Clone, Default)]
+pub struct SourceOptions {
+    /// Cancels queries of the source which run longer than this, e.g. creating
+    /// the replication slot, loading the table schemas or starting a table copy
+    /// while another session holds a lock they wait for. The cdc stream is not
+    /// affected.
+    pub statement_timeout: Option<Duration>,
+}
+
 #[derive(Debug, Error)]
 pub enum PostgresSourceError {
     #[error("replication client error: {0}")]
@@ -42,13 +137,83 @@ pub enum PostgresSourceError {
 
     #[error("cdc stream can only be started with a slot_name")]
     MissingSlotName,
+
+    #[error("replication slot {0} doesn't exist")]
+    MissingSlot(String),
+
+    #[error("start lsn {start_lsn} is behind the restart lsn {restart_lsn} of the replication slot, which no longer retains the WAL before it")]
+    StartLsnBehindRestartLsn {
+        start_lsn: PgLsn,
+        restart_lsn: PgLsn,
+    },
+
+    #[error("start lsn {start_lsn} is behind the lsn {confirmed_flush_lsn} up to which the replication slot was confirmed, where Postgres would start instead")]
+    StartLsnBehindConfirmedLsn {
+        start_lsn: PgLsn,
+        confirmed_flush_lsn: PgLsn,
+    },
+
+    #[error("start lsn {start_lsn} is ahead of the server's WAL flush position {flush_lsn}")]
+    StartLsnAheadOfWal { start_lsn: PgLsn, flush_lsn: PgLsn },
+
+    #[error("the copy connection's server didn't replay WAL up to the replication slot's lsn {slot_lsn} within {max_wait:?}, it is at {replayed_lsn}")]
+    ReplicaReplayTimeout {
+        slot_lsn: PgLsn,
+        replayed_lsn: PgLsn,
+        max_wait: Duration,
+    },
+
+    #[error("timed out after {timeout:?} while {operation}, another session may hold a lock it waits for")]
+    StatementTimeout {
+        operation: String,
+        timeout: Duration,
+    },
+}
+
+/// Awaits `query`, which runs on `client`, and cancels it on the server if it
+/// doesn't complete within `timeout`
+async fn with_statement_timeout<T>(
+    client: &ReplicationClient,
+    timeout: Option<Duration>,
+    operation: impl FnOnce() -> String,
+    query: impl Future<Output = Result<T, PostgresSourceError>>,
+) -> Result<T, PostgresSourceError> {
+    let Some(timeout) = timeout else {
+        return query.await;
+    };
+    match tokio::time::timeout(timeout, query).await {
+        Ok(result) => result,
+        Err(_) => {
+            let operation = operation();
+            if let Err(e) = client.cancel_query().await {
+                warn!("failed to cancel the query {operation}: {e}");
+            }
+            Err(PostgresSourceError::StatementTimeout { operation, timeout })
+        }
+    }
 }
 
 pub struct PostgresSource {
+    /// Config the replication connection was made with
+    connection_config: Config,
     replication_client: ReplicationClient,
+    /// Client which table copies use instead of `replication_client`, connected
+    /// with the parameters of a [CopyConnection]
+    snapshot_client: Option<ReplicationClient>,
+    /// Client which reads the publication's tables while `replication_client` is
+    /// streaming changes, connected on first use
+    publication_client: Option<ReplicationClient>,
     table_schemas: HashMap<TableId, TableSchema>,
     slot_name: Option<String>,
     publication: Option<String>,
+    unsupported_type_policy: UnsupportedTypePolicy,
+    unknown_message_policy: UnknownMessagePolicy,
+    /// Row filters of the tables in the publication
+    row_filters: HashMap<TableName, String>,
+    /// Predicates which only table copies apply, set with
+    /// [PostgresSource::set_copy_filter]
+    copy_filters: HashMap<TableName, String>,
+    statement_timeout: Option<Duration>,
 }
 
 impl PostgresSource {
@@ -58,26 +223,376 @@ impl PostgresSource {
         database: &str,
         username: &str,
         password: Option<String>,
-        slot_name: Option<String>,
+        slot: Option<ReplicationSlot>,
+        table_names_from: TableNamesFrom,
+    ) -> Result<PostgresSource, PostgresSourceError> {
+        let mut connection_config = Config::new();
+        connection_config
+            .host(host)
+            .port(port)
+            .dbname(database)
+            .user(username);
+        if let Some(password) = password {
+            connection_config.password(password);
+        }
+        Self::new_with_config(connection_config, slot, table_names_from).await
+    }
+
+    /// Creates a source connected with the parameters in `connection_config`, e.g.
+    /// parsed from a connection string with [ReplicationClient::parse_connection_string]
+    pub async fn new_with_config(
+        connection_config: Config,
+        slot: Option<ReplicationSlot>,
+        table_names_from: TableNamesFrom,
+    ) -> Result<PostgresSource, PostgresSourceError> {
+        Self::new_with_options(
+            connection_config,
+            slot,
+            table_names_from,
+            SourceOptions::default(),
+        )
+        .await
+    }
+
+    /// Creates a source like [PostgresSource::new_with_config] which applies
+    /// `options` to the queries it makes while it is being created as well
+    pub async fn new_with_options(
+        connection_config: Config,
+        slot: Option<ReplicationSlot>,
         table_names_from: TableNamesFrom,
+        options: SourceOptions,
     ) -> Result<PostgresSource, PostgresSourceError> {
+        let statement_timeout = options.statement_timeout;
         let replication_client =
-            ReplicationClient::connect_no_tls(host, port, database, username, password).await?;
+            ReplicationClient::connect_with_config(connection_config.clone()).await?;
         replication_client.begin_readonly_transaction().await?;
-        if let Some(ref slot_name) = slot_name {
-            replication_client.get_or_create_slot(slot_name).await?;
+        if let Some(ref slot) = slot {
+            if slot.temporary {
+                warn!(
+                    "replication slot {} is temporary, resuming from a previous run won't be possible",
+                    slot.name
+                );
+            }
+            with_statement_timeout(
+                &replication_client,
+                statement_timeout,
+                || format!("getting or creating replication slot {}", slot.name),
+                async {
+                    if slot.create_if_missing {
+                        replication_client
+                            .get_or_create_slot(&slot.name, slot.temporary)
+                            .await?;
+                    } else if replication_client.get_slot(&slot.name).await?.is_none() {
+                        return Err(PostgresSourceError::MissingSlot(slot.name.clone()));
+                    }
+                    Ok(())
+                },
+            )
+            .await?;
         }
-        let (table_names, publication) =
-            Self::get_table_names_and_publication(&replication_client, table_names_from).await?;
-        let table_schemas = replication_client.get_table_schemas(&table_names).await?;
+        let slot_name = slot.map(|slot| slot.name);
+        let (table_schemas, row_filters, publication) = with_statement_timeout(
+            &replication_client,
+            statement_timeout,
+            || "loading the table schemas".to_string(),
+            async {
+                let (table_names, publication) =
+                    Self::get_table_names_and_publication(&replication_client, table_names_from)
+                        .await?;
+                let mut table_schemas = replication_client.get_table_schemas(&table_names).await?;
+                let mut row_filters = HashMap::new();
+                if let Some(ref publication) = publication {
+                    let filters = replication_client
+                        .get_publication_table_filters(publication)
+                        .await?;
+                    Self::apply_publication_table_filters(
+                        &mut table_schemas,
+                        &mut row_filters,
+                        filters,
+                    );
+                }
+                Ok((table_schemas, row_filters, publication))
+            },
+        )
+        .await?;
         Ok(PostgresSource {
+            connection_config,
             replication_client,
+            snapshot_client: None,
+            publication_client: None,
             table_schemas,
             publication,
             slot_name,
+            unsupported_type_policy: UnsupportedTypePolicy::default(),
+            unknown_message_policy: UnknownMessagePolicy::default(),
+            row_filters,
+            copy_filters: HashMap::new(),
+            statement_timeout,
         })
     }
 
+    /// Removes columns which a publication's column lists leave out from the table
+    /// schemas, since they are missing from the cdc stream's tuples, and collects the
+    /// row filters which table copies need to apply.
+    fn apply_publication_table_filters(
+        table_schemas: &mut HashMap<TableId, TableSchema>,
+        row_filters: &mut HashMap<TableName, String>,
+        filters: Vec<PublicationTableFilter>,
+    ) {
+        for filter in filters {
+            let Some(table_schema) = table_schemas
+                .values_mut()
+                .find(|s| s.table_name == filter.table_name)
+            else {
+                continue;
+            };
+
+            if let Some(column_names) = filter.column_names {
+                table_schema
+                    .column_schemas
+                    .retain(|c| column_names.contains(&c.name));
+            }
+
+            if let Some(row_filter) = filter.row_filter {
+                info!(
+                    "copying only rows of table {} which match the publication's row filter {row_filter}",
+                    filter.table_name
+                );
+                row_filters.insert(filter.table_name, row_filter);
+            }
+        }
+    }
+
+    /// Only copies the rows of `table_name` which match the SQL `predicate`, e.g.
+    /// `created_at > now() - interval '7 days'` to copy only a recent window of an
+    /// append-only table. The predicate is added to the table's publication row
+    /// filter and doesn't apply to the cdc stream.
+    ///
+    /// Rows outside of the window are missing from the copy, so the sink only has a
+    /// consistent copy of the table if it already has them from elsewhere. Updates
+    /// and deletes which the cdc stream later brings for such rows refer to rows the
+    /// sink didn't get from the copy.
+    pub fn set_copy_filter(&mut self, table_name: TableName, predicate: String) {
+        info!("copying only rows of table {table_name} which match {predicate}");
+        self.copy_filters.insert(table_name, predicate);
+    }
+
+    /// Sets what the table copy and cdc streams do with columns of unsupported types
+    pub fn set_unsupported_type_policy(&mut self, unsupported_type_policy: UnsupportedTypePolicy) {
+        self.unsupported_type_policy = unsupported_type_policy;
+    }
+
+    /// Sets what the cdc stream does with replication messages which it can't
+    /// convert into events
+    pub fn set_unknown_message_policy(&mut self, unknown_message_policy: UnknownMessagePolicy) {
+        self.unknown_message_policy = unknown_message_policy;
+    }
+
+    /// Copies tables over a separate connection made with `copy_connection`, e.g.
+    /// to a read replica of the source's server, instead of within the replication
+    /// slot's snapshot. The cdc stream still comes from the slot on the source's
+    /// server.
+    ///
+    /// The cdc stream starts at the slot's consistent point, so the copies must not
+    /// see an older state of the tables, or changes made in between would neither be
+    /// copied nor streamed. This waits until the replica has replayed the WAL up to
+    /// the slot's position before it starts the copies' transaction, so their
+    /// snapshot is at or after that position. A primary server is always caught up.
+    /// If the replica hasn't caught up within the copy connection's
+    /// `max_replay_wait`, [PostgresSourceError::ReplicaReplayTimeout] is returned.
+    ///
+    /// Unlike with the slot's snapshot, changes made between the slot's position and
+    /// the copies' snapshot are both copied and streamed, so they are applied twice.
+    /// The sink has to apply them idempotently: the BigQuery sink upserts rows by
+    /// primary key, but the DuckDB sink fails to insert a copied row again.
+    pub async fn set_copy_connection(
+        &mut self,
+        copy_connection: CopyConnection,
+    ) -> Result<(), PostgresSourceError> {
+        let max_replay_wait = copy_connection.max_replay_wait;
+        let copy_config = self.copy_connection_config(copy_connection);
+        let snapshot_client = ReplicationClient::connect_with_config(copy_config.clone()).await?;
+
+        if let Some(slot_name) = self.slot_name() {
+            let slot_info = self
+                .replication_client
+                .get_slot(slot_name)
+                .await?
+                .ok_or_else(|| PostgresSourceError::MissingSlot(slot_name.clone()))?;
+            let slot_lsn = slot_info.confirmed_flush_lsn;
+            let wait_start = Instant::now();
+            loop {
+                let replayed_lsn = snapshot_client.replayed_wal_lsn().await?;
+                if replayed_lsn >= slot_lsn {
+                    break;
+                }
+                if wait_start.elapsed() >= max_replay_wait {
+                    return Err(PostgresSourceError::ReplicaReplayTimeout {
+                        slot_lsn,
+                        replayed_lsn,
+                        max_wait: max_replay_wait,
+                    });
+                }
+                info!("waiting for the replica to replay WAL up to lsn {slot_lsn}, it is at {replayed_lsn}");
+                tokio::time::sleep(REPLICA_REPLAY_POLL_INTERVAL).await;
+            }
+        }
+
+        snapshot_client.begin_readonly_transaction().await?;
+        info!(
+            "copying tables from {} as {}",
+            describe_hosts(&copy_config),
+            copy_config.get_user().unwrap_or_default()
+        );
+        self.snapshot_client = Some(snapshot_client);
+        Ok(())
+    }
+
+    /// Returns the config of the replication connection with the parameters set in
+    /// `copy_connection` replaced
+    fn copy_connection_config(&self, copy_connection: CopyConnection) -> Config {
+        let config = &self.connection_config;
+        let mut copy_config = if copy_connection.host.is_none() && copy_connection.port.is_none() {
+            config.clone()
+        } else {
+            // hosts and ports are added to a config rather than replaced, so the
+            // config is made anew with the other parameters copied over
+            let mut copy_config = Config::new();
+            copy_config
+                .ssl_mode(config.get_ssl_mode())
+                .keepalives(config.get_keepalives())
+                .keepalives_idle(config.get_keepalives_idle())
+                .target_session_attrs(config.get_target_session_attrs())
+                .channel_binding(config.get_channel_binding());
+            if let Some(dbname) = config.get_dbname() {
+                copy_config.dbname(dbname);
+            }
+            if let Some(user) = config.get_user() {
+                copy_config.user(user);
+            }
+            if let Some(password) = config.get_password() {
+                copy_config.password(password);
+            }
+            if let Some(options) = config.get_options() {
+                copy_config.options(options);
+            }
+            if let Some(application_name) = config.get_application_name() {
+                copy_config.application_name(application_name);
+            }
+            if let Some(connect_timeout) = config.get_connect_timeout() {
+                copy_config.connect_timeout(*connect_timeout);
+            }
+            match copy_connection.host {
+                Some(host) => {
+                    copy_config.host(host);
+                }
+                None => {
+                    for host in config.get_hosts() {
+                        match host {
+                            Host::Tcp(host) => copy_config.host(host),
+                            #[cfg(unix)]
+                            Host::Unix(path) => copy_config.host_path(path),
+                        };
+                    }
+                }
+            }
+            match copy_connection.port {
+                Some(port) => {
+                    copy_config.port(port);
+                }
+                None => {
+                    for port in config.get_ports() {
+                        copy_config.port(*port);
+                    }
+                }
+            }
+            copy_config
+        };
+        if let Some(username) = copy_connection.username {
+            copy_config.user(username);
+        }
+        if let Some(password) = copy_connection.password {
+            copy_config.password(password);
+        }
+        copy_config
+    }
+
+    /// Returns the client which table copies use
+    fn copy_client(&self) -> &ReplicationClient {
+        self.snapshot_client
+            .as_ref()
+            .unwrap_or(&self.replication_client)
+    }
+
+    /// Checks that a cdc stream can be started at `start_lsn`. The server no longer
+    /// has the WAL before the slot's restart_lsn, so starting before it would fail.
+    /// Postgres starts a stream requested before the slot's confirmed_flush_lsn at
+    /// the confirmed_flush_lsn instead, so the changes in between would be skipped
+    /// without an error. Starting after the server's WAL flush position, e.g. at a resume lsn saved
+    /// before a failover to a lagging replica, would skip the changes written until
+    /// the server reaches it. That usually resolves itself once the server catches
+    /// up, so [PostgresSourceError::StartLsnAheadOfWal] can be retried.
+    pub async fn validate_start_lsn(&self, start_lsn: PgLsn) -> Result<(), PostgresSourceError> {
+        let slot_name = self
+            .slot_name()
+            .ok_or(PostgresSourceError::MissingSlotName)?;
+        let slot_info = self
+            .replication_client
+            .get_slot(slot_name)
+            .await?
+            .ok_or_else(|| PostgresSourceError::MissingSlot(slot_name.clone()))?;
+
+        if start_lsn < slot_info.restart_lsn {
+            return Err(PostgresSourceError::StartLsnBehindRestartLsn {
+                start_lsn,
+                restart_lsn: slot_info.restart_lsn,
+            });
+        }
+
+        if start_lsn < slot_info.confirmed_flush_lsn {
+            return Err(PostgresSourceError::StartLsnBehindConfirmedLsn {
+                start_lsn,
+                confirmed_flush_lsn: slot_info.confirmed_flush_lsn,
+            });
+        }
+
+        let flush_lsn = self.replication_client.current_wal_flush_lsn().await?;
+        if start_lsn > flush_lsn {
+            return Err(PostgresSourceError::StartLsnAheadOfWal {
+                start_lsn,
+                flush_lsn,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the server's current WAL flush position, e.g. to stop a cdc stream
+    /// once everything written before it started has been replicated. It must be
+    /// called before the cdc stream is started on the source's connection.
+    pub async fn current_wal_lsn(&self) -> Result<PgLsn, PostgresSourceError> {
+        Ok(self.replication_client.current_wal_flush_lsn().await?)
+    }
+
+    /// Returns the commit lsn of the first transaction which the source's slot has
+    /// yet to stream and which committed at or after `timestamp`, e.g.
+    /// `2024-05-01T15:00:00Z`. See [ReplicationClient::commit_lsn_since].
+    pub async fn commit_lsn_since(
+        &self,
+        timestamp: &str,
+    ) -> Result<Option<PgLsn>, PostgresSourceError> {
+        let slot_name = self
+            .slot_name()
+            .ok_or(PostgresSourceError::MissingSlotName)?;
+        let publication = self
+            .publication()
+            .ok_or(PostgresSourceError::MissingPublication)?;
+        Ok(self
+            .replication_client
+            .commit_lsn_since(slot_name, publication, timestamp)
+            .await?)
+    }
+
     fn publication(&self) -> Option<&String> {
         self.publication.as_ref()
     }
@@ -92,12 +607,18 @@ impl PostgresSource {
     ) -> Result<(Vec<TableName>, Option<String>), ReplicationClientError> {
         Ok(match table_names_from {
             TableNamesFrom::Vec(table_names) => (table_names, None),
-            TableNamesFrom::Publication(publication) => (
-                replication_client
+            TableNamesFrom::Publication(publication) => {
+                if !replication_client.publication_exists(&publication).await? {
+                    return Err(ReplicationClientError::MissingPublication(publication));
+                }
+                let table_names = replication_client
                     .get_publication_table_names(&publication)
-                    .await?,
-                Some(publication),
-            ),
+                    .await?;
+                if table_names.is_empty() {
+                    warn!("publication {publication} doesn't contain any tables");
+                }
+                (table_names, Some(publication))
+            }
         })
     }
 }
@@ -114,18 +635,37 @@ impl Source for PostgresSource {
         column_schemas: &[ColumnSchema],
     ) -> Result<TableCopyStream, SourceError> {
         info!("starting table copy stream for table {table_name}");
-        let column_types: Vec<Type> = column_schemas.iter().map(|c| c.typ.clone()).collect();
 
-        let stream = self
-            .replication_client
-            .get_table_copy_stream(table_name, &column_types)
-            .await
-            .map_err(PostgresSourceError::ReplicationClient)?;
+        let row_filter = match (
+            self.row_filters.get(table_name),
+            self.copy_filters.get(table_name),
+        ) {
+            (Some(row_filter), Some(copy_filter)) => {
+                Some(format!("({row_filter}) AND ({copy_filter})"))
+            }
+            (row_filter, copy_filter) => row_filter.or(copy_filter).cloned(),
+        };
+
+        let copy_client = self.copy_client();
+        let stream = with_statement_timeout(
+            copy_client,
+            self.statement_timeout,
+            || format!("starting the copy of table {table_name}"),
+            async {
+                let stream = copy_client
+                    .copy_table_rows(
+                        table_name,
+                        column_schemas,
+                        row_filter.as_deref(),
+                        self.unsupported_type_policy,
+                    )
+                    .await?;
+                Ok(stream)
+            },
+        )
+        .await?;
 
-        Ok(TableCopyStream {
-            stream,
-            column_schemas: column_schemas.to_vec(),
-        })
+        Ok(stream)
     }
 
     async fn commit_transaction(&self) -> Result<(), SourceError> {
@@ -133,6 +673,12 @@ impl Source for PostgresSource {
             .commit_txn()
             .await
             .map_err(PostgresSourceError::ReplicationClient)?;
+        if let Some(ref snapshot_client) = self.snapshot_client {
+            snapshot_client
+                .commit_txn()
+                .await
+                .map_err(PostgresSourceError::ReplicationClient)?;
+        }
         Ok(())
     }
 
@@ -150,15 +696,55 @@ impl Source for PostgresSource {
             .await
             .map_err(PostgresSourceError::ReplicationClient)?;
 
-        const TIME_SEC_CONVERSION: u64 = 946_684_800;
         let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
 
         Ok(CdcStream {
             stream,
             table_schemas: self.table_schemas.clone(),
             postgres_epoch,
+            unsupported_type_policy: self.unsupported_type_policy,
+            unknown_message_policy: self.unknown_message_policy,
         })
     }
+
+    async fn removed_tables(&mut self) -> Result<Vec<TableId>, SourceError> {
+        let Some(publication) = self.publication.clone() else {
+            return Ok(vec![]);
+        };
+
+        if self.publication_client.is_none() {
+            let publication_client =
+                ReplicationClient::connect_with_config(self.connection_config.clone())
+                    .await
+                    .map_err(PostgresSourceError::ReplicationClient)?;
+            self.publication_client = Some(publication_client);
+        }
+        let publication_client = self
+            .publication_client
+            .as_ref()
+            .expect("publication client is none");
+        let published_table_ids = publication_client
+            .get_publication_table_ids(&publication)
+            .await
+            .map_err(PostgresSourceError::ReplicationClient)?;
+
+        let removed_table_ids: Vec<TableId> = self
+            .table_schemas
+            .keys()
+            .filter(|table_id| !published_table_ids.contains(table_id))
+            .copied()
+            .collect();
+        for table_id in &removed_table_ids {
+            if let Some(table_schema) = self.table_schemas.remove(table_id) {
+                info!(
+                    "table {} was removed from publication {publication}",
+                    table_schema.table_name
+                );
+            }
+        }
+
+        Ok(removed_table_ids)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -170,12 +756,53 @@ pub enum TableCopyStreamError {
     ConversionError(TableRowConversionError),
 }
 
+pin_project! {
+    #[project = CopyOutStreamKindProj]
+    enum CopyOutStreamKind {
+        Binary {
+            #[pin]
+            stream: BinaryCopyOutStream,
+        },
+        Text {
+            #[pin]
+            stream: CopyOutStream,
+        },
+    }
+}
+
 pin_project! {
     #[must_use = "streams do nothing unless polled"]
     pub struct TableCopyStream {
         #[pin]
-        stream: BinaryCopyOutStream,
+        stream: CopyOutStreamKind,
         column_schemas: Vec<ColumnSchema>,
+        unsupported_type_policy: UnsupportedTypePolicy,
+    }
+}
+
+impl TableCopyStream {
+    pub(crate) fn binary(
+        stream: BinaryCopyOutStream,
+        column_schemas: &[ColumnSchema],
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> TableCopyStream {
+        TableCopyStream {
+            stream: CopyOutStreamKind::Binary { stream },
+            column_schemas: column_schemas.to_vec(),
+            unsupported_type_policy,
+        }
+    }
+
+    pub(crate) fn text(
+        stream: CopyOutStream,
+        column_schemas: &[ColumnSchema],
+        unsupported_type_policy: UnsupportedTypePolicy,
+    ) -> TableCopyStream {
+        TableCopyStream {
+            stream: CopyOutStreamKind::Text { stream },
+            column_schemas: column_schemas.to_vec(),
+            unsupported_type_policy,
+        }
     }
 }
 
@@ -184,16 +811,32 @@ impl Stream for TableCopyStream {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
-        match ready!(this.stream.poll_next(cx)) {
-            Some(Ok(row)) => match TableRowConverter::try_from(&row, this.column_schemas) {
-                Ok(row) => Poll::Ready(Some(Ok(row))),
-                Err(e) => {
-                    let e = TableCopyStreamError::ConversionError(e);
-                    Poll::Ready(Some(Err(e)))
-                }
+        let row = match this.stream.project() {
+            CopyOutStreamKindProj::Binary { stream } => match ready!(stream.poll_next(cx)) {
+                Some(Ok(row)) => TableRowConverter::try_from(
+                    &row,
+                    this.column_schemas,
+                    *this.unsupported_type_policy,
+                ),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
+            },
+            CopyOutStreamKindProj::Text { stream } => match ready!(stream.poll_next(cx)) {
+                Some(Ok(row)) => TableRowConverter::try_from_text(
+                    &row,
+                    this.column_schemas,
+                    *this.unsupported_type_policy,
+                ),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
             },
-            Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
-            None => Poll::Ready(None),
+        };
+        match row {
+            Ok(row) => Poll::Ready(Some(Ok(row))),
+            Err(e) => {
+                let e = TableCopyStreamError::ConversionError(e);
+                Poll::Ready(Some(Err(e)))
+            }
         }
     }
 }
@@ -214,6 +857,8 @@ pin_project! {
         stream: LogicalReplicationStream,
         table_schemas: HashMap<TableId, TableSchema>,
         postgres_epoch: SystemTime,
+        unsupported_type_policy: UnsupportedTypePolicy,
+        unknown_message_policy: UnknownMessagePolicy,
     }
 }
 
@@ -245,14 +890,29 @@ impl Stream for CdcStream {
     type Item = Result<CdcEvent, CdcStreamError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.project();
-        match ready!(this.stream.poll_next(cx)) {
-            Some(Ok(msg)) => match CdcEventConverter::try_from(msg, this.table_schemas) {
-                Ok(row) => Poll::Ready(Some(Ok(row))),
-                Err(e) => Poll::Ready(Some(Err(e.into()))),
-            },
-            Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
-            None => Poll::Ready(None),
+        let mut this = self.project();
+        loop {
+            return match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(msg)) => match CdcEventConverter::try_from(
+                    msg,
+                    this.table_schemas,
+                    *this.unsupported_type_policy,
+                ) {
+                    Ok(row) => Poll::Ready(Some(Ok(row))),
+                    // a skipped message within a transaction leaves its commit to
+                    // advance the lsn as usual
+                    Err(e)
+                        if e.is_unsupported_message()
+                            && *this.unknown_message_policy == UnknownMessagePolicy::Skip =>
+                    {
+                        warn!("skipping replication message: {e}");
+                        continue;
+                    }
+                    Err(e) => Poll::Ready(Some(Err(e.into()))),
+                },
+                Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
+                None => Poll::Ready(None),
+            };
         }
     }
 }
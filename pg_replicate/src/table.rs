@@ -4,7 +4,7 @@ use tokio_postgres::types::Type;
 
 use crate::escape::quote_identifier;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TableName {
     pub schema: String,
     pub name: String,
@@ -26,20 +26,55 @@ impl Display for TableName {
 
 type TypeModifier = i32;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ColumnSchema {
     pub name: String,
     pub typ: Type,
     pub modifier: TypeModifier,
     pub nullable: bool,
     pub identity: bool,
+    /// The column's default expression, e.g. `now()`, if it has one
+    pub default: Option<String>,
+    /// Whether the column is a stored generated column. Postgres before 18 doesn't
+    /// publish the values of generated columns, so their cells in cdc events are null
+    pub generated: bool,
 }
 
 pub type TableId = u32;
 
+/// How Postgres identifies the old row in updates and deletes of a table, set
+/// with `alter table ... replica identity`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicaIdentity {
+    /// The primary key columns
+    Default,
+    /// The columns of a unique index
+    Index,
+    /// All columns
+    Full,
+    /// No columns, updates and deletes of the table can't be published
+    Nothing,
+}
+
 #[derive(Debug, Clone)]
 pub struct TableSchema {
     pub table_name: TableName,
     pub table_id: TableId,
     pub column_schemas: Vec<ColumnSchema>,
+    /// Names of the primary key columns in key order, empty if the table has no primary key
+    pub primary_key: Vec<String>,
+    pub replica_identity: ReplicaIdentity,
+}
+
+impl TableSchema {
+    /// Names of the columns which identify a row in the key tuple of updates and
+    /// deletes, i.e. the columns with `identity` set. Other columns of a deleted
+    /// row are null unless the replica identity is [ReplicaIdentity::Full]
+    pub fn replica_identity_column_names(&self) -> Vec<&str> {
+        self.column_schemas
+            .iter()
+            .filter(|c| c.identity)
+            .map(|c| c.name.as_str())
+            .collect()
+    }
 }
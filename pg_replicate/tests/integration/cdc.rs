@@ -0,0 +1,1931 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use pg_replicate::{
+    conversions::{
+        cdc_event::CdcEvent,
+        geometry::{Geometry, Point},
+        table_row::{Cell, TableRow},
+        UnknownMessagePolicy,
+    },
+    pipeline::{
+        data_pipeline::DataPipeline,
+        sources::{
+            postgres::{PostgresSource, ReplicationSlot, TableNamesFrom},
+            Source,
+        },
+        FeedbackPolicy, LargeTransactionPolicy, PipelineAction, PipelineError, TransformEvent,
+    },
+    table::{ReplicaIdentity, TableName},
+};
+use serde_json::json;
+use tokio_postgres::types::PgLsn;
+
+use crate::{database::create_test_database, sink::CollectingSink};
+
+#[tokio::test]
+async fn inserts_updates_and_deletes_are_replicated() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, name text not null);
+            insert into items values (1, 'copied');
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // the slot's snapshot, which the table copy uses, was taken before these changes
+    database
+        .client
+        .batch_execute(
+            "insert into items values (2, 'inserted');
+            update items set name = 'updated' where id = 1;
+            delete from items where id = 2;",
+        )
+        .await
+        .expect("Failed to change table");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_schemas.len(), 1);
+    assert_eq!(collected.copied_tables.len(), 1);
+
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::String(name)] if name == "copied"
+    ));
+
+    let changes: Vec<&CdcEvent> = collected
+        .cdc_events
+        .iter()
+        .filter(|e| {
+            matches!(
+                e,
+                CdcEvent::Insert(_) | CdcEvent::Update(_) | CdcEvent::Delete(_)
+            )
+        })
+        .collect();
+    assert_eq!(changes.len(), 3);
+    assert!(matches!(
+        changes[0],
+        CdcEvent::Insert((_, row)) if matches!(
+            &row.values[..],
+            [Cell::I32(2), Cell::String(name)] if name == "inserted"
+        )
+    ));
+    assert!(matches!(
+        changes[1],
+        CdcEvent::Update((_, row)) if matches!(
+            &row.values[..],
+            [Cell::I32(1), Cell::String(name)] if name == "updated"
+        )
+    ));
+    // a delete only carries the replica identity, the primary key here
+    assert!(matches!(
+        changes[2],
+        CdcEvent::Delete((_, row)) if matches!(&row.values[..], [Cell::I32(2), Cell::Null])
+    ));
+}
+
+#[tokio::test]
+async fn deletes_carry_the_replica_identity_index_columns() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, code text not null, name text);
+            create unique index items_code on items (code);
+            alter table items replica identity using index items_code;
+            insert into items values (1, 'a', 'first');
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("delete from items where id = 1;")
+        .await
+        .expect("Failed to delete row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let table_schema = collected
+        .table_schemas
+        .values()
+        .next()
+        .expect("missing table schema");
+    assert_eq!(table_schema.replica_identity, ReplicaIdentity::Index);
+    assert_eq!(table_schema.replica_identity_column_names(), vec!["code"]);
+    assert_eq!(table_schema.primary_key, vec!["id".to_string()]);
+
+    let deleted_rows: Vec<&TableRow> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Delete((_, row)) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(deleted_rows.len(), 1);
+    assert!(matches!(
+        &deleted_rows[0].values[..],
+        [Cell::Null, Cell::String(code), Cell::Null] if code == "a"
+    ));
+}
+
+#[tokio::test]
+async fn generated_columns_keep_rows_aligned() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (
+                id int primary key,
+                doubled int generated always as (id * 2) stored,
+                name text not null
+            );
+            insert into items (id, name) values (1, 'copied');
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items (id, name) values (2, 'inserted');")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let table_schema = collected
+        .table_schemas
+        .values()
+        .next()
+        .expect("missing table schema");
+    let generated: Vec<bool> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.generated)
+        .collect();
+    assert_eq!(generated, vec![false, true, false]);
+
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::I32(2), Cell::String(name)] if name == "copied"
+    ));
+
+    let inserted_rows: Vec<&TableRow> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_rows.len(), 1);
+    // the generated value is only published by Postgres 18 and later
+    assert!(matches!(
+        &inserted_rows[0].values[..],
+        [Cell::I32(2), Cell::Null | Cell::I32(4), Cell::String(name)] if name == "inserted"
+    ));
+}
+
+#[tokio::test]
+async fn feedback_is_sent_while_the_stream_is_idle() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    let (sink, _) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_feedback_interval(Duration::from_millis(500));
+    pipeline.set_max_runtime(Duration::from_secs(4));
+
+    // Act
+    let reply_times = async {
+        let mut reply_times = vec![];
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            reply_times.push(database.last_reply_time(&database.name).await);
+        }
+        reply_times
+    };
+    let (result, reply_times) = tokio::join!(pipeline.start(), reply_times);
+
+    // Assert
+    result.expect("pipeline failed");
+    // no keepalive requests a reply this early, so only the feedback can advance it
+    assert!(reply_times.iter().all(Option::is_some), "{reply_times:?}");
+    assert!(
+        reply_times.windows(2).all(|w| w[0] < w[1]),
+        "{reply_times:?}"
+    );
+}
+
+#[tokio::test]
+async fn tables_with_special_characters_in_their_names_are_replicated() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            r#"create schema "Weird.Schema";
+            create table "Weird.Schema"."My Table" (id int primary key, "Odd ""Column""" text);
+            insert into "Weird.Schema"."My Table" values (1, 'copied');
+            create publication "My Publication" for table "Weird.Schema"."My Table";"#,
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("My Publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute(r#"insert into "Weird.Schema"."My Table" values (2, 'inserted');"#)
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let table_schema = collected
+        .table_schemas
+        .values()
+        .next()
+        .expect("missing table schema");
+    assert_eq!(table_schema.table_name.schema, "Weird.Schema");
+    assert_eq!(table_schema.table_name.name, "My Table");
+    assert_eq!(table_schema.column_schemas[1].name, r#"Odd "Column""#);
+
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::String(value)] if value == "copied"
+    ));
+
+    let inserted_rows: Vec<&TableRow> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_rows.len(), 1);
+    assert!(matches!(
+        &inserted_rows[0].values[..],
+        [Cell::I32(2), Cell::String(value)] if value == "inserted"
+    ));
+}
+
+#[tokio::test]
+async fn ranges_are_replicated_in_their_canonical_form() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, r int4range, ts tsrange, n numrange);
+            insert into items values
+                (1, '[1,10)', '[2024-01-01, 2024-01-02 12:00)', '[1.5,2.25)'),
+                (2, 'empty', null, null);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (3, '(,5]', '[2024-01-01,)', '(,10.10]');")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+
+    // ranges of supported subtypes are copied in the binary format
+    let mut copied_rows: Vec<&[Cell]> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| &row.values[..])
+        .collect();
+    copied_rows.sort_by_key(|values| match values[0] {
+        Cell::I32(id) => id,
+        _ => panic!("id isn't an int"),
+    });
+    assert_eq!(copied_rows.len(), 2);
+    assert!(matches!(
+        copied_rows[0],
+        [Cell::I32(1), Cell::String(r), Cell::String(ts), Cell::String(n)]
+            if r == "[1,10)"
+                && ts == r#"["2024-01-01 00:00:00","2024-01-02 12:00:00")"#
+                && n == "[1.5,2.25)"
+    ));
+    assert!(matches!(
+        copied_rows[1],
+        [Cell::I32(2), Cell::String(r), Cell::Null, Cell::Null] if r == "empty"
+    ));
+
+    // and streamed in the text format
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert!(matches!(
+        &inserted_row.values[..],
+        [Cell::I32(3), Cell::String(r), Cell::String(ts), Cell::String(n)]
+            if r == "(,6)" && ts == r#"["2024-01-01 00:00:00",)"# && n == "(,10.10]"
+    ));
+}
+
+#[tokio::test]
+async fn money_is_replicated_as_an_amount_in_cents() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, price money);
+            insert into items values (1, '$1,234.56'::money);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (2, '-$1,234.56'::money);")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::I64(123456)]
+    ));
+
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert!(matches!(
+        &inserted_row.values[..],
+        [Cell::I32(2), Cell::I64(-123456)]
+    ));
+}
+
+#[tokio::test]
+async fn numerics_are_replicated_exactly() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, amount numeric);
+            insert into items values (1, -12.3400), (2, 'NaN'), (3, 0.0000000001), (4, 100);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (5, -123456789.000100000);")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let mut copied_amounts: Vec<_> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| match &row.values[..] {
+            [Cell::I32(id), Cell::String(amount)] => (*id, amount.as_str()),
+            values => panic!("unexpected row {values:?}"),
+        })
+        .collect();
+    copied_amounts.sort();
+    assert_eq!(
+        copied_amounts,
+        [(1, "-12.3400"), (2, "NaN"), (3, "0.0000000001"), (4, "100")]
+    );
+
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert!(matches!(
+        &inserted_row.values[..],
+        [Cell::I32(5), Cell::String(amount)] if amount == "-123456789.000100000"
+    ));
+}
+
+#[tokio::test]
+async fn points_and_polygons_are_replicated_with_all_their_points() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table places (id int primary key, location point);
+            create table areas (id int primary key, outline polygon);
+            insert into places values (1, '(1.5,-2)');
+            insert into areas values (1, '((0,0),(1,1),(2,0))');
+            create publication test_publication for table places, areas;",
+        )
+        .await
+        .expect("Failed to create tables");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute(
+            "insert into places values (2, '(3,4)');
+            insert into areas values (2, '((0,0),(0,5),(5,5),(5,0))');",
+        )
+        .await
+        .expect("Failed to insert rows");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let point = |x, y| Point { x, y };
+    let collected = collected.lock().unwrap();
+    let copied_geometries: Vec<_> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| match &row.values[..] {
+            [Cell::I32(1), Cell::Geometry(geometry)] => geometry.clone(),
+            values => panic!("unexpected row {values:?}"),
+        })
+        .collect();
+    assert!(copied_geometries.contains(&Geometry::Point(point(1.5, -2.0))));
+    assert!(copied_geometries.contains(&Geometry::Polygon {
+        points: vec![point(0.0, 0.0), point(1.0, 1.0), point(2.0, 0.0)]
+    }));
+
+    let inserted_geometries: Vec<_> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => match &row.values[..] {
+                [Cell::I32(2), Cell::Geometry(geometry)] => Some(geometry.clone()),
+                values => panic!("unexpected row {values:?}"),
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        inserted_geometries,
+        [
+            Geometry::Point(point(3.0, 4.0)),
+            Geometry::Polygon {
+                points: vec![
+                    point(0.0, 0.0),
+                    point(0.0, 5.0),
+                    point(5.0, 5.0),
+                    point(5.0, 0.0)
+                ]
+            }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn nested_arrays_and_composites_are_replicated() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create type pair as (a int, b text);
+            create table items (id int primary key, grid int[][], pairs pair[]);
+            insert into items values (1, '{{1,2},{3,NULL}}', array[row(1, 'x'), null]::pair[]);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute(
+            "insert into items values (2, '{{NULL},{4}}', array[row(2, 'a \"b\", c'), row(null, null)]::pair[]);",
+        )
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let to_json = |row: &TableRow| serde_json::to_value(&row.values).unwrap();
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert_eq!(
+        to_json(copied_row),
+        json!([1, [[1, 2], [3, null]], [{"a": 1, "b": "x"}, null]])
+    );
+
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert_eq!(
+        to_json(inserted_row),
+        json!([2, [[null], [4]], [{"a": 2, "b": "a \"b\", c"}, {"a": null, "b": null}]])
+    );
+}
+
+#[tokio::test]
+async fn text_search_types_are_replicated_as_text() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, words tsvector);
+            insert into items values (1, 'a fat cat');
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (2, 'sat on a mat');")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::String(words)] if words == "'a' 'cat' 'fat'"
+    ));
+
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert!(matches!(
+        &inserted_row.values[..],
+        [Cell::I32(2), Cell::String(words)] if words == "'a' 'mat' 'on' 'sat'"
+    ));
+}
+
+#[tokio::test]
+async fn hstores_are_replicated_as_maps() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            r#"create extension hstore;
+            create table items (id int primary key, attributes hstore);
+            insert into items values (1, '"a"=>"1", "b"=>NULL');
+            create publication test_publication for table items;"#,
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute(r#"insert into items values (2, '"a"=>"1", "b"=>NULL');"#)
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let expected = BTreeMap::from([
+        ("a".to_string(), Some("1".to_string())),
+        ("b".to_string(), None),
+    ]);
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::Map(map)] if *map == expected
+    ));
+
+    let inserted_row = collected
+        .cdc_events
+        .iter()
+        .find_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .expect("missing insert");
+    assert!(matches!(
+        &inserted_row.values[..],
+        [Cell::I32(2), Cell::Map(map)] if *map == expected
+    ));
+}
+
+#[tokio::test]
+async fn changes_dropped_by_a_transform_keep_transactions_complete() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, name text not null);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // separate statements so that each insert is its own transaction
+    for (id, name) in [(1, "kept"), (2, "dropped")] {
+        database
+            .client
+            .execute("insert into items values ($1, $2);", &[&id, &name])
+            .await
+            .expect("Failed to insert row");
+    }
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+    pipeline.set_transform(|event| {
+        !matches!(
+            event,
+            TransformEvent::CdcEvent(CdcEvent::Insert((_, row)))
+                if matches!(&row.values[..], [_, Cell::String(name)] if name == "dropped")
+        )
+    });
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let inserted_ids: Vec<i32> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => match row.values[..] {
+                [Cell::I32(id), _] => Some(id),
+                _ => panic!("unexpected inserted row: {row:?}"),
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_ids, vec![1]);
+
+    // the transaction of the dropped insert is still written, so the sink's lsn
+    // advances to its commit
+    let begins = collected
+        .cdc_events
+        .iter()
+        .filter(|e| matches!(e, CdcEvent::Begin(_)))
+        .count();
+    let commit_lsns: Vec<PgLsn> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Commit(commit_body) => Some(commit_body.end_lsn().into()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(begins, 2);
+    assert_eq!(commit_lsns.len(), 2);
+    assert_eq!(collected.last_lsn, commit_lsns[1]);
+}
+
+#[tokio::test]
+async fn streaming_from_an_active_slot_fails_with_a_slot_active_error() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let create_source = |create_if_missing| {
+        PostgresSource::new(
+            &settings.host,
+            settings.port,
+            &database.name,
+            &settings.username,
+            Some(settings.password.clone()),
+            Some(ReplicationSlot {
+                name: database.name.clone(),
+                create_if_missing,
+                temporary: false,
+            }),
+            TableNamesFrom::Publication("test_publication".to_string()),
+        )
+    };
+    let active_source = create_source(true).await.expect("Failed to create source");
+    active_source
+        .commit_transaction()
+        .await
+        .expect("Failed to commit transaction");
+    let active_stream = active_source
+        .get_cdc_stream(PgLsn::from(0))
+        .await
+        .expect("Failed to start cdc stream");
+
+    let postgres_source = create_source(false).await.expect("Failed to create source");
+    let (sink, _) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+
+    // Act
+    let result = tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't fail");
+
+    // Assert
+    let error = result.expect_err("pipeline streamed from an active slot");
+    assert!(error.is_slot_active(), "unexpected error: {error}");
+
+    drop(active_stream);
+    drop(active_source);
+    database.wait_for_slot_release(&database.name).await;
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn unsupported_messages_are_skipped_with_the_skip_policy() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let mut postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    postgres_source.set_unknown_message_policy(UnknownMessagePolicy::Skip);
+
+    // the converter doesn't support truncate messages. Each statement is a
+    // transaction of its own.
+    let statements = [
+        "insert into items values (1);",
+        "truncate items;",
+        "insert into items values (2);",
+    ];
+    for statement in statements {
+        database
+            .client
+            .batch_execute(statement)
+            .await
+            .expect("Failed to change table");
+    }
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let inserted_ids: Vec<&Cell> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(&row.values[0]),
+            _ => None,
+        })
+        .collect();
+    assert!(matches!(inserted_ids[..], [Cell::I32(1), Cell::I32(2)]));
+    let commits = collected
+        .cdc_events
+        .iter()
+        .filter(|e| matches!(e, CdcEvent::Commit(_)))
+        .count();
+    assert_eq!(commits, 3);
+}
+
+#[tokio::test]
+async fn tables_removed_from_the_publication_are_reported() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create table other_items (id int primary key);
+            create publication test_publication for table items, other_items;",
+        )
+        .await
+        .expect("Failed to create tables");
+    let other_items_id: u32 = database
+        .client
+        .query_one("select 'other_items'::regclass::oid;", &[])
+        .await
+        .expect("Failed to get table id")
+        .get(0);
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    for statement in [
+        "insert into other_items values (1);",
+        "alter publication test_publication drop table other_items;",
+        "insert into other_items values (2);",
+        "insert into items values (1);",
+    ] {
+        database
+            .client
+            .batch_execute(statement)
+            .await
+            .expect("Failed to change tables");
+    }
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+    pipeline.set_publication_check_interval(Duration::ZERO);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let removed_tables: Vec<u32> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::TableRemoved(table_id) => Some(*table_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(removed_tables, [other_items_id]);
+
+    // only the insert made while the table was still published is streamed
+    let inserted_table_ids: Vec<u32> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((table_id, _)) => Some(*table_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_table_ids.len(), 2);
+    assert_eq!(inserted_table_ids[0], other_items_id);
+    assert_ne!(inserted_table_ids[1], other_items_id);
+}
+
+#[tokio::test]
+async fn copy_filters_only_apply_to_the_table_copy() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            insert into items values (1), (2), (3);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let mut postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    postgres_source.set_copy_filter(
+        TableName {
+            schema: "public".to_string(),
+            name: "items".to_string(),
+        },
+        "id >= 2".to_string(),
+    );
+
+    database
+        .client
+        .batch_execute("insert into items values (0);")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let mut copied_ids: Vec<i32> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| match row.values[..] {
+            [Cell::I32(id)] => id,
+            _ => panic!("unexpected copied row: {row:?}"),
+        })
+        .collect();
+    copied_ids.sort();
+    assert_eq!(copied_ids, vec![2, 3]);
+
+    // the streamed insert doesn't match the copy filter
+    let inserted_ids: Vec<i32> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => match row.values[..] {
+                [Cell::I32(id)] => Some(id),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_ids, vec![0]);
+}
+
+#[tokio::test]
+async fn domains_are_decoded_as_their_base_types() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create domain positive_int as int4 check (value > 0);
+            create domain short_name as varchar(10) check (value <> '');
+            create table items (id positive_int primary key, name short_name);
+            insert into items values (1, 'copied');
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (2, 'streamed');")
+        .await
+        .expect("Failed to insert row");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::Both);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, copied_row) = &collected.table_rows[0];
+    assert!(matches!(
+        &copied_row.values[..],
+        [Cell::I32(1), Cell::String(name)] if name == "copied"
+    ));
+
+    let inserted_rows: Vec<&TableRow> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_rows.len(), 1);
+    assert!(matches!(
+        &inserted_rows[0].values[..],
+        [Cell::I32(2), Cell::String(name)] if name == "streamed"
+    ));
+}
+
+/// Streams a change to a published table followed by one to an unpublished table
+/// and returns the lsn before the second change and the lsn the slot confirmed
+async fn confirmed_lsn_with_feedback_policy(feedback_policy: FeedbackPolicy) -> (PgLsn, PgLsn) {
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create table unpublished_items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create tables");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    let slot_lsn = database.confirmed_flush_lsn(&database.name).await;
+
+    database
+        .client
+        .batch_execute("insert into items values (1);")
+        .await
+        .expect("Failed to insert row");
+    let unpublished_change_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    database
+        .client
+        .batch_execute("insert into unpublished_items values (1);")
+        .await
+        .expect("Failed to insert row");
+
+    let (sink, _) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_feedback_policy(feedback_policy);
+    pipeline.set_max_runtime(Duration::from_secs(2));
+    pipeline.start().await.expect("pipeline failed");
+
+    // the server applies the final status update shortly after receiving it
+    for _ in 0..50 {
+        let confirmed_lsn = database.confirmed_flush_lsn(&database.name).await;
+        if confirmed_lsn > slot_lsn {
+            return (unpublished_change_lsn, confirmed_lsn);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the slot's confirmed lsn didn't advance");
+}
+
+#[tokio::test]
+async fn the_feedback_policy_decides_which_lsn_is_confirmed() {
+    // Act
+    let (flushed_change_lsn, flushed_confirmed_lsn) =
+        confirmed_lsn_with_feedback_policy(FeedbackPolicy::Flushed).await;
+    let (received_change_lsn, received_confirmed_lsn) =
+        confirmed_lsn_with_feedback_policy(FeedbackPolicy::Received).await;
+
+    // Assert
+    // only the transaction written to the sink is confirmed
+    assert!(
+        flushed_confirmed_lsn <= flushed_change_lsn,
+        "{flushed_confirmed_lsn} is beyond {flushed_change_lsn}"
+    );
+    // the keepalive after the unpublished change carries the server's wal end
+    assert!(
+        received_confirmed_lsn > received_change_lsn,
+        "{received_confirmed_lsn} isn't beyond {received_change_lsn}"
+    );
+}
+
+/// Streams a transaction inserting 5 rows with a limit of 3 rows per transaction
+/// and `transform` and returns the result of the pipeline and the number of inserts
+/// it wrote
+async fn stream_large_transaction(
+    policy: LargeTransactionPolicy,
+    transform: fn(&mut TransformEvent) -> bool,
+) -> (Result<(), PipelineError>, usize) {
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items select generate_series(1, 5);")
+        .await
+        .expect("Failed to insert rows");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+    pipeline.set_max_transaction_rows(3, policy);
+    pipeline.set_transform(transform);
+
+    let result = tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn");
+    let inserts = collected
+        .lock()
+        .unwrap()
+        .cdc_events
+        .iter()
+        .filter(|e| matches!(e, CdcEvent::Insert(_)))
+        .count();
+    (result, inserts)
+}
+
+#[tokio::test]
+async fn transactions_over_the_row_limit_fail_the_pipeline() {
+    // Act
+    let (result, inserts) = stream_large_transaction(LargeTransactionPolicy::Fail, |_| true).await;
+
+    // Assert
+    assert!(
+        matches!(
+            result,
+            Err(PipelineError::TransactionTooLarge { max_rows: 3, .. })
+        ),
+        "{result:?}"
+    );
+    assert_eq!(inserts, 3);
+}
+
+#[tokio::test]
+async fn transactions_over_the_row_limit_are_replicated_with_the_warn_policy() {
+    // Act
+    let (result, inserts) = stream_large_transaction(LargeTransactionPolicy::Warn, |_| true).await;
+
+    // Assert
+    result.expect("pipeline failed");
+    assert_eq!(inserts, 5);
+}
+
+#[tokio::test]
+async fn rows_dropped_by_a_transform_dont_count_towards_the_row_limit() {
+    // Act
+    let (result, inserts) = stream_large_transaction(LargeTransactionPolicy::Fail, |event| {
+        !matches!(
+            event,
+            TransformEvent::CdcEvent(CdcEvent::Insert((_, row)))
+                if matches!(row.values[..], [Cell::I32(id)] if id > 2)
+        )
+    })
+    .await;
+
+    // Assert
+    result.expect("pipeline failed");
+    assert_eq!(inserts, 2);
+}
+
+#[tokio::test]
+async fn tuples_are_decoded_in_the_column_order_of_the_relation_message() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, a text, b text, c text);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // the source loaded the columns id, a, b, c from the catalog, the tuples only
+    // have id, a, c and later id, a, c, b
+    database
+        .client
+        .batch_execute(
+            "alter table items drop column b;
+            insert into items values (1, 'a1', 'c1');
+            alter table items add column b text;
+            insert into items values (2, 'a2', 'c2', 'b2');",
+        )
+        .await
+        .expect("Failed to change table");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let inserted_rows: Vec<&TableRow> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => Some(row),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_rows.len(), 2);
+    assert!(matches!(
+        &inserted_rows[0].values[..],
+        [Cell::I32(1), Cell::String(a), Cell::String(c)] if a == "a1" && c == "c1"
+    ));
+    assert!(matches!(
+        &inserted_rows[1].values[..],
+        [Cell::I32(2), Cell::String(a), Cell::String(c), Cell::String(b)]
+            if a == "a2" && c == "c2" && b == "b2"
+    ));
+}
+
+#[tokio::test]
+async fn the_stream_stops_once_it_is_idle_for_the_idle_timeout() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (1);")
+        .await
+        .expect("Failed to insert row");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_idle_timeout(Duration::from_secs(2));
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't stop while idle")
+        .expect("pipeline failed");
+
+    // Assert
+    assert!(pipeline.stopped_idle());
+    let collected = collected.lock().unwrap();
+    assert!(
+        collected
+            .cdc_events
+            .iter()
+            .any(|e| matches!(e, CdcEvent::Insert(_))),
+        "the changes before the idle period are replicated"
+    );
+}
+
+#[tokio::test]
+async fn changes_from_skipped_origins_are_not_replicated() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, source text);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    let origin = database.name.clone();
+    let replicated_client = database.connect().await;
+    replicated_client
+        .batch_execute(&format!(
+            "select pg_replication_origin_create('{origin}');
+            select pg_replication_origin_session_setup('{origin}');
+            begin;
+            insert into items values (1, 'replicated');"
+        ))
+        .await
+        .expect("Failed to start replicated transaction");
+    database
+        .client
+        .batch_execute(
+            "begin;
+            insert into items values (2, 'local');",
+        )
+        .await
+        .expect("Failed to start local transaction");
+    replicated_client
+        .batch_execute(&format!(
+            "insert into items values (3, 'replicated');
+            commit;
+            select pg_replication_origin_session_reset(), pg_replication_origin_drop('{origin}');"
+        ))
+        .await
+        .expect("Failed to commit replicated transaction");
+    database
+        .client
+        .batch_execute("insert into items values (4, 'local'); commit;")
+        .await
+        .expect("Failed to commit local transaction");
+
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_skipped_origins(HashSet::from([origin]));
+    pipeline.set_stop_lsn(stop_lsn);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let inserted_ids: Vec<i32> = collected
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => match row.values[0] {
+                Cell::I32(id) => Some(id),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_ids, [2, 4]);
+    let commits = collected
+        .cdc_events
+        .iter()
+        .filter(|e| matches!(e, CdcEvent::Commit(_)))
+        .count();
+    assert_eq!(commits, 2, "the replicated transaction is still committed");
+}
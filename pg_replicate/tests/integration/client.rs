@@ -0,0 +1,50 @@
+use pg_replicate::clients::postgres::ReplicationClient;
+
+use crate::database::create_test_database;
+
+#[tokio::test]
+async fn current_wal_lsn_is_not_behind_the_confirmed_flush_lsn() {
+    // Arrange
+    let database = create_test_database().await;
+    // the slot is temporary so that it doesn't keep the test database from being dropped
+    database
+        .client
+        .query_one(
+            "select pg_create_logical_replication_slot($1, 'pgoutput', true);",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to create replication slot");
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            insert into items values (1);",
+        )
+        .await
+        .expect("Failed to write to table");
+
+    let settings = &database.settings;
+    let client = ReplicationClient::connect_no_tls(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+    )
+    .await
+    .expect("Failed to connect");
+
+    // Act
+    let confirmed_flush_lsn = client
+        .confirmed_flush_lsn(&database.name)
+        .await
+        .expect("Failed to get confirmed flush lsn");
+    let current_wal_lsn = client
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    // Assert
+    assert!(current_wal_lsn >= confirmed_flush_lsn);
+}
@@ -0,0 +1,345 @@
+use std::time::Duration;
+
+use pg_replicate::{
+    conversions::table_row::Cell,
+    pipeline::{
+        data_pipeline::DataPipeline,
+        sources::postgres::{CopyConnection, PostgresSource, ReplicationSlot, TableNamesFrom},
+        PipelineAction,
+    },
+};
+
+use crate::{database::create_test_database, sink::CollectingSink};
+
+#[tokio::test]
+async fn nulls_are_copied_for_every_type() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (
+                id int primary key,
+                b bool,
+                t text,
+                v varchar(10),
+                s smallint,
+                i int,
+                l bigint,
+                ts timestamp
+            );
+            insert into items values
+                (1, true, 'text', 'varchar', 1, 2, 3, '2024-01-02 03:04:05.678'),
+                (2, null, null, null, null, null, null, null);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::TableCopiesOnly);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("table copy didn't finish")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 2);
+    let mut rows: Vec<&[Cell]> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| &row.values[..])
+        .collect();
+    rows.sort_by_key(|values| match values[0] {
+        Cell::I32(id) => id,
+        _ => panic!("id isn't an int"),
+    });
+
+    assert!(matches!(
+        rows[0],
+        [
+            Cell::I32(1),
+            Cell::Bool(true),
+            Cell::String(t),
+            Cell::String(v),
+            Cell::I16(1),
+            Cell::I32(2),
+            Cell::I64(3),
+            Cell::TimeStamp(ts),
+        ] if t == "text" && v == "varchar" && ts == "2024-01-02 03:04:05.678"
+    ));
+    assert!(matches!(rows[1][0], Cell::I32(2)));
+    assert!(rows[1][1..].iter().all(|cell| matches!(cell, Cell::Null)));
+}
+
+// publication column lists need Postgres 15 or later
+#[tokio::test]
+async fn only_published_columns_are_copied() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, secret text, name text);
+            insert into items values (1, 'hidden', 'copied');
+            create publication test_publication for table items (id, name);",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::TableCopiesOnly);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("table copy didn't finish")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let table_schema = collected
+        .table_schemas
+        .values()
+        .next()
+        .expect("missing table schema");
+    let column_names: Vec<&str> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(column_names, vec!["id", "name"]);
+
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, row) = &collected.table_rows[0];
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(1), Cell::String(name)] if name == "copied"
+    ));
+}
+
+#[tokio::test]
+async fn tables_are_copied_from_the_snapshot_host() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            insert into items values (1);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let mut postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // the slot's snapshot doesn't see this row, a snapshot taken later does
+    database
+        .client
+        .execute("insert into items values (2);", &[])
+        .await
+        .expect("Failed to insert row");
+
+    // the same server stands in for a read replica
+    postgres_source
+        .set_copy_connection(CopyConnection::default())
+        .await
+        .expect("Failed to connect to the snapshot host");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::TableCopiesOnly);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("table copy didn't finish")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    let mut copied_ids: Vec<i32> = collected
+        .table_rows
+        .iter()
+        .map(|(_, row)| match row.values[..] {
+            [Cell::I32(id)] => id,
+            _ => panic!("unexpected copied row: {row:?}"),
+        })
+        .collect();
+    copied_ids.sort();
+    assert_eq!(copied_ids, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn tables_are_copied_with_the_copy_connections_credentials() {
+    // Arrange
+    let database = create_test_database().await;
+    // role names starting with pg_ are reserved
+    let copy_username = format!("copier_{}", database.name);
+    database
+        .client
+        .batch_execute(&format!(
+            "create table items (id int primary key);
+            insert into items values (1), (2);
+            create publication test_publication for table items;
+            create role {copy_username} login replication password 'copier';
+            grant select on items to {copy_username};
+            alter table items enable row level security;
+            create policy copier_sees_one_row on items for select to {copy_username} using (id = 1);"
+        ))
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let mut postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // the host and port default to the replication connection's
+    postgres_source
+        .set_copy_connection(CopyConnection {
+            username: Some(copy_username),
+            password: Some("copier".to_string()),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to make the copy connection");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::TableCopiesOnly);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("table copy didn't finish")
+        .expect("pipeline failed");
+
+    // Assert
+    // the row level security policy only applies to the copy connection's user
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 1);
+    let (_, row) = &collected.table_rows[0];
+    assert!(matches!(row.values[..], [Cell::I32(1)]));
+}
+
+#[tokio::test]
+async fn tables_are_copied_over_a_unix_socket() {
+    // Arrange
+    let database = create_test_database().await;
+    let settings = &database.settings;
+    let Some(ref socket_dir) = settings.socket_dir else {
+        eprintln!("skipping, PG_REPLICATE_TEST_DB_SOCKET_DIR is not set");
+        return;
+    };
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            insert into items values (1), (2);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let mut postgres_source = PostgresSource::new(
+        socket_dir,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to connect over the unix socket");
+
+    // setting the port makes a new copy config, which must keep the socket directory
+    postgres_source
+        .set_copy_connection(CopyConnection {
+            port: Some(settings.port),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to make the copy connection over the unix socket");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::TableCopiesOnly);
+
+    // Act
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("table copy didn't finish")
+        .expect("pipeline failed");
+
+    // Assert
+    let collected = collected.lock().unwrap();
+    assert_eq!(collected.table_rows.len(), 2);
+}
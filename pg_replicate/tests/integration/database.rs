@@ -0,0 +1,174 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio_postgres::{types::PgLsn, Client, NoTls};
+
+/// Connection details of the Postgres server the tests run against. It must run with
+/// `wal_level = logical`. Each value can be overridden with an environment variable.
+pub struct DatabaseSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    /// Directory of the server's unix socket, if the tests can reach it
+    pub socket_dir: Option<String>,
+}
+
+impl DatabaseSettings {
+    pub fn from_env() -> DatabaseSettings {
+        let var = |name: &str, default: &str| std::env::var(name).unwrap_or(default.to_string());
+        DatabaseSettings {
+            host: var("PG_REPLICATE_TEST_DB_HOST", "localhost"),
+            port: var("PG_REPLICATE_TEST_DB_PORT", "5432")
+                .parse()
+                .expect("PG_REPLICATE_TEST_DB_PORT is not a valid port"),
+            username: var("PG_REPLICATE_TEST_DB_USERNAME", "postgres"),
+            password: var("PG_REPLICATE_TEST_DB_PASSWORD", "postgres"),
+            socket_dir: std::env::var("PG_REPLICATE_TEST_DB_SOCKET_DIR").ok(),
+        }
+    }
+
+    async fn connect(&self, database: &str) -> Client {
+        let (client, connection) = tokio_postgres::Config::new()
+            .host(&self.host)
+            .port(self.port)
+            .user(&self.username)
+            .password(&self.password)
+            .dbname(database)
+            .connect(NoTls)
+            .await
+            .expect("Failed to connect to Postgres");
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                panic!("Postgres connection error: {e}");
+            }
+        });
+        client
+    }
+}
+
+pub struct TestDatabase {
+    pub settings: DatabaseSettings,
+    pub name: String,
+    pub client: Client,
+}
+
+/// Creates a new database with a unique name so that tests can run in parallel
+pub async fn create_test_database() -> TestDatabase {
+    let settings = DatabaseSettings::from_env();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_nanos();
+    let name = format!("pg_replicate_test_{nanos}");
+
+    let client = settings.connect("postgres").await;
+    client
+        .simple_query(&format!(r#"create database "{name}";"#))
+        .await
+        .expect("Failed to create database");
+
+    let client = settings.connect(&name).await;
+
+    TestDatabase {
+        settings,
+        name,
+        client,
+    }
+}
+
+impl TestDatabase {
+    /// Opens another connection to the database, e.g. to run concurrent transactions
+    pub async fn connect(&self) -> Client {
+        self.settings.connect(&self.name).await
+    }
+
+    /// Waits until no connection streams from the slot any more. A dropped
+    /// replication connection only releases the slot once the server notices.
+    pub async fn wait_for_slot_release(&self, slot_name: &str) {
+        for _ in 0..100 {
+            let row = self
+                .client
+                .query_one(
+                    "select active from pg_replication_slots where slot_name = $1;",
+                    &[&slot_name],
+                )
+                .await
+                .expect("Failed to get replication slot");
+            if !row.get::<_, bool>("active") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("replication slot {slot_name} wasn't released");
+    }
+
+    /// Returns when the server last received a status update from the connection
+    /// streaming from the slot, in seconds since the unix epoch
+    pub async fn last_reply_time(&self, slot_name: &str) -> Option<f64> {
+        let row = self
+            .client
+            .query_opt(
+                "select extract(epoch from r.reply_time)::float8 reply_time
+                from pg_stat_replication r
+                join pg_replication_slots s on (s.active_pid = r.pid)
+                where s.slot_name = $1;",
+                &[&slot_name],
+            )
+            .await
+            .expect("Failed to get replication status");
+        row.and_then(|row| row.get("reply_time"))
+    }
+
+    /// Returns the lsn up to which the slot's consumer confirmed having flushed changes
+    pub async fn confirmed_flush_lsn(&self, slot_name: &str) -> PgLsn {
+        self.client
+            .query_one(
+                "select confirmed_flush_lsn from pg_replication_slots where slot_name = $1;",
+                &[&slot_name],
+            )
+            .await
+            .expect("Failed to get replication slot")
+            .get("confirmed_flush_lsn")
+    }
+
+    pub async fn drop_replication_slot(&self, slot_name: &str) {
+        self.client
+            .execute("select pg_drop_replication_slot($1);", &[&slot_name])
+            .await
+            .expect("Failed to drop replication slot");
+    }
+}
+
+// This is not an actual test. It is only used to delete test databases.
+// Enabling it might interfere with other running tests, so keep the
+// #[ignore] attribute. But remember to temporarily comment it out before
+// running the test when you do want to cleanup the database.
+#[ignore]
+#[tokio::test]
+async fn delete_test_databases() {
+    let settings = DatabaseSettings::from_env();
+    let client = settings.connect("postgres").await;
+    // a database can't be dropped while a replication slot uses it
+    client
+        .execute(
+            "select pg_drop_replication_slot(slot_name) from pg_replication_slots
+            where database like 'pg_replicate_test_%' and not active;",
+            &[],
+        )
+        .await
+        .expect("Failed to delete replication slots");
+    let rows = client
+        .query(
+            "select datname from pg_database where datname like 'pg_replicate_test_%';",
+            &[],
+        )
+        .await
+        .expect("Failed to get databases");
+    for row in rows {
+        let name: String = row.get("datname");
+        client
+            .simple_query(&format!(r#"drop database "{name}" with (force);"#))
+            .await
+            .expect("Failed to delete database");
+    }
+}
@@ -0,0 +1,7 @@
+mod cdc;
+mod client;
+mod copy;
+mod database;
+mod resumption;
+mod sink;
+mod source;
@@ -0,0 +1,279 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::Cell},
+    pipeline::{
+        data_pipeline::DataPipeline,
+        sources::postgres::{PostgresSource, PostgresSourceError, ReplicationSlot, TableNamesFrom},
+        PipelineAction,
+    },
+};
+
+use crate::{
+    database::{create_test_database, TestDatabase},
+    sink::{Collected, CollectingSink, CrashPoint},
+};
+
+async fn create_source(database: &TestDatabase) -> PostgresSource {
+    let settings = &database.settings;
+    PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        // the slot has to outlive the crashed pipeline's connection
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: false,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source")
+}
+
+/// Replicates two transactions, crashing the sink at `crash_point` in the second
+/// one, then restarts the pipeline and checks that every insert arrived exactly once.
+async fn changes_are_replicated_once_after_crash(crash_point: CrashPoint) {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let postgres_source = create_source(&database).await;
+    // separate statements so that each insert is its own transaction
+    for id in [1, 2] {
+        database
+            .client
+            .execute("insert into items values ($1);", &[&id])
+            .await
+            .expect("Failed to insert row");
+    }
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(
+        postgres_source,
+        sink.crash_at(crash_point),
+        PipelineAction::Both,
+    );
+    pipeline.set_stop_lsn(stop_lsn);
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the crash point")
+        .expect_err("pipeline didn't crash");
+    drop(pipeline);
+    database.wait_for_slot_release(&database.name).await;
+
+    // Act
+    let postgres_source = create_source(&database).await;
+    let mut pipeline = DataPipeline::new(
+        postgres_source,
+        CollectingSink::resume(collected.clone()),
+        PipelineAction::Both,
+    );
+    pipeline.set_stop_lsn(stop_lsn);
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+    drop(pipeline);
+
+    // Assert
+    let inserted_ids: Vec<i32> = collected
+        .lock()
+        .unwrap()
+        .cdc_events
+        .iter()
+        .filter_map(|e| match e {
+            CdcEvent::Insert((_, row)) => match row.values[..] {
+                [Cell::I32(id)] => Some(id),
+                _ => panic!("unexpected inserted row: {row:?}"),
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inserted_ids, vec![1, 2], "crashed at {crash_point:?}");
+
+    database.wait_for_slot_release(&database.name).await;
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn changes_are_replicated_once_after_crash_at_begin() {
+    changes_are_replicated_once_after_crash(CrashPoint::Begin).await;
+}
+
+#[tokio::test]
+async fn changes_are_replicated_once_after_crash_at_insert() {
+    changes_are_replicated_once_after_crash(CrashPoint::Insert).await;
+}
+
+#[tokio::test]
+async fn changes_are_replicated_once_after_crash_at_commit() {
+    changes_are_replicated_once_after_crash(CrashPoint::Commit).await;
+}
+
+/// Validates the resume lsn of what `collected` has so far like the replicator
+/// does, then inserts a row and resumes a pipeline until it has replicated it.
+/// Returns the validation's result and the number of inserts collected.
+async fn resume_and_replicate_an_insert(
+    database: &TestDatabase,
+    collected: &Arc<Mutex<Collected>>,
+) -> (Result<(), PostgresSourceError>, usize) {
+    let resume_lsn = collected.lock().unwrap().last_lsn;
+    let postgres_source = create_source(database).await;
+    let validation = postgres_source.validate_start_lsn(resume_lsn).await;
+
+    database
+        .client
+        .batch_execute("insert into items values (1);")
+        .await
+        .expect("Failed to insert row");
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    let mut pipeline = DataPipeline::new(
+        postgres_source,
+        CollectingSink::resume(collected.clone()),
+        PipelineAction::CdcOnly,
+    );
+    pipeline.set_stop_lsn(stop_lsn);
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+    drop(pipeline);
+
+    let inserts = collected
+        .lock()
+        .unwrap()
+        .cdc_events
+        .iter()
+        .filter(|e| matches!(e, CdcEvent::Insert(_)))
+        .count();
+    (validation, inserts)
+}
+
+#[tokio::test]
+async fn a_pipeline_stopped_while_idle_resumes_at_the_confirmed_lsn() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create table unpublished_items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create tables");
+
+    let postgres_source = create_source(&database).await;
+    let slot_lsn = database.confirmed_flush_lsn(&database.name).await;
+    // moves the server's wal end past the slot without a change to replicate
+    database
+        .client
+        .batch_execute("insert into unpublished_items values (1);")
+        .await
+        .expect("Failed to insert row");
+    let stop_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_stop_lsn(stop_lsn);
+    tokio::time::timeout(Duration::from_secs(30), pipeline.start())
+        .await
+        .expect("pipeline didn't reach the stop lsn")
+        .expect("pipeline failed");
+    drop(pipeline);
+    database.wait_for_slot_release(&database.name).await;
+
+    // Act
+    let mut confirmed_lsn = slot_lsn;
+    for _ in 0..50 {
+        confirmed_lsn = database.confirmed_flush_lsn(&database.name).await;
+        if confirmed_lsn > slot_lsn {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let resume_lsn = collected.lock().unwrap().last_lsn;
+    let (validation, inserts) = resume_and_replicate_an_insert(&database, &collected).await;
+
+    // Assert
+    // the sink records the wal end confirmed while no transaction was streamed
+    assert!(
+        confirmed_lsn > slot_lsn,
+        "the slot's confirmed lsn didn't advance"
+    );
+    assert_eq!(resume_lsn, confirmed_lsn);
+    assert!(validation.is_ok(), "{validation:?}");
+    assert_eq!(inserts, 1);
+
+    database.wait_for_slot_release(&database.name).await;
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn a_pipeline_resumes_after_a_run_which_only_confirmed_heartbeats() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create table unpublished_items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create tables");
+
+    let postgres_source = create_source(&database).await;
+    let slot_lsn = database.confirmed_flush_lsn(&database.name).await;
+    // the server answers it with a keepalive carrying its wal end
+    database
+        .client
+        .batch_execute("insert into unpublished_items values (1);")
+        .await
+        .expect("Failed to insert row");
+
+    let (sink, collected) = CollectingSink::new();
+    let mut pipeline = DataPipeline::new(postgres_source, sink, PipelineAction::CdcOnly);
+    pipeline.set_heartbeat_interval(Duration::ZERO);
+    pipeline.set_max_runtime(Duration::from_secs(2));
+    pipeline.start().await.expect("pipeline failed");
+    drop(pipeline);
+    database.wait_for_slot_release(&database.name).await;
+
+    // Act
+    // nothing has been written since, so the resume lsn is the server's wal end
+    let resume_lsn = collected.lock().unwrap().last_lsn;
+    let (validation, inserts) = resume_and_replicate_an_insert(&database, &collected).await;
+
+    // Assert
+    assert!(resume_lsn > slot_lsn, "no heartbeat was confirmed");
+    assert!(validation.is_ok(), "{validation:?}");
+    assert_eq!(inserts, 1);
+
+    database.wait_for_slot_release(&database.name).await;
+    database.drop_replication_slot(&database.name).await;
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow},
+    pipeline::{
+        sinks::{Sink, SinkError},
+        PipelineResumptionState,
+    },
+    table::{TableId, TableSchema},
+};
+use tokio_postgres::types::PgLsn;
+
+/// What a [CollectingSink] received. It is shared with the test so that it can
+/// be inspected after the pipeline, which owns the sink, has finished.
+pub struct Collected {
+    pub table_schemas: HashMap<TableId, TableSchema>,
+    pub table_rows: Vec<(TableId, TableRow)>,
+    pub copied_tables: HashSet<TableId>,
+    /// Events of committed transactions and those received between transactions
+    pub cdc_events: Vec<CdcEvent>,
+    /// End lsn of the last committed transaction
+    pub last_lsn: PgLsn,
+}
+
+/// The event of the second transaction at which a [CollectingSink] fails to
+/// simulate a crash. The first transaction is committed by then.
+#[derive(Debug, Clone, Copy)]
+pub enum CrashPoint {
+    Begin,
+    Insert,
+    Commit,
+}
+
+impl CrashPoint {
+    fn matches(&self, event: &CdcEvent) -> bool {
+        matches!(
+            (self, event),
+            (CrashPoint::Begin, CdcEvent::Begin(_))
+                | (CrashPoint::Insert, CdcEvent::Insert(_))
+                | (CrashPoint::Commit, CdcEvent::Commit(_))
+        )
+    }
+}
+
+/// A sink which keeps everything written to it in memory. Like a transactional
+/// destination, events of a transaction only become visible once it commits.
+pub struct CollectingSink {
+    collected: Arc<Mutex<Collected>>,
+    pending: Vec<CdcEvent>,
+    in_transaction: bool,
+    committed_transactions: usize,
+    crash_point: Option<CrashPoint>,
+}
+
+impl CollectingSink {
+    pub fn new() -> (CollectingSink, Arc<Mutex<Collected>>) {
+        let collected = Arc::new(Mutex::new(Collected {
+            table_schemas: HashMap::new(),
+            table_rows: vec![],
+            copied_tables: HashSet::new(),
+            cdc_events: vec![],
+            last_lsn: PgLsn::from(0),
+        }));
+        (Self::resume(collected.clone()), collected)
+    }
+
+    /// Returns a sink which continues from what an earlier sink collected
+    pub fn resume(collected: Arc<Mutex<Collected>>) -> CollectingSink {
+        CollectingSink {
+            collected,
+            pending: vec![],
+            in_transaction: false,
+            committed_transactions: 0,
+            crash_point: None,
+        }
+    }
+
+    pub fn crash_at(mut self, crash_point: CrashPoint) -> CollectingSink {
+        self.crash_point = Some(crash_point);
+        self
+    }
+}
+
+#[async_trait]
+impl Sink for CollectingSink {
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, SinkError> {
+        let collected = self.collected.lock().unwrap();
+        Ok(PipelineResumptionState {
+            copied_tables: collected.copied_tables.clone(),
+            last_lsn: collected.last_lsn,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), SinkError> {
+        self.collected.lock().unwrap().table_schemas = table_schemas;
+        Ok(())
+    }
+
+    async fn write_table_row(&mut self, row: TableRow, table_id: TableId) -> Result<(), SinkError> {
+        self.collected
+            .lock()
+            .unwrap()
+            .table_rows
+            .push((table_id, row));
+        Ok(())
+    }
+
+    async fn write_cdc_event(&mut self, event: CdcEvent) -> Result<PgLsn, SinkError> {
+        if self.committed_transactions == 1 && self.crash_point.is_some_and(|c| c.matches(&event)) {
+            // the pipeline stops on any sink error
+            return Err(SinkError::NoResponseReceived);
+        }
+
+        let mut collected = self.collected.lock().unwrap();
+        match event {
+            CdcEvent::Begin(_) => {
+                self.in_transaction = true;
+                self.pending.push(event);
+            }
+            CdcEvent::Commit(ref commit_body) => {
+                self.in_transaction = false;
+                self.committed_transactions += 1;
+                collected.last_lsn = commit_body.end_lsn().into();
+                collected.cdc_events.append(&mut self.pending);
+                collected.cdc_events.push(event);
+            }
+            event if self.in_transaction => self.pending.push(event),
+            event => collected.cdc_events.push(event),
+        }
+
+        Ok(collected.last_lsn)
+    }
+
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+        self.collected.lock().unwrap().last_lsn = lsn;
+        Ok(())
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError> {
+        self.collected
+            .lock()
+            .unwrap()
+            .copied_tables
+            .insert(table_id);
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), SinkError> {
+        self.collected
+            .lock()
+            .unwrap()
+            .table_rows
+            .retain(|(id, _)| *id != table_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,793 @@
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use pg_replicate::{
+    clients::postgres::{ReplicationClient, ReplicationClientError},
+    conversions::{table_row::Cell, UnsupportedTypePolicy},
+    pipeline::sources::{
+        postgres::{
+            PostgresSource, PostgresSourceError, ReplicationSlot, SourceOptions, TableNamesFrom,
+        },
+        Source, SourceError,
+    },
+    table::TableName,
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::database::create_test_database;
+
+#[tokio::test]
+async fn current_wal_lsn_is_not_behind_the_slot() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // Act
+    let current_wal_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    // Assert
+    let restart_lsn: String = database
+        .client
+        .query_one(
+            "select restart_lsn::text from pg_replication_slots where slot_name = $1;",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to get replication slot")
+        .get("restart_lsn");
+    let restart_lsn: PgLsn = restart_lsn.parse().expect("invalid restart lsn");
+    assert!(current_wal_lsn >= restart_lsn);
+}
+
+#[tokio::test]
+async fn start_lsns_behind_the_slots_restart_lsn_are_rejected() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    let current_wal_lsn = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    // Act
+    // a resume lsn from before the slot was created, whose WAL it never had
+    let behind = postgres_source.validate_start_lsn(PgLsn::from(1)).await;
+    let ahead = postgres_source.validate_start_lsn(current_wal_lsn).await;
+
+    // Assert
+    assert!(
+        matches!(
+            behind,
+            Err(PostgresSourceError::StartLsnBehindRestartLsn { start_lsn, .. })
+                if start_lsn == PgLsn::from(1)
+        ),
+        "{behind:?}"
+    );
+    assert!(ahead.is_ok(), "{ahead:?}");
+}
+
+#[tokio::test]
+async fn start_lsns_behind_the_slots_confirmed_lsn_are_rejected() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        // a temporary slot stays active, so it couldn't be advanced
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: false,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    database
+        .client
+        .batch_execute("insert into items values (1);")
+        .await
+        .expect("Failed to insert row");
+    // confirms the insert as if it had been replicated, which leaves the slot's
+    // restart_lsn at the running transactions record before it
+    database
+        .client
+        .query_one(
+            "select pg_replication_slot_advance($1, pg_current_wal_lsn());",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to advance the replication slot");
+    let row = database
+        .client
+        .query_one(
+            "select restart_lsn, confirmed_flush_lsn from pg_replication_slots
+            where slot_name = $1;",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to query the replication slot");
+    let restart_lsn: PgLsn = row.get("restart_lsn");
+    let confirmed_flush_lsn: PgLsn = row.get("confirmed_flush_lsn");
+
+    // Act
+    // a resume lsn whose WAL the slot still has, but from before the insert
+    let behind = postgres_source.validate_start_lsn(restart_lsn).await;
+    let confirmed = postgres_source
+        .validate_start_lsn(confirmed_flush_lsn)
+        .await;
+
+    // Assert
+    assert!(restart_lsn < confirmed_flush_lsn);
+    assert!(
+        matches!(
+            behind,
+            Err(PostgresSourceError::StartLsnBehindConfirmedLsn { start_lsn, confirmed_flush_lsn: lsn })
+                if start_lsn == restart_lsn && lsn == confirmed_flush_lsn
+        ),
+        "{behind:?}"
+    );
+    assert!(confirmed.is_ok(), "{confirmed:?}");
+
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn slots_which_cant_be_streamed_from_are_reported() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+    // a physical slot has neither a confirmed lsn nor, until it is used, a restart lsn
+    database
+        .client
+        .execute(
+            "select pg_create_physical_replication_slot($1);",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to create physical replication slot");
+
+    // Act
+    let settings = &database.settings;
+    let result = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: false,
+            temporary: false,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await;
+
+    // Assert
+    assert!(
+        matches!(
+            result,
+            Err(PostgresSourceError::ReplicationClient(
+                ReplicationClientError::SlotNotConfirmed(ref name)
+            )) if *name == database.name
+        ),
+        "{:?}",
+        result.err()
+    );
+
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn start_lsns_ahead_of_the_servers_wal_are_accepted_once_it_catches_up() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+    let current_wal_lsn: u64 = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn")
+        .into();
+    let wal_segment_size: i64 = database
+        .client
+        .query_one(
+            "select setting::bigint from pg_settings where name = 'wal_segment_size';",
+            &[],
+        )
+        .await
+        .expect("Failed to get the wal segment size")
+        .get(0);
+    // the start of the next WAL segment, far enough ahead that WAL written by other
+    // tests in the meantime doesn't reach it
+    let wal_segment_size = wal_segment_size as u64;
+    let start_lsn = PgLsn::from((current_wal_lsn / wal_segment_size + 1) * wal_segment_size);
+
+    // Act
+    let before_catching_up = postgres_source.validate_start_lsn(start_lsn).await;
+    database
+        .client
+        .batch_execute(
+            "insert into items values (1);
+            select pg_switch_wal();",
+        )
+        .await
+        .expect("Failed to switch WAL segment");
+    let after_catching_up = postgres_source.validate_start_lsn(start_lsn).await;
+
+    // Assert
+    assert!(
+        matches!(
+            before_catching_up,
+            Err(PostgresSourceError::StartLsnAheadOfWal { start_lsn: lsn, .. }) if lsn == start_lsn
+        ),
+        "{before_catching_up:?}"
+    );
+    assert!(after_catching_up.is_ok(), "{after_catching_up:?}");
+}
+
+#[tokio::test]
+async fn commit_lsn_since_a_timestamp_is_that_of_the_first_later_transaction() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let postgres_source = PostgresSource::new(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    database
+        .client
+        .batch_execute("insert into items values (1);")
+        .await
+        .expect("Failed to insert row");
+    // commit timestamps are taken at commit, the sleep keeps them apart
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let since: String = database
+        .client
+        .query_one("select to_json(clock_timestamp()) #>> '{}';", &[])
+        .await
+        .expect("Failed to get timestamp")
+        .get(0);
+    let before_second_insert = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+    database
+        .client
+        .batch_execute("insert into items values (2);")
+        .await
+        .expect("Failed to insert row");
+    let after_second_insert = postgres_source
+        .current_wal_lsn()
+        .await
+        .expect("Failed to get current wal lsn");
+
+    // Act
+    let commit_lsn = postgres_source
+        .commit_lsn_since(&since)
+        .await
+        .expect("Failed to find commit lsn");
+    let future_commit_lsn = postgres_source
+        .commit_lsn_since("2999-01-01T00:00:00Z")
+        .await
+        .expect("Failed to find commit lsn");
+
+    // Assert
+    let commit_lsn = commit_lsn.expect("no transaction committed since the timestamp");
+    assert!(commit_lsn > before_second_insert);
+    assert!(commit_lsn < after_second_insert);
+    assert_eq!(future_commit_lsn, None);
+}
+
+#[tokio::test]
+async fn sources_connect_in_replication_mode_with_a_connection_string() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let connection_string = format!(
+        "postgres://{}:{}@{}:{}/{}?application_name=connection_string_test&replication=off",
+        settings.username, settings.password, settings.host, settings.port, database.name
+    );
+    let connection_config = ReplicationClient::parse_connection_string(&connection_string)
+        .expect("Failed to parse connection string");
+
+    // Act
+    let postgres_source = PostgresSource::new_with_config(
+        connection_config,
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Publication("test_publication".to_string()),
+    )
+    .await
+    .expect("Failed to create source");
+
+    // Assert
+    let walsenders: i64 = database
+        .client
+        .query_one(
+            "select count(*) from pg_stat_activity
+            where application_name = 'connection_string_test' and backend_type = 'walsender';",
+            &[],
+        )
+        .await
+        .expect("Failed to query pg_stat_activity")
+        .get(0);
+    assert_eq!(walsenders, 1);
+    assert_eq!(postgres_source.get_table_schemas().len(), 1);
+}
+
+#[tokio::test]
+async fn creating_a_slot_exposes_its_snapshot_name_and_consistent_point() {
+    // Arrange
+    let database = create_test_database().await;
+    let settings = &database.settings;
+    let replication_client = ReplicationClient::connect_no_tls(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+    )
+    .await
+    .expect("Failed to connect");
+
+    // Act
+    let created_slot = replication_client
+        .create_slot_if_missing(&database.name, "pgoutput")
+        .await
+        .expect("Failed to create slot")
+        .expect("slot already existed");
+
+    // Assert
+    let snapshot_name = replication_client.snapshot_name();
+    assert!(snapshot_name.is_some());
+    assert_eq!(snapshot_name, created_slot.snapshot_name);
+    assert_eq!(
+        replication_client.consistent_point(),
+        Some(created_slot.consistent_point)
+    );
+
+    replication_client
+        .drop_slot(&database.name, false)
+        .await
+        .expect("Failed to drop slot");
+}
+
+#[tokio::test]
+async fn creating_the_same_slot_twice_at_once_creates_it_once() {
+    // Arrange
+    let database = create_test_database().await;
+    let settings = &database.settings;
+    let connect = || {
+        ReplicationClient::connect_no_tls(
+            &settings.host,
+            settings.port,
+            &database.name,
+            &settings.username,
+            Some(settings.password.clone()),
+        )
+    };
+    let first_client = connect().await.expect("Failed to connect");
+    let second_client = connect().await.expect("Failed to connect");
+
+    // Act
+    let (first, second) = tokio::join!(
+        first_client.create_slot_if_missing(&database.name, "pgoutput"),
+        second_client.create_slot_if_missing(&database.name, "pgoutput"),
+    );
+    let again = first_client
+        .create_slot_if_missing(&database.name, "pgoutput")
+        .await;
+
+    // Assert
+    let first = first.expect("Failed to create slot");
+    let second = second.expect("Failed to create slot");
+    assert_eq!(
+        first.is_some() as u8 + second.is_some() as u8,
+        1,
+        "exactly one of the clients creates the slot"
+    );
+    assert!(matches!(again, Ok(None)), "{again:?}");
+
+    first_client
+        .drop_slot(&database.name, false)
+        .await
+        .expect("Failed to drop slot");
+}
+
+#[tokio::test]
+async fn sources_creating_the_same_slot_at_once_both_start() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key);
+            create publication test_publication for table items;",
+        )
+        .await
+        .expect("Failed to create table");
+    let settings = &database.settings;
+    let create_source = || {
+        PostgresSource::new(
+            &settings.host,
+            settings.port,
+            &database.name,
+            &settings.username,
+            Some(settings.password.clone()),
+            Some(ReplicationSlot {
+                name: database.name.clone(),
+                create_if_missing: true,
+                temporary: false,
+            }),
+            TableNamesFrom::Publication("test_publication".to_string()),
+        )
+    };
+
+    // Act
+    let (first, second) = tokio::join!(create_source(), create_source());
+
+    // Assert
+    let first = first.expect("Failed to create source");
+    let second = second.expect("Failed to create source");
+    let slots: i64 = database
+        .client
+        .query_one(
+            "select count(*) from pg_replication_slots where slot_name = $1;",
+            &[&database.name],
+        )
+        .await
+        .expect("Failed to count replication slots")
+        .get(0);
+    assert_eq!(slots, 1);
+
+    drop((first, second));
+    database.wait_for_slot_release(&database.name).await;
+    database.drop_replication_slot(&database.name).await;
+}
+
+#[tokio::test]
+async fn table_copies_blocked_by_a_lock_time_out() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute("create table items (id int primary key);")
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let mut connection_config = tokio_postgres::Config::new();
+    connection_config
+        .host(&settings.host)
+        .port(settings.port)
+        .dbname(&database.name)
+        .user(&settings.username)
+        .password(&settings.password);
+    let table_name = TableName {
+        schema: "public".to_string(),
+        name: "items".to_string(),
+    };
+    let postgres_source = PostgresSource::new_with_options(
+        connection_config,
+        None,
+        TableNamesFrom::Vec(vec![table_name.clone()]),
+        SourceOptions {
+            statement_timeout: Some(Duration::from_secs(1)),
+        },
+    )
+    .await
+    .expect("Failed to create source");
+    let table_schema = postgres_source
+        .get_table_schemas()
+        .values()
+        .next()
+        .expect("missing table schema");
+
+    let locking_client = database.connect().await;
+    locking_client
+        .batch_execute("begin; lock table items in access exclusive mode;")
+        .await
+        .expect("Failed to lock table");
+
+    // Act
+    let result = postgres_source
+        .get_table_copy_stream(&table_name, &table_schema.column_schemas)
+        .await;
+
+    // Assert
+    let Err(SourceError::Postgres(PostgresSourceError::StatementTimeout { operation, timeout })) =
+        result
+    else {
+        panic!("table copy didn't time out");
+    };
+    assert_eq!(operation, "starting the copy of table public.items");
+    assert_eq!(timeout, Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn slot_creation_blocked_by_a_running_transaction_times_out() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute("create table items (id int primary key);")
+        .await
+        .expect("Failed to create table");
+
+    // creating a slot waits for running transactions which have an xid
+    let transaction_client = database.connect().await;
+    transaction_client
+        .batch_execute("begin; insert into items values (1);")
+        .await
+        .expect("Failed to start transaction");
+
+    let settings = &database.settings;
+    let mut connection_config = tokio_postgres::Config::new();
+    connection_config
+        .host(&settings.host)
+        .port(settings.port)
+        .dbname(&database.name)
+        .user(&settings.username)
+        .password(&settings.password);
+
+    // Act
+    let result = PostgresSource::new_with_options(
+        connection_config,
+        Some(ReplicationSlot {
+            name: database.name.clone(),
+            create_if_missing: true,
+            temporary: true,
+        }),
+        TableNamesFrom::Vec(vec![]),
+        SourceOptions {
+            statement_timeout: Some(Duration::from_secs(1)),
+        },
+    )
+    .await;
+
+    // Assert
+    let Err(PostgresSourceError::StatementTimeout { operation, .. }) = result else {
+        panic!("slot creation didn't time out");
+    };
+    assert_eq!(
+        operation,
+        format!("getting or creating replication slot {}", database.name)
+    );
+}
+
+#[tokio::test]
+async fn copied_table_rows_are_decoded() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, name text);
+            insert into items values (1, 'one'), (2, null);",
+        )
+        .await
+        .expect("Failed to create table");
+
+    let settings = &database.settings;
+    let replication_client = ReplicationClient::connect_no_tls(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+    )
+    .await
+    .expect("Failed to connect");
+    let table_name = TableName {
+        schema: "public".to_string(),
+        name: "items".to_string(),
+    };
+    let table_schemas = replication_client
+        .get_table_schemas(std::slice::from_ref(&table_name))
+        .await
+        .expect("Failed to get table schemas");
+    let table_schema = table_schemas.values().next().expect("missing table schema");
+
+    // Act
+    let rows: Vec<_> = replication_client
+        .copy_table_rows(
+            &table_name,
+            &table_schema.column_schemas,
+            None,
+            UnsupportedTypePolicy::Fail,
+        )
+        .await
+        .expect("Failed to start table copy")
+        .try_collect()
+        .await
+        .expect("Failed to copy table");
+
+    // Assert
+    assert_eq!(rows.len(), 2);
+    assert!(matches!(
+        &rows[0].values[..],
+        [Cell::I32(1), Cell::String(name)] if name == "one"
+    ));
+    assert!(matches!(&rows[1].values[..], [Cell::I32(2), Cell::Null]));
+}
+
+#[tokio::test]
+async fn all_columns_of_unsupported_types_are_reported() {
+    // Arrange
+    let database = create_test_database().await;
+    database
+        .client
+        .batch_execute(
+            "create table items (id int primary key, doc json, name text);
+            create table prices (id int primary key, amount float8, tag uuid);",
+        )
+        .await
+        .expect("Failed to create tables");
+
+    let settings = &database.settings;
+    let replication_client = ReplicationClient::connect_no_tls(
+        &settings.host,
+        settings.port,
+        &database.name,
+        &settings.username,
+        Some(settings.password.clone()),
+    )
+    .await
+    .expect("Failed to connect");
+    let table_name = |name: &str| TableName {
+        schema: "public".to_string(),
+        name: name.to_string(),
+    };
+    let table_schemas = replication_client
+        .get_table_schemas(&[table_name("items"), table_name("prices")])
+        .await
+        .expect("Failed to get table schemas");
+
+    // Act
+    let unsupported_columns = ReplicationClient::check_supported_types(table_schemas.values())
+        .expect_err("unsupported columns weren't reported");
+
+    // Assert
+    let mut unsupported_columns: Vec<_> = unsupported_columns
+        .iter()
+        .map(|c| (c.table_name.name.as_str(), c.column_name.as_str(), &c.typ))
+        .collect();
+    unsupported_columns.sort_by_key(|&(table, column, _)| (table, column));
+    assert_eq!(
+        unsupported_columns,
+        [
+            ("items", "doc", &Type::JSON),
+            ("prices", "amount", &Type::FLOAT8),
+            ("prices", "tag", &Type::UUID),
+        ]
+    );
+}
@@ -1,16 +1,304 @@
-use std::{error::Error, time::Duration};
-
-use configuration::{get_configuration, BatchSettings, SinkSettings, SourceSettings};
-use pg_replicate::pipeline::{
-    batching::{data_pipeline::BatchDataPipeline, BatchConfig},
-    sinks::bigquery::BigQueryBatchSink,
-    sources::postgres::{PostgresSource, TableNamesFrom},
-    PipelineAction,
+use std::{
+    error::Error,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tracing::{error, info};
+
+use clap::{Parser, ValueEnum};
+use configuration::{get_configuration, BatchSettings, Settings, SinkSettings, SourceSettings};
+use pg_replicate::{
+    clients::postgres::ReplicationClient,
+    conversions::UnknownMessagePolicy,
+    pipeline::{
+        batching::{data_pipeline::BatchDataPipeline, BatchConfig},
+        sinks::{bigquery::BigQueryBatchSink, BatchSink},
+        sources::{
+            postgres::{
+                CopyConnection, PostgresSource, PostgresSourceError, ReplicationSlot,
+                SourceOptions, TableNamesFrom,
+            },
+            Source,
+        },
+        CdcOperation, FeedbackPolicy, LargeTransactionPolicy, PipelineAction, PipelineError,
+    },
+    table::TableName,
+};
+use retry::{is_retryable, CircuitBreaker, RetryPolicy};
+use status::StatusFileSink;
+use tokio_postgres::types::PgLsn;
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod configuration;
+mod retry;
+mod status;
+
+/// Exit code when the replication slot is in use by another connection, EX_TEMPFAIL
+/// from sysexits.h, so that orchestrators can tell it apart from other failures
+const SLOT_ACTIVE_EXIT_CODE: i32 = 75;
+
+/// Exit code when --idle-timeout-secs passed without any changes to replicate, so
+/// that a scheduled runner can tell that there was nothing to do
+const IDLE_EXIT_CODE: i32 = 3;
+
+/// A run which fails after lasting at least this long counts as healthy, so it
+/// resets the retry budget and the circuit breaker
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/// How often to check whether the server's WAL has caught up with the lsn the
+/// destination resumes at
+const RESUME_LSN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+#[command(name = "replicator", version, about)]
+struct AppArgs {
+    /// Path to a yaml or toml file with settings which override the ones in the configuration directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Connect to Postgres with this connection string, e.g.
+    /// postgres://user@host:5432/db?connect_timeout=10, instead of the source's host,
+    /// port, name and username settings, which only fill in the ones it lacks. The
+    /// source's password overrides the one in the url
+    #[arg(long)]
+    db_url: Option<String>,
+
+    /// Connect to Postgres on this host instead of the source's host setting. A path
+    /// starting with / is the directory of the server's unix socket, e.g.
+    /// /var/run/postgresql, for connecting without TCP when running next to Postgres
+    #[arg(long)]
+    db_host: Option<String>,
+
+    /// Name the replicator's connections show up with in pg_stat_activity. Defaults
+    /// to the db url's application_name, then to pg_replicate/<slot name>
+    #[arg(long)]
+    application_name: Option<String>,
+
+    /// Connect to Postgres and check that the columns of all published tables have
+    /// supported types, then exit without creating a slot or writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// What the replicator should do
+    #[arg(long, value_enum, default_value_t = Mode::Replicate)]
+    mode: Mode,
+
+    /// Exit once changes up to this lsn, e.g. 16/B374D848, have been replicated
+    #[arg(long, value_parser = parse_lsn, conflicts_with = "stop_at_current")]
+    stop_at_lsn: Option<PgLsn>,
+
+    /// Exit once changes up to the server's WAL position at startup have been replicated
+    #[arg(long)]
+    stop_at_current: bool,
+
+    /// Only stream changes committed at or after this time, e.g.
+    /// 2024-05-01T15:00:00Z, instead of resuming from the destination's position.
+    /// Changes which the replication slot has already confirmed can't be streamed
+    /// again. Finding the position reads through the changes the slot retains
+    #[arg(long)]
+    since_timestamp: Option<String>,
+
+    /// Exit after streaming changes for this many seconds
+    #[arg(long)]
+    max_runtime_secs: Option<u64>,
+
+    /// Exit with code 3 once Postgres has sent nothing but keepalives for this many
+    /// seconds, e.g. for a scheduled job to stop when there is nothing to replicate
+    #[arg(long)]
+    idle_timeout_secs: Option<u64>,
+
+    /// Report replication progress to Postgres at least this often, even while idle
+    #[arg(long)]
+    feedback_interval_secs: Option<u64>,
+
+    /// Confirm the server's wal end to Postgres at most every this many seconds while
+    /// no transaction is in progress, so that WAL isn't retained while the published
+    /// tables see no changes. The confirmed lsn is also saved as the resume lsn.
+    #[arg(long)]
+    heartbeat_interval_secs: Option<u64>,
+
+    /// Check which tables are still published this often, in seconds, and stop
+    /// replicating tables which were removed from the publication
+    #[arg(long)]
+    publication_check_interval_secs: Option<u64>,
+
+    /// Reply to every keepalive from Postgres, not only to those requesting a reply
+    #[arg(long)]
+    always_reply_keepalive: bool,
+
+    /// Which lsn to confirm to Postgres. received lets Postgres free WAL as soon as
+    /// changes arrive, but changes not yet written to BigQuery when the replicator
+    /// crashes are lost. flushed only confirms changes written to BigQuery, which is
+    /// safe but makes Postgres retain more WAL
+    #[arg(long, value_enum, default_value_t = Feedback::Flushed)]
+    feedback_policy: Feedback,
+
+    /// Keep a json file with the last replicated lsn, the number of batches written,
+    /// which tables have been copied and the row counts and durations of table copies
+    /// up to date, for monitoring
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+
+    /// Copy the tables from this host, e.g. a read replica of the source, instead of
+    /// from the replication slot's snapshot. Changes streamed from the slot which the
+    /// copy already contains are applied again. Overrides the host of the source's
+    /// copy settings
+    #[arg(long)]
+    snapshot_host: Option<String>,
+
+    /// Port of the snapshot host. Defaults to the port of the source's copy settings,
+    /// then to the source's port
+    #[arg(long, requires = "snapshot_host")]
+    snapshot_port: Option<u16>,
+
+    /// Only copy the rows of a table which match a SQL predicate, e.g.
+    /// "public.events:created_at > '2024-05-01'" to copy a recent window of a huge
+    /// append-only table. Can be given once per table. Changes are streamed for all
+    /// rows, so the destination only has a consistent copy of the table if it
+    /// already has the older rows from elsewhere
+    #[arg(long, value_parser = parse_copy_where)]
+    copy_where: Vec<(TableName, String)>,
+
+    /// How often to retry when another connection is streaming from the replication
+    /// slot, e.g. while the previous instance shuts down. The replicator exits with
+    /// code 75 if the slot is still in use after the last retry
+    #[arg(long, default_value_t = 0)]
+    slot_active_retries: u32,
+
+    /// Seconds to wait before the first retry when the replication slot is in use,
+    /// doubled for each further retry
+    #[arg(long, default_value_t = 5)]
+    slot_active_retry_delay_secs: u64,
+
+    /// How often to run the replication, including the first run, when it fails
+    /// with an error from Postgres, e.g. a dropped connection or a server restart
+    #[arg(long, default_value_t = 1)]
+    retry_max_attempts: u32,
+
+    /// Seconds to wait before the first retry after an error from Postgres, doubled
+    /// for each further retry
+    #[arg(long, default_value_t = 1)]
+    retry_base_delay_secs: u64,
+
+    /// Longest wait between retries after errors from Postgres, in seconds
+    #[arg(long, default_value_t = 60)]
+    retry_max_delay_secs: u64,
+
+    /// Fraction by which retry delays are randomly lengthened or shortened
+    #[arg(long, default_value_t = 0.2)]
+    retry_jitter: f64,
+
+    /// Pause retries for --breaker-cooldown-secs after this many consecutive
+    /// failures. 0 never pauses
+    #[arg(long, default_value_t = 5)]
+    breaker_failure_threshold: u32,
+
+    /// Seconds to pause retries for once the consecutive failures reach
+    /// --breaker-failure-threshold
+    #[arg(long, default_value_t = 300)]
+    breaker_cooldown_secs: u64,
+
+    /// What to do with a replication message which the replicator can't handle,
+    /// e.g. one added by a newer Postgres version. Skipped messages are logged
+    #[arg(long, value_enum, default_value_t = OnUnknownMessage::Fail)]
+    on_unknown_message: OnUnknownMessage,
+
+    /// Copy all tables again when the replication slot can't stream the changes
+    /// after the lsn at which the destination would resume, because it no longer has
+    /// their WAL or was confirmed beyond it, instead of exiting with an error. Only
+    /// in replicate mode
+    #[arg(long)]
+    resnapshot_on_gap: bool,
+
+    /// Seconds to wait for Postgres to catch up when the destination resumes at an
+    /// lsn ahead of its WAL, e.g. after failing over to a replica which lagged
+    /// behind, before failing with an error
+    #[arg(long, default_value_t = 30)]
+    resume_lsn_max_wait_secs: u64,
+
+    /// Stop with an error when a transaction changes more than this many rows, e.g.
+    /// to catch a runaway batch job before it is replicated
+    #[arg(long)]
+    max_txn_rows: Option<u64>,
+
+    /// Instead of stopping, log a warning for transactions changing more than
+    /// --max-txn-rows rows and replicate them as usual
+    #[arg(long, requires = "max_txn_rows")]
+    warn_large_txn: bool,
+
+    /// Kinds of changes to replicate, e.g. --operations insert to only append rows
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Operation::Insert, Operation::Update, Operation::Delete])]
+    operations: Vec<Operation>,
+
+    /// Don't replicate the changes which this replication origin applied to the
+    /// source, e.g. those another replicator wrote into it, to keep changes from
+    /// looping between databases replicating into each other. Can be given more
+    /// than once
+    #[arg(long)]
+    skip_origin: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Copy the published tables and then stream their changes
+    Replicate,
+    /// Copy the published tables and exit. No replication slot is used
+    Backfill,
+    /// Only stream changes, starting from the replication slot's position. The
+    /// destination must already contain a consistent copy of the tables
+    StreamOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnUnknownMessage {
+    /// Log the message and carry on with the next one
+    Skip,
+    /// Stop the replication with an error
+    Fail,
+}
+
+impl From<OnUnknownMessage> for UnknownMessagePolicy {
+    fn from(on_unknown_message: OnUnknownMessage) -> Self {
+        match on_unknown_message {
+            OnUnknownMessage::Skip => UnknownMessagePolicy::Skip,
+            OnUnknownMessage::Fail => UnknownMessagePolicy::Fail,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Feedback {
+    /// Confirm the last lsn received from Postgres
+    Received,
+    /// Confirm the lsn of the last transaction written to BigQuery
+    Flushed,
+}
+
+impl From<Feedback> for FeedbackPolicy {
+    fn from(feedback_policy: Feedback) -> Self {
+        match feedback_policy {
+            Feedback::Received => FeedbackPolicy::Received,
+            Feedback::Flushed => FeedbackPolicy::Flushed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl From<Operation> for CdcOperation {
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::Insert => CdcOperation::Insert,
+            Operation::Update => CdcOperation::Update,
+            Operation::Delete => CdcOperation::Delete,
+        }
+    }
+}
 
 // APP_SOURCE__POSTGRES__PASSWORD and APP_SINK__BIGQUERY__PROJECT_ID environment variables must be set
 // before running because these are sensitive values which can't be configured in the config files
@@ -18,11 +306,35 @@ mod configuration;
 async fn main() -> Result<(), Box<dyn Error>> {
     if let Err(e) = main_impl().await {
         error!("{e}");
+        if e.downcast_ref::<PipelineError>()
+            .is_some_and(PipelineError::is_slot_active)
+        {
+            std::process::exit(SLOT_ACTIVE_EXIT_CODE);
+        }
     }
 
     Ok(())
 }
 
+fn parse_lsn(lsn: &str) -> Result<PgLsn, String> {
+    lsn.parse().map_err(|_| format!("{lsn} is not a valid lsn"))
+}
+
+/// Parses a `schema.table:predicate` copy filter. The schema defaults to public
+fn parse_copy_where(copy_where: &str) -> Result<(TableName, String), String> {
+    let invalid = || format!("{copy_where} is not in the form schema.table:predicate");
+    let (table, predicate) = copy_where.split_once(':').ok_or_else(invalid)?;
+    let (schema, name) = table.split_once('.').unwrap_or(("public", table));
+    if name.is_empty() || predicate.trim().is_empty() {
+        return Err(invalid());
+    }
+    let table_name = TableName {
+        schema: schema.to_string(),
+        name: name.to_string(),
+    };
+    Ok((table_name, predicate.to_string()))
+}
+
 fn init_tracing() {
     tracing_subscriber::registry()
         .with(
@@ -47,39 +359,165 @@ async fn main_impl() -> Result<(), Box<dyn Error>> {
         .install_default()
         .expect("failed to install default crypto provider");
 
-    let settings = get_configuration()?;
+    let args = AppArgs::parse();
+    let mut settings = get_configuration(args.config.as_deref())?;
+    if let Some(ref db_host) = args.db_host {
+        let SourceSettings::Postgres { host, .. } = &mut settings.source;
+        *host = db_host.clone();
+    }
 
     info!("settings: {settings:#?}");
 
+    let retry_policy = RetryPolicy {
+        max_attempts: args.retry_max_attempts,
+        base_delay: Duration::from_secs(args.retry_base_delay_secs),
+        max_delay: Duration::from_secs(args.retry_max_delay_secs),
+        jitter: args.retry_jitter,
+    };
+    let mut circuit_breaker = CircuitBreaker::new(
+        args.breaker_failure_threshold,
+        Duration::from_secs(args.breaker_cooldown_secs),
+    );
+    let mut stop_lsn = args.stop_at_lsn;
+    let mut attempt = 1;
+    loop {
+        let run_start = Instant::now();
+        let e = match replicate(&args, &settings, &mut stop_lsn).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if !is_retryable(&*e) {
+            return Err(e);
+        }
+
+        // a run which got going before it failed doesn't use up the retry budget
+        if run_start.elapsed() >= HEALTHY_RUN {
+            attempt = 1;
+            circuit_breaker.record_success();
+        }
+        if attempt >= retry_policy.max_attempts {
+            return Err(e);
+        }
+        circuit_breaker.record_failure();
+
+        let delay = if circuit_breaker.is_open() {
+            warn!(
+                "too many consecutive failures, pausing for {}s",
+                circuit_breaker.cooldown().as_secs()
+            );
+            circuit_breaker.cooldown()
+        } else {
+            retry_policy.delay(attempt, random_fraction())
+        };
+        warn!(
+            "{e}, retrying in {}s ({attempt}/{})",
+            delay.as_secs(),
+            retry_policy.max_attempts - 1
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Returns a number between 0 and 1 for retry jitter, taken from the clock since
+/// it only needs to differ between replicators
+fn random_fraction() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    f64::from(nanos) / 1e9
+}
+
+/// Connects to Postgres and the sink and runs the pipeline once. `stop_lsn` is
+/// set on the first run with --stop-at-current, so that retries stop at the same lsn.
+async fn replicate(
+    args: &AppArgs,
+    settings: &Settings,
+    stop_lsn: &mut Option<PgLsn>,
+) -> Result<(), Box<dyn Error>> {
     let SourceSettings::Postgres {
-        host,
-        port,
-        name,
-        username,
-        password,
         slot_name,
         publication,
-    } = settings.source;
-
-    let postgres_source = PostgresSource::new(
-        &host,
-        port,
-        &name,
-        &username,
-        password,
-        Some(slot_name),
-        TableNamesFrom::Publication(publication),
+        copy,
+        statement_timeout_secs,
+        ..
+    } = &settings.source;
+
+    let needs_slot = !args.dry_run && args.mode != Mode::Backfill;
+    let slot = needs_slot.then(|| ReplicationSlot {
+        name: slot_name.clone(),
+        create_if_missing: true,
+        temporary: false,
+    });
+
+    let connection_config = settings
+        .source
+        .connection_config(args.db_url.as_deref(), args.application_name.as_deref())?;
+    let options = SourceOptions {
+        statement_timeout: statement_timeout_secs.map(Duration::from_secs),
+    };
+    let mut postgres_source = PostgresSource::new_with_options(
+        connection_config,
+        slot,
+        TableNamesFrom::Publication(publication.clone()),
+        options,
     )
     .await?;
+    postgres_source.set_unknown_message_policy(args.on_unknown_message.into());
+
+    let all_supported = check_table_schemas(&postgres_source);
+    if args.dry_run {
+        if !all_supported {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if !all_supported {
+        return Err("published tables have columns of unsupported types".into());
+    }
+
+    for (table_name, predicate) in &args.copy_where {
+        let is_published = postgres_source
+            .get_table_schemas()
+            .values()
+            .any(|schema| schema.table_name == *table_name);
+        if !is_published {
+            return Err(format!("--copy-where table {table_name} is not published").into());
+        }
+        postgres_source.set_copy_filter(table_name.clone(), predicate.clone());
+    }
+
+    let mut copy_connection = copy.as_ref().map(|copy| CopyConnection {
+        host: copy.host.clone(),
+        port: copy.port,
+        username: copy.username.clone(),
+        password: copy.password.clone(),
+        ..Default::default()
+    });
+    if let Some(ref snapshot_host) = args.snapshot_host {
+        let copy_connection = copy_connection.get_or_insert_with(CopyConnection::default);
+        copy_connection.host = Some(snapshot_host.clone());
+        copy_connection.port = args.snapshot_port.or(copy_connection.port);
+    }
+    if let Some(copy_connection) = copy_connection {
+        if args.mode != Mode::StreamOnly {
+            postgres_source.set_copy_connection(copy_connection).await?;
+        }
+    }
 
     let SinkSettings::BigQuery {
         project_id,
         dataset_id,
         service_account_key,
-    } = settings.sink;
+    } = &settings.sink;
 
-    let bigquery_sink =
-        BigQueryBatchSink::new_with_key(project_id, dataset_id, &service_account_key).await?;
+    let bigquery_sink = BigQueryBatchSink::new_with_key(
+        project_id.clone(),
+        dataset_id.clone(),
+        service_account_key,
+    )
+    .await?;
 
     let BatchSettings {
         max_size,
@@ -87,14 +525,216 @@ async fn main_impl() -> Result<(), Box<dyn Error>> {
     } = settings.batch;
 
     let batch_config = BatchConfig::new(max_size, Duration::from_secs(max_fill_secs));
-    let mut pipeline = BatchDataPipeline::new(
-        postgres_source,
-        bigquery_sink,
-        PipelineAction::Both,
-        batch_config,
-    );
+    let action = match args.mode {
+        Mode::Replicate => PipelineAction::Both,
+        Mode::Backfill => PipelineAction::TableCopiesOnly,
+        Mode::StreamOnly => {
+            warn!("skipping table copies, the destination is assumed to already have a consistent copy of the tables");
+            PipelineAction::CdcOnly
+        }
+    };
+    if args.stop_at_current && stop_lsn.is_none() {
+        let current_wal_lsn = postgres_source.current_wal_lsn().await?;
+        info!("stopping once changes up to lsn {current_wal_lsn} have been replicated");
+        *stop_lsn = Some(current_wal_lsn);
+    }
+    let stop_lsn = *stop_lsn;
+    let start_lsn = match args.since_timestamp {
+        Some(ref since) if args.mode != Mode::Backfill => {
+            let start_lsn = match postgres_source.commit_lsn_since(since).await? {
+                Some(commit_lsn) => commit_lsn,
+                // every change the slot retains is older, skip all of them
+                None => postgres_source.current_wal_lsn().await?,
+            };
+            info!("streaming changes committed since {since}, starting at lsn {start_lsn}");
+            Some(start_lsn)
+        }
+        _ => None,
+    };
 
-    pipeline.start().await?;
+    match args.status_file.clone() {
+        Some(status_file) => {
+            let sink = StatusFileSink::new(bigquery_sink, status_file);
+            run_pipeline(
+                postgres_source,
+                sink,
+                action,
+                batch_config,
+                start_lsn,
+                stop_lsn,
+                args,
+            )
+            .await
+        }
+        None => {
+            run_pipeline(
+                postgres_source,
+                bigquery_sink,
+                action,
+                batch_config,
+                start_lsn,
+                stop_lsn,
+                args,
+            )
+            .await
+        }
+    }
+}
 
-    Ok(())
+/// Returns true if the tables have to be copied again because the replication slot
+/// can't stream the changes after the destination's `resume_lsn`: it no longer has
+/// their WAL, or it was confirmed beyond `resume_lsn` and would skip them. Without --resnapshot-on-gap that is an error. A server
+/// whose WAL hasn't reached `resume_lsn` yet, e.g. a replica which was promoted while
+/// lagging behind, is given --resume-lsn-max-wait-secs to catch up.
+async fn has_resume_gap(
+    postgres_source: &PostgresSource,
+    resume_lsn: PgLsn,
+    args: &AppArgs,
+) -> Result<bool, Box<dyn Error>> {
+    // a destination which hasn't written any changes yet starts at the slot's position
+    if resume_lsn == PgLsn::from(0) {
+        return Ok(false);
+    }
+    let wait_until = Instant::now() + Duration::from_secs(args.resume_lsn_max_wait_secs);
+    loop {
+        // the stream starts after `resume_lsn`, which may be the server's wal end
+        // saved on a heartbeat, so it's enough for the WAL to have reached it
+        let gap = match postgres_source.validate_start_lsn(resume_lsn).await {
+            Ok(()) => return Ok(false),
+            Err(PostgresSourceError::StartLsnBehindRestartLsn {
+                start_lsn,
+                restart_lsn,
+            }) => format!("the destination resumes at lsn {start_lsn} but the replication slot's WAL starts at lsn {restart_lsn}"),
+            Err(PostgresSourceError::StartLsnBehindConfirmedLsn {
+                start_lsn,
+                confirmed_flush_lsn,
+            }) => format!("the destination resumes at lsn {start_lsn} but the replication slot was confirmed up to lsn {confirmed_flush_lsn}, after which Postgres would resume"),
+            Err(PostgresSourceError::StartLsnAheadOfWal {
+                start_lsn,
+                flush_lsn,
+            }) if Instant::now() < wait_until => {
+                warn!("the destination resumes at lsn {start_lsn} but the server's WAL only reaches lsn {flush_lsn}, waiting for it to catch up");
+                tokio::time::sleep(RESUME_LSN_POLL_INTERVAL).await;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        return if args.resnapshot_on_gap && args.mode == Mode::Replicate {
+            warn!("gap detected, {gap}, copying all tables again");
+            Ok(true)
+        } else {
+            Err(format!("gap detected, {gap}: a full re-snapshot is required, e.g. with --resnapshot-on-gap").into())
+        };
+    }
+}
+
+async fn run_pipeline<Snk: BatchSink + Send>(
+    postgres_source: PostgresSource,
+    mut sink: Snk,
+    action: PipelineAction,
+    batch_config: BatchConfig,
+    start_lsn: Option<PgLsn>,
+    stop_lsn: Option<PgLsn>,
+    args: &AppArgs,
+) -> Result<(), Box<dyn Error>> {
+    let copy_all_tables = if args.mode != Mode::Backfill && start_lsn.is_none() {
+        let resume_lsn = sink.get_resumption_state().await?.last_lsn;
+        has_resume_gap(&postgres_source, resume_lsn, args).await?
+    } else {
+        false
+    };
+
+    let mut pipeline = BatchDataPipeline::new(postgres_source, sink, action, batch_config);
+
+    pipeline.set_copy_all_tables(copy_all_tables);
+
+    if let Some(start_lsn) = start_lsn {
+        pipeline.set_start_lsn(start_lsn);
+    }
+
+    if let Some(stop_lsn) = stop_lsn {
+        pipeline.set_stop_lsn(stop_lsn);
+    }
+
+    if let Some(max_runtime_secs) = args.max_runtime_secs {
+        pipeline.set_max_runtime(Duration::from_secs(max_runtime_secs));
+    }
+
+    if let Some(idle_timeout_secs) = args.idle_timeout_secs {
+        pipeline.set_idle_timeout(Duration::from_secs(idle_timeout_secs));
+    }
+
+    if let Some(feedback_interval_secs) = args.feedback_interval_secs {
+        pipeline.set_feedback_interval(Duration::from_secs(feedback_interval_secs));
+    }
+
+    if let Some(heartbeat_interval_secs) = args.heartbeat_interval_secs {
+        pipeline.set_heartbeat_interval(Duration::from_secs(heartbeat_interval_secs));
+    }
+
+    if let Some(publication_check_interval_secs) = args.publication_check_interval_secs {
+        pipeline
+            .set_publication_check_interval(Duration::from_secs(publication_check_interval_secs));
+    }
+
+    pipeline.set_always_reply_to_keepalives(args.always_reply_keepalive);
+
+    pipeline.set_feedback_policy(args.feedback_policy.into());
+
+    if let Some(max_txn_rows) = args.max_txn_rows {
+        let policy = if args.warn_large_txn {
+            LargeTransactionPolicy::Warn
+        } else {
+            LargeTransactionPolicy::Fail
+        };
+        pipeline.set_max_transaction_rows(max_txn_rows, policy);
+    }
+
+    pipeline.set_operations(args.operations.iter().copied().map(Into::into).collect());
+
+    pipeline.set_skipped_origins(args.skip_origin.iter().cloned().collect());
+
+    let mut retries = 0;
+    let mut retry_delay = Duration::from_secs(args.slot_active_retry_delay_secs);
+    loop {
+        match pipeline.start().await {
+            Err(e) if e.is_slot_active() && retries < args.slot_active_retries => {
+                retries += 1;
+                warn!(
+                    "{e}, retrying in {}s ({retries}/{})",
+                    retry_delay.as_secs(),
+                    args.slot_active_retries
+                );
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
+            }
+            result => {
+                result?;
+                if pipeline.stopped_idle() {
+                    info!("no changes to replicate, exiting");
+                    std::process::exit(IDLE_EXIT_CODE);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Logs the published tables and all of their columns with unsupported types.
+/// Returns false if there was at least one such column
+fn check_table_schemas(postgres_source: &PostgresSource) -> bool {
+    let table_schemas = postgres_source.get_table_schemas().values();
+    for table_schema in table_schemas.clone() {
+        info!("table {}", table_schema.table_name);
+    }
+
+    match ReplicationClient::check_supported_types(table_schemas) {
+        Ok(()) => true,
+        Err(unsupported_columns) => {
+            for unsupported_column in unsupported_columns {
+                error!("{unsupported_column}");
+            }
+            false
+        }
+    }
 }
@@ -0,0 +1,260 @@
+use std::{error::Error, time::Duration};
+
+use pg_replicate::pipeline::{sources::postgres::PostgresSourceError, PipelineError};
+use tokio_postgres::error::SqlState;
+
+/// How often and how long to wait before running the replication again after it
+/// failed with an error from Postgres
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of runs, including the first one, before giving up
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Upper bound of the doubled delay
+    pub max_delay: Duration,
+    /// Fraction by which a delay is randomly lengthened or shortened, so that
+    /// replicators failing together don't retry in lockstep
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retry number `retry`, starting at 1. `random` is a
+    /// number between 0 and 1 which picks the delay within the jitter.
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter + 2.0 * jitter * random.clamp(0.0, 1.0))
+    }
+}
+
+/// Pauses retries after too many consecutive failures instead of retrying in a
+/// hot loop, e.g. while Postgres is down for longer than the retry delays
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold,
+            cooldown,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    /// Returns true once `failure_threshold` failures were recorded in a row. It
+    /// stays open until a success is recorded, so each further retry waits for
+    /// the cooldown.
+    pub fn is_open(&self) -> bool {
+        self.failure_threshold > 0 && self.consecutive_failures >= self.failure_threshold
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+}
+
+/// Returns true if `e` is an error which running the replication again with new
+/// connections may resolve: a lost connection to Postgres, or an error from the
+/// server which is transient, e.g. because it is shutting down. Errors in the data,
+/// e.g. a value which can't be converted, or in the configuration, e.g. a missing
+/// slot, would fail the same way again. An active slot has a retry of its own. A
+/// read replica which didn't catch up in time, or a lock which made a query time
+/// out, may be gone by the next run.
+pub fn is_retryable(e: &(dyn Error + 'static)) -> bool {
+    if e.downcast_ref::<PipelineError>()
+        .is_some_and(PipelineError::is_slot_active)
+    {
+        return false;
+    }
+
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(
+            PostgresSourceError::ReplicaReplayTimeout { .. }
+            | PostgresSourceError::StatementTimeout { .. },
+        ) = e.downcast_ref::<PostgresSourceError>()
+        {
+            return true;
+        }
+        if let Some(e) = e.downcast_ref::<tokio_postgres::Error>() {
+            // errors without a SQLSTATE didn't come from the server, e.g. a
+            // connection which was closed or timed out
+            return e.is_closed() || e.code().is_none_or(is_transient_sqlstate);
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Returns true for the SQLSTATEs of connection failures and of errors which the
+/// server raises while it can't serve a connection for the time being
+fn is_transient_sqlstate(code: &SqlState) -> bool {
+    // class 08 is connection exceptions
+    code.code().starts_with("08")
+        || [
+            SqlState::ADMIN_SHUTDOWN,
+            SqlState::CRASH_SHUTDOWN,
+            SqlState::CANNOT_CONNECT_NOW,
+            SqlState::TOO_MANY_CONNECTIONS,
+            SqlState::T_R_SERIALIZATION_FAILURE,
+            SqlState::T_R_DEADLOCK_DETECTED,
+        ]
+        .contains(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pg_replicate::{
+        clients::postgres::ReplicationClientError,
+        conversions::cdc_event::CdcEventConversionError,
+        pipeline::{
+            sources::{
+                postgres::{CdcStreamError, PostgresSourceError},
+                SourceError,
+            },
+            PipelineError,
+        },
+    };
+    use tokio_postgres::error::SqlState;
+
+    use crate::retry::{is_retryable, is_transient_sqlstate, CircuitBreaker, RetryPolicy};
+
+    #[test]
+    fn circuit_breaker_opens_after_consecutive_failures() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        breaker.record_success();
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn retry_delays_double_up_to_the_max_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+        };
+
+        let delays: Vec<_> = (1..=4).map(|retry| policy.delay(retry, 0.5)).collect();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 5].map(Duration::from_secs),
+            "a random value of 0.5 is in the middle of the jitter"
+        );
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(500));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_millis(1500));
+    }
+
+    /// An error without a SQLSTATE, like the one of a lost connection
+    fn connection_error() -> tokio_postgres::Error {
+        tokio_postgres::Error::__private_api_timeout()
+    }
+
+    #[test]
+    fn connection_errors_are_retried() {
+        let cdc_stream_error = PipelineError::SourceError(SourceError::CdcStream(
+            CdcStreamError::TokioPostgresError(connection_error()),
+        ));
+        let connect_error = PostgresSourceError::ReplicationClient(
+            ReplicationClientError::TokioPostgresError(connection_error()),
+        );
+
+        assert!(is_retryable(&cdc_stream_error));
+        assert!(is_retryable(&connect_error));
+    }
+
+    #[test]
+    fn only_transient_sqlstates_are_retried() {
+        assert!(is_transient_sqlstate(&SqlState::CONNECTION_FAILURE));
+        assert!(is_transient_sqlstate(&SqlState::ADMIN_SHUTDOWN));
+        assert!(is_transient_sqlstate(&SqlState::T_R_SERIALIZATION_FAILURE));
+        assert!(!is_transient_sqlstate(&SqlState::UNDEFINED_TABLE));
+        assert!(!is_transient_sqlstate(&SqlState::INSUFFICIENT_PRIVILEGE));
+    }
+
+    #[test]
+    fn conversion_errors_are_not_retried() {
+        let error = PipelineError::SourceError(SourceError::CdcStream(
+            CdcStreamError::CdcEventConversion(CdcEventConversionError::MissingSchema(1)),
+        ));
+
+        assert!(!is_retryable(&error));
+    }
+
+    #[test]
+    fn configuration_errors_are_not_retried() {
+        let errors = [
+            PostgresSourceError::MissingPublication,
+            PostgresSourceError::MissingSlot("slot".to_string()),
+            PostgresSourceError::StartLsnBehindRestartLsn {
+                start_lsn: 1.into(),
+                restart_lsn: 2.into(),
+            },
+            PostgresSourceError::StartLsnBehindConfirmedLsn {
+                start_lsn: 2.into(),
+                confirmed_flush_lsn: 3.into(),
+            },
+        ];
+
+        for error in errors {
+            assert!(!is_retryable(&error), "{error}");
+        }
+    }
+
+    #[test]
+    fn a_replica_which_didnt_catch_up_is_retried() {
+        let error = PostgresSourceError::ReplicaReplayTimeout {
+            slot_lsn: 2.into(),
+            replayed_lsn: 1.into(),
+            max_wait: Duration::from_secs(300),
+        };
+
+        assert!(is_retryable(&error));
+    }
+
+    #[test]
+    fn a_statement_timeout_is_retried() {
+        let error = PipelineError::SourceError(SourceError::Postgres(
+            PostgresSourceError::StatementTimeout {
+                operation: "starting the copy of table public.items".to_string(),
+                timeout: Duration::from_secs(30),
+            },
+        ));
+
+        assert!(is_retryable(&error));
+    }
+
+    #[test]
+    fn an_active_slot_is_not_retried() {
+        let error = PipelineError::SourceError(SourceError::Postgres(
+            PostgresSourceError::ReplicationClient(ReplicationClientError::SlotActive(
+                "slot".to_string(),
+            )),
+        ));
+
+        assert!(!is_retryable(&error));
+    }
+}
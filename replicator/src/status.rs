@@ -0,0 +1,306 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow},
+    pipeline::{
+        sinks::{BatchSink, SinkError},
+        PipelineResumptionState,
+    },
+    table::{TableId, TableSchema},
+};
+use serde::Serialize;
+use tokio_postgres::types::PgLsn;
+use tracing::warn;
+
+/// Progress of the replicator as written to the status file
+#[derive(Debug, Default, Serialize)]
+struct Status {
+    /// Lsn of the last transaction written to the sink
+    last_lsn: String,
+    /// Number of batches of cdc events written to the sink by this process
+    batches_written: u64,
+    /// When the last batch of cdc events was written, in seconds since the unix epoch
+    last_flush_unix_secs: Option<u64>,
+    /// Whether each published table, by name, has been copied
+    tables_copied: BTreeMap<String, bool>,
+    /// Progress of the tables, by name, which this process has copied or is copying
+    table_copies: BTreeMap<String, TableCopyStatus>,
+}
+
+/// Progress of a table copy, e.g. to plan the capacity for copying a similar table
+#[derive(Debug, Default, Serialize)]
+struct TableCopyStatus {
+    /// Number of rows written to the sink
+    rows_copied: u64,
+    /// Number of batches of rows written to the sink
+    batches_written: u64,
+    /// When the first batch of rows was written, in seconds since the unix epoch
+    started_unix_secs: Option<u64>,
+    /// When the copy finished, in seconds since the unix epoch
+    finished_unix_secs: Option<u64>,
+}
+
+/// Returns the current time in seconds since the unix epoch
+fn unix_secs_now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// A sink which writes the replicator's progress to a json file for external
+/// monitors after passing each call on to `inner`. The file is only observational,
+/// failing to write it is logged and doesn't stop replication.
+pub struct StatusFileSink<S: BatchSink> {
+    inner: S,
+    path: PathBuf,
+    status: Status,
+    table_names: HashMap<TableId, String>,
+    copied_tables: HashSet<TableId>,
+}
+
+impl<S: BatchSink> StatusFileSink<S> {
+    pub fn new(inner: S, path: PathBuf) -> StatusFileSink<S> {
+        StatusFileSink {
+            inner,
+            path,
+            status: Status::default(),
+            table_names: HashMap::new(),
+            copied_tables: HashSet::new(),
+        }
+    }
+
+    fn set_table_copied(&mut self, table_id: TableId) {
+        if let Some(name) = self.table_names.get(&table_id) {
+            self.status.tables_copied.insert(name.clone(), true);
+            let table_copy = self.status.table_copies.entry(name.clone()).or_default();
+            let now = unix_secs_now();
+            table_copy.started_unix_secs = table_copy.started_unix_secs.or(now);
+            table_copy.finished_unix_secs = now;
+        }
+    }
+
+    fn add_copied_rows(&mut self, table_id: TableId, rows: usize) {
+        if let Some(name) = self.table_names.get(&table_id) {
+            let table_copy = self.status.table_copies.entry(name.clone()).or_default();
+            table_copy.rows_copied += rows as u64;
+            table_copy.batches_written += 1;
+            table_copy.started_unix_secs = table_copy.started_unix_secs.or_else(unix_secs_now);
+        }
+    }
+
+    fn write_status(&self) {
+        if let Err(e) = write_atomically(&self.path, &self.status) {
+            warn!(
+                "failed to write status file {}: {e}",
+                self.path.to_string_lossy()
+            );
+        }
+    }
+}
+
+/// Writes `status` to a temporary file next to `path` and renames it over
+/// `path`, so that readers never see a partially written file
+fn write_atomically(path: &Path, status: &Status) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, serde_json::to_vec_pretty(status)?)?;
+    fs::rename(&temp_path, path)
+}
+
+#[async_trait]
+impl<S: BatchSink + Send> BatchSink for StatusFileSink<S> {
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, SinkError> {
+        let resumption_state = self.inner.get_resumption_state().await?;
+        self.status.last_lsn = resumption_state.last_lsn.to_string();
+        self.copied_tables = resumption_state.copied_tables.clone();
+        Ok(resumption_state)
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), SinkError> {
+        for table_schema in table_schemas.values() {
+            let name = table_schema.table_name.to_string();
+            let copied = self.copied_tables.contains(&table_schema.table_id);
+            self.status.tables_copied.insert(name.clone(), copied);
+            self.table_names.insert(table_schema.table_id, name);
+        }
+        self.inner.write_table_schemas(table_schemas).await?;
+        self.write_status();
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), SinkError> {
+        let row_count = rows.len();
+        self.inner.write_table_rows(rows, table_id).await?;
+        self.add_copied_rows(table_id, row_count);
+        self.write_status();
+        Ok(())
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, SinkError> {
+        let last_lsn = self.inner.write_cdc_events(events).await?;
+        self.status.last_lsn = last_lsn.to_string();
+        self.status.batches_written += 1;
+        self.status.last_flush_unix_secs = unix_secs_now();
+        self.write_status();
+        Ok(last_lsn)
+    }
+
+    async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+        self.inner.write_last_lsn(lsn).await?;
+        self.status.last_lsn = lsn.to_string();
+        self.write_status();
+        Ok(())
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), SinkError> {
+        self.inner.table_copied(table_id).await?;
+        self.set_table_copied(table_id);
+        self.write_status();
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), SinkError> {
+        self.inner.truncate_table(table_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use async_trait::async_trait;
+    use pg_replicate::{
+        conversions::{
+            cdc_event::CdcEvent,
+            table_row::{Cell, TableRow},
+        },
+        pipeline::{
+            sinks::{BatchSink, SinkError},
+            PipelineResumptionState,
+        },
+        table::{ReplicaIdentity, TableId, TableName, TableSchema},
+    };
+    use tokio_postgres::types::PgLsn;
+
+    use crate::status::StatusFileSink;
+
+    struct LsnSink {
+        lsn: PgLsn,
+    }
+
+    #[async_trait]
+    impl BatchSink for LsnSink {
+        async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, SinkError> {
+            Ok(PipelineResumptionState {
+                copied_tables: HashSet::new(),
+                last_lsn: self.lsn,
+            })
+        }
+
+        async fn write_table_schemas(
+            &mut self,
+            _: HashMap<TableId, TableSchema>,
+        ) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn write_table_rows(
+            &mut self,
+            _: Vec<TableRow>,
+            _: TableId,
+        ) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn write_cdc_events(&mut self, _: Vec<CdcEvent>) -> Result<PgLsn, SinkError> {
+            Ok(self.lsn)
+        }
+
+        async fn write_last_lsn(&mut self, lsn: PgLsn) -> Result<(), SinkError> {
+            self.lsn = lsn;
+            Ok(())
+        }
+
+        async fn table_copied(&mut self, _: TableId) -> Result<(), SinkError> {
+            Ok(())
+        }
+
+        async fn truncate_table(&mut self, _: TableId) -> Result<(), SinkError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn status_file_is_updated_after_a_commit() {
+        let path = std::env::temp_dir().join(format!(
+            "replicator_status_test_{}.json",
+            std::process::id()
+        ));
+        let lsn: PgLsn = "16/B374D848".parse().unwrap();
+        let mut sink = StatusFileSink::new(LsnSink { lsn }, path.clone());
+
+        sink.write_cdc_events(vec![]).await.unwrap();
+
+        let status: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(status["last_lsn"], "16/B374D848");
+        assert_eq!(status["batches_written"], 1);
+        assert!(status["last_flush_unix_secs"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn status_file_has_the_row_count_of_a_copied_table() {
+        let path = std::env::temp_dir().join(format!(
+            "replicator_copy_status_test_{}.json",
+            std::process::id()
+        ));
+        let lsn: PgLsn = "16/B374D848".parse().unwrap();
+        let mut sink = StatusFileSink::new(LsnSink { lsn }, path.clone());
+        let table_schema = TableSchema {
+            table_name: TableName {
+                schema: "public".to_string(),
+                name: "items".to_string(),
+            },
+            table_id: 1,
+            column_schemas: vec![],
+            primary_key: vec![],
+            replica_identity: ReplicaIdentity::Default,
+        };
+        let row = || TableRow {
+            values: vec![Cell::I32(1)],
+        };
+
+        sink.write_table_schemas(HashMap::from([(1, table_schema)]))
+            .await
+            .unwrap();
+        sink.write_table_rows(vec![row(), row()], 1).await.unwrap();
+        sink.write_table_rows(vec![row()], 1).await.unwrap();
+        sink.table_copied(1).await.unwrap();
+
+        let status: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let table_copy = &status["table_copies"]["public.items"];
+        assert_eq!(status["tables_copied"]["public.items"], true);
+        assert_eq!(table_copy["rows_copied"], 3);
+        assert_eq!(table_copy["batches_written"], 2);
+        assert!(table_copy["started_unix_secs"].is_u64());
+        assert!(table_copy["finished_unix_secs"].is_u64());
+    }
+}